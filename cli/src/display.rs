@@ -0,0 +1,174 @@
+use anchor_lang::AccountDeserialize;
+use anchor_spl::token_interface::Mint;
+use anyhow::{anyhow, Result};
+use lending::{
+    math,
+    pda::{bank_address, treasury_address, user_address},
+    state::{Bank, User},
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+fn load<T: AccountDeserialize>(rpc: &RpcClient, address: &Pubkey) -> Result<T> {
+    let account = rpc.get_account(address)?;
+    T::try_deserialize(&mut account.data.as_slice())
+        .map_err(|err| anyhow!("decoding {address}: {err}"))
+}
+
+/// Fetches a bank with interest accrued to the cluster's current time, plus its mint decimals.
+fn load_bank(rpc: &RpcClient, mint: &Pubkey) -> Result<(Bank, u8)> {
+    let mut bank: Bank = load(rpc, &bank_address(mint))?;
+    let now = rpc.get_block_time(rpc.get_slot()?)?;
+    bank.accrue_interest(now).map_err(|err| anyhow!("{err}"))?;
+    let decimals = load::<Mint>(rpc, mint)?.decimals;
+    Ok((bank, decimals))
+}
+
+fn ui_amount(amount: u64, decimals: u8) -> f64 {
+    amount as f64 / 10f64.powi(decimals as i32)
+}
+
+pub fn show_bank(rpc: &RpcClient, mint: &Pubkey) -> Result<()> {
+    let (bank, decimals) = load_bank(rpc, mint)?;
+    let vault = rpc.get_token_account_balance(&treasury_address(mint))?;
+    let utilization = if bank.total_deposits == 0 {
+        0.0
+    } else {
+        (bank.total_borrowed + bank.total_stable_borrowed) as f64 / bank.total_deposits as f64
+            * 100.0
+    };
+
+    println!("bank                   {}", bank_address(mint));
+    println!("mint                   {}", bank.mint_address);
+    println!("authority              {}", bank.authority);
+    println!("paused                 {}", bank.paused);
+    println!("unsafe mint allowed    {}", bank.unsafe_mint_allowed);
+    println!("layout version         {}", bank.version);
+    println!("treasury balance       {}", vault.ui_amount_string);
+    println!(
+        "total deposits         {}",
+        ui_amount(bank.total_deposits, decimals)
+    );
+    println!(
+        "total borrowed         {}",
+        ui_amount(bank.total_borrowed, decimals)
+    );
+    println!(
+        "total stable borrowed  {}",
+        ui_amount(bank.total_stable_borrowed, decimals)
+    );
+    println!(
+        "term lent / borrowed   {} / {}",
+        ui_amount(bank.total_term_lent, decimals),
+        ui_amount(bank.total_term_borrowed, decimals)
+    );
+    println!("utilization            {utilization:.2}%");
+    println!(
+        "protocol fees          {}",
+        ui_amount(bank.protocol_fees, decimals)
+    );
+    println!("interest rate          {:.2}%", bank.instrest_rate * 100.0);
+    if bank.stable_borrow_enabled {
+        println!(
+            "stable rate            {:.2}% (average outstanding {:.2}%)",
+            bank.stable_rate() * 100.0,
+            bank.average_stable_rate * 100.0
+        );
+        println!(
+            "stable rebalance at    {}% utilization",
+            bank.stable_rebalance_utilization
+        );
+    }
+    println!("reserve factor         {}%", bank.reserve_factor);
+    println!(
+        "borrow fee             {}bps ({}% to hosts, {}% of the rest to referrers)",
+        bank.borrow_fee_bps, bank.host_fee_percent, bank.referral_fee_percent
+    );
+    println!("max ltv                {}%", bank.max_ltv);
+    println!("liquidation threshold  {}%", bank.liquidation_threshold);
+    println!("liquidation bonus      {}%", bank.liquidation_bonus);
+    println!("close factor           {}%", bank.liquidation_close_factor);
+    println!("guardian               {}", bank.guardian);
+    println!("config delay           {}s", bank.config_delay);
+    if bank.outflow_window > 0 {
+        println!("outflow window         {}s", bank.outflow_window);
+        println!(
+            "outflow limit          {} / {}%",
+            ui_amount(bank.outflow_limit, decimals),
+            bank.outflow_limit_percent
+        );
+        println!(
+            "window outflow         {}",
+            ui_amount(bank.window_outflow, decimals)
+        );
+    }
+    Ok(())
+}
+
+pub fn show_user(rpc: &RpcClient, owner: &Pubkey, index: u8) -> Result<()> {
+    let address = user_address(owner, index);
+    let user: User = load(rpc, &address)?;
+    println!("position               {}", address);
+    println!("owner                  {}", user.owner);
+    println!("sub-account            {}", user.index);
+    println!("layout version         {}", user.version);
+    if user.referrer != Pubkey::default() {
+        println!("referrer               {}", user.referrer);
+    }
+
+    let positions = [
+        (
+            "usdc",
+            user.usdc_address,
+            user.deposited_usdc_shares,
+            user.borrowed_usdc_shares,
+        ),
+        (
+            "sol",
+            user.sol_address,
+            user.deposited_sol_shares,
+            user.borrowed_sol_shares,
+        ),
+    ];
+    for (label, mint, deposit_shares, borrow_shares) in positions {
+        let (bank, decimals) = load_bank(rpc, &mint)?;
+        let deposited = math::shares_to_amount(
+            deposit_shares,
+            bank.total_deposits,
+            bank.total_deposit_shares,
+            false,
+        )
+        .map_err(|err| anyhow!("{err}"))?;
+        let borrowed = math::shares_to_amount(
+            borrow_shares,
+            bank.total_borrowed,
+            bank.total_borrowed_shares,
+            true,
+        )
+        .map_err(|err| anyhow!("{err}"))?;
+        println!(
+            "{label:<4} deposited          {}",
+            ui_amount(deposited, decimals)
+        );
+        println!(
+            "{label:<4} borrowed           {}",
+            ui_amount(borrowed, decimals)
+        );
+        let (stable, rate) = user.stable_debt(&bank).map_err(|err| anyhow!("{err}"))?;
+        if stable > 0 {
+            println!(
+                "{label:<4} stable borrowed    {} at {:.2}%",
+                ui_amount(stable, decimals),
+                rate * 100.0
+            );
+        }
+        let term = user.term_debt(&bank).map_err(|err| anyhow!("{err}"))?;
+        if term > 0 {
+            println!(
+                "{label:<4} term borrowed      {}",
+                ui_amount(term, decimals)
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,491 @@
+//! Admin CLI for creating, inspecting and managing lending banks.
+
+mod display;
+mod tx;
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use lending::{
+    instructions::BankConfigParams,
+    pda::{
+        bank_address, pending_config_address, reward_pool_address, reward_vault_address,
+        roles_address, treasury_address,
+    },
+    state::{Bank, PendingConfig, RewardPool, RewardSide, Roles},
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, instruction::Instruction, pubkey::Pubkey,
+    signature::read_keypair_file, signer::Signer, system_program,
+};
+
+use crate::tx::Output;
+
+#[derive(Parser)]
+#[command(name = "lending-cli", version)]
+struct Cli {
+    #[arg(
+        long,
+        env = "RPC_URL",
+        default_value = "http://127.0.0.1:8899",
+        global = true
+    )]
+    rpc_url: String,
+
+    /// Keypair that signs and pays for transactions.
+    #[arg(
+        long,
+        env = "LENDING_KEYPAIR",
+        default_value = "~/.config/solana/id.json",
+        global = true
+    )]
+    keypair: String,
+
+    /// Print an unsigned base64 transaction instead of sending it, e.g. for a multisig proposal.
+    #[arg(long, global = true)]
+    unsigned: bool,
+
+    /// Authority and fee payer used with `--unsigned`; defaults to the keypair's pubkey.
+    #[arg(long, global = true)]
+    authority: Option<Pubkey>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a bank and its treasury for a mint.
+    InitBank {
+        #[arg(long)]
+        mint: Pubkey,
+        /// Percent of collateral value at which positions become liquidatable.
+        #[arg(long)]
+        liquidation_threshold: u64,
+        /// Percent of collateral value that can be borrowed.
+        #[arg(long)]
+        max_ltv: u64,
+        /// Create the bank even if the mint has a freeze authority or risky extensions.
+        #[arg(long)]
+        allow_unsafe_mint: bool,
+    },
+    /// Appoint the keys that may act on the market authority's banks; omitted roles are left
+    /// vacant. The first call claims the market's roles account for the signer.
+    SetRoles {
+        #[arg(long, default_value_t = Pubkey::default())]
+        risk_admin: Pubkey,
+        #[arg(long, default_value_t = Pubkey::default())]
+        guardian: Pubkey,
+        #[arg(long, default_value_t = Pubkey::default())]
+        fee_admin: Pubkey,
+        #[arg(long, default_value_t = Pubkey::default())]
+        listing_admin: Pubkey,
+    },
+    /// Print a bank's state in token units.
+    ShowBank {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    /// Print a wallet's position in token units.
+    ShowUser {
+        #[arg(long)]
+        owner: Pubkey,
+        /// Sub-account number.
+        #[arg(long, default_value_t = 0)]
+        index: u8,
+    },
+    /// Change risk and rate parameters; omitted values are left as they are.
+    UpdateConfig {
+        #[arg(long)]
+        mint: Pubkey,
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Queue a parameter change behind the bank's config delay.
+    QueueConfig {
+        #[arg(long)]
+        mint: Pubkey,
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Apply a queued change once its delay has passed; any wallet may do this.
+    ExecuteConfig {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    /// Drop a queued change before it is executed.
+    CancelConfig {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    /// Stop deposits, withdrawals and borrows.
+    Pause {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    /// Resume a paused bank.
+    Unpause {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    TransferAuthority {
+        #[arg(long)]
+        mint: Pubkey,
+        #[arg(long)]
+        new_authority: Pubkey,
+    },
+    /// Send accrued protocol fees to a token account.
+    CollectFees {
+        #[arg(long)]
+        mint: Pubkey,
+        /// Defaults to the authority's associated token account.
+        #[arg(long)]
+        destination: Option<Pubkey>,
+    },
+    /// Delist an empty bank, sweeping its treasury to a token account and closing it. Protocol
+    /// fees must be collected first; unclaimed rewards go to the authority's reward token accounts.
+    CloseBank {
+        #[arg(long)]
+        mint: Pubkey,
+        /// Defaults to the authority's associated token account.
+        #[arg(long)]
+        destination: Option<Pubkey>,
+    },
+}
+
+#[derive(Args)]
+struct ConfigArgs {
+    #[arg(long)]
+    liquidation_threshold: Option<u64>,
+    #[arg(long)]
+    liquidation_bonus: Option<u64>,
+    #[arg(long)]
+    close_factor: Option<u64>,
+    #[arg(long)]
+    max_ltv: Option<u64>,
+    /// Yearly borrow rate, e.g. 0.05 for 5%.
+    #[arg(long)]
+    interest_rate: Option<f64>,
+    /// Percent of interest kept as protocol fees.
+    #[arg(long)]
+    reserve_factor: Option<u64>,
+    /// Account allowed to temporarily raise the outflow limit.
+    #[arg(long)]
+    guardian: Option<Pubkey>,
+    /// Seconds for tracked outflow to drain; 0 disables the limiter.
+    #[arg(long)]
+    outflow_window: Option<i64>,
+    /// Net outflow allowed per window, in base units.
+    #[arg(long)]
+    outflow_limit: Option<u64>,
+    /// Net outflow allowed per window, as a percent of deposits.
+    #[arg(long)]
+    outflow_limit_percent: Option<u64>,
+    /// Seconds a change must stay queued; changes that reduce risk skip the queue.
+    #[arg(long)]
+    config_delay: Option<i64>,
+    /// Origination fee added to each borrow, in basis points.
+    #[arg(long)]
+    borrow_fee_bps: Option<u64>,
+    /// Percent of the origination fee paid to the borrow's host account.
+    #[arg(long)]
+    host_fee_percent: Option<u64>,
+    /// Percent of the protocol's share of the fee credited to the borrower's referrer.
+    #[arg(long)]
+    referral_fee_percent: Option<u64>,
+    /// Whether borrowers may lock in a stable rate.
+    #[arg(long)]
+    stable_borrow_enabled: Option<bool>,
+    /// Yearly premium over the variable rate that stable borrows lock in, e.g. 0.02 for 2%.
+    #[arg(long)]
+    stable_rate_premium: Option<f64>,
+    /// Utilization percent at which anyone may re-price stable debt; 0 disables rebalancing.
+    #[arg(long)]
+    stable_rebalance_utilization: Option<u64>,
+}
+
+impl From<ConfigArgs> for BankConfigParams {
+    fn from(args: ConfigArgs) -> Self {
+        BankConfigParams {
+            liquidation_threshold: args.liquidation_threshold,
+            liquidation_bonus: args.liquidation_bonus,
+            liquidation_close_factor: args.close_factor,
+            max_ltv: args.max_ltv,
+            interest_rate: args.interest_rate,
+            reserve_factor: args.reserve_factor,
+            guardian: args.guardian,
+            outflow_window: args.outflow_window,
+            outflow_limit: args.outflow_limit,
+            outflow_limit_percent: args.outflow_limit_percent,
+            config_delay: args.config_delay,
+            borrow_fee_bps: args.borrow_fee_bps,
+            host_fee_percent: args.host_fee_percent,
+            referral_fee_percent: args.referral_fee_percent,
+            stable_borrow_enabled: args.stable_borrow_enabled,
+            stable_rate_premium: args.stable_rate_premium,
+            stable_rebalance_utilization: args.stable_rebalance_utilization,
+        }
+    }
+}
+
+fn lending_ix(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+fn update_bank_ix(
+    authority: Pubkey,
+    roles: Option<Pubkey>,
+    mint: &Pubkey,
+    data: impl InstructionData,
+) -> Instruction {
+    lending_ix(
+        lending::accounts::UpdateBank {
+            signer: authority,
+            bank: bank_address(mint),
+            roles,
+        },
+        data,
+    )
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let rpc = RpcClient::new_with_commitment(cli.rpc_url.clone(), CommitmentConfig::confirmed());
+
+    let output = match cli.command {
+        Command::ShowBank { mint } => return display::show_bank(&rpc, &mint),
+        Command::ShowUser { owner, index } => return display::show_user(&rpc, &owner, index),
+        _ if cli.unsigned => match cli.authority {
+            Some(authority) => Output::Unsigned(authority),
+            None => Output::Unsigned(load_keypair(&cli.keypair)?.pubkey()),
+        },
+        _ => Output::Signed(load_keypair(&cli.keypair)?),
+    };
+    let authority = output.authority();
+
+    let ix = match cli.command {
+        Command::ShowBank { .. } | Command::ShowUser { .. } => unreachable!(),
+        Command::InitBank {
+            mint,
+            liquidation_threshold,
+            max_ltv,
+            allow_unsafe_mint,
+        } => lending_ix(
+            lending::accounts::InitBank {
+                signer: authority,
+                mint,
+                bank: bank_address(&mint),
+                bank_token_account: treasury_address(&mint),
+                roles: roles_address(),
+                token_program: rpc.get_account(&mint)?.owner,
+                system_program: system_program::id(),
+            },
+            lending::instruction::InitBank {
+                liquidation_threshold,
+                max_ltv,
+                allow_unsafe_mint,
+            },
+        ),
+        Command::SetRoles {
+            risk_admin,
+            guardian,
+            fee_admin,
+            listing_admin,
+        } => lending_ix(
+            lending::accounts::SetRoles {
+                signer: authority,
+                roles: roles_address(),
+                system_program: system_program::id(),
+            },
+            lending::instruction::SetRoles {
+                risk_admin,
+                guardian,
+                fee_admin,
+                listing_admin,
+            },
+        ),
+        Command::UpdateConfig { mint, config } => update_bank_ix(
+            authority,
+            roles_for(&rpc, &mint, &authority)?,
+            &mint,
+            lending::instruction::UpdateBankConfig {
+                params: config.into(),
+            },
+        ),
+        Command::QueueConfig { mint, config } => lending_ix(
+            lending::accounts::QueueBankConfig {
+                signer: authority,
+                bank: bank_address(&mint),
+                roles: roles_for(&rpc, &mint, &authority)?,
+                pending_config: pending_config_address(&bank_address(&mint)),
+                system_program: system_program::id(),
+            },
+            lending::instruction::QueueBankConfig {
+                params: config.into(),
+            },
+        ),
+        Command::ExecuteConfig { mint } => lending_ix(
+            lending::accounts::ExecuteBankConfig {
+                bank: bank_address(&mint),
+                pending_config: pending_config_address(&bank_address(&mint)),
+                payer: load_pending_config(&rpc, &mint)?.payer,
+            },
+            lending::instruction::ExecuteBankConfig {},
+        ),
+        Command::CancelConfig { mint } => lending_ix(
+            lending::accounts::CancelBankConfig {
+                signer: authority,
+                bank: bank_address(&mint),
+                roles: roles_for(&rpc, &mint, &authority)?,
+                pending_config: pending_config_address(&bank_address(&mint)),
+                payer: load_pending_config(&rpc, &mint)?.payer,
+            },
+            lending::instruction::CancelBankConfig {},
+        ),
+        Command::Pause { mint } => update_bank_ix(
+            authority,
+            roles_for(&rpc, &mint, &authority)?,
+            &mint,
+            lending::instruction::SetPaused { paused: true },
+        ),
+        Command::Unpause { mint } => update_bank_ix(
+            authority,
+            roles_for(&rpc, &mint, &authority)?,
+            &mint,
+            lending::instruction::SetPaused { paused: false },
+        ),
+        Command::TransferAuthority {
+            mint,
+            new_authority,
+        } => update_bank_ix(
+            authority,
+            None,
+            &mint,
+            lending::instruction::TransferAuthority { new_authority },
+        ),
+        Command::CollectFees { mint, destination } => {
+            let token_program = rpc.get_account(&mint)?.owner;
+            let roles = roles_for(&rpc, &mint, &authority)?;
+            lending_ix(
+                lending::accounts::CollectFees {
+                    signer: authority,
+                    mint,
+                    bank: bank_address(&mint),
+                    bank_token_account: treasury_address(&mint),
+                    fee_destination: destination.unwrap_or_else(|| {
+                        get_associated_token_address_with_program_id(
+                            &authority,
+                            &mint,
+                            &token_program,
+                        )
+                    }),
+                    roles,
+                    token_program,
+                },
+                lending::instruction::CollectFees {},
+            )
+        }
+        Command::CloseBank { mint, destination } => {
+            let token_program = rpc.get_account(&mint)?.owner;
+            let roles = roles_for(&rpc, &mint, &authority)?;
+            let bank =
+                Bank::try_deserialize(&mut rpc.get_account(&bank_address(&mint))?.data.as_slice())?;
+            // (vault, reward mint, authority's account of it) for each side still rewarding
+            let reward_accounts = |active: bool, side| -> Result<[Option<Pubkey>; 3]> {
+                if !active {
+                    return Ok([None; 3]);
+                }
+                let reward_pool = reward_pool_address(&bank_address(&mint), side);
+                let reward_mint = RewardPool::try_deserialize(
+                    &mut rpc.get_account(&reward_pool)?.data.as_slice(),
+                )?
+                .reward_mint;
+                let destination = get_associated_token_address_with_program_id(
+                    &authority,
+                    &reward_mint,
+                    &rpc.get_account(&reward_mint)?.owner,
+                );
+                Ok([
+                    Some(reward_vault_address(&reward_pool)),
+                    Some(reward_mint),
+                    Some(destination),
+                ])
+            };
+            let [deposit_reward_vault, deposit_reward_mint, deposit_reward_destination] =
+                reward_accounts(bank.deposit_rewards, RewardSide::Deposit)?;
+            let [borrow_reward_vault, borrow_reward_mint, borrow_reward_destination] =
+                reward_accounts(bank.borrow_rewards, RewardSide::Borrow)?;
+            lending_ix(
+                lending::accounts::CloseBank {
+                    signer: authority,
+                    mint,
+                    bank: bank_address(&mint),
+                    bank_token_account: treasury_address(&mint),
+                    destination: destination.unwrap_or_else(|| {
+                        get_associated_token_address_with_program_id(
+                            &authority,
+                            &mint,
+                            &token_program,
+                        )
+                    }),
+                    roles,
+                    token_program,
+                    deposit_reward_vault,
+                    deposit_reward_mint,
+                    deposit_reward_destination,
+                    borrow_reward_vault,
+                    borrow_reward_mint,
+                    borrow_reward_destination,
+                },
+                lending::instruction::CloseBank {},
+            )
+        }
+    };
+
+    output.submit(&rpc, &[ix])
+}
+
+/// The roles account to pass when `signer` is not the bank authority itself. Fails early when
+/// the market's roles do not cover the bank, since the program would reject the signer anyway.
+fn roles_for(rpc: &RpcClient, mint: &Pubkey, signer: &Pubkey) -> Result<Option<Pubkey>> {
+    let bank = Bank::try_deserialize(&mut rpc.get_account(&bank_address(mint))?.data.as_slice())?;
+    if bank.authority == *signer {
+        return Ok(None);
+    }
+    let address = roles_address();
+    let roles = rpc
+        .get_account(&address)
+        .ok()
+        .and_then(|account| Roles::try_deserialize(&mut account.data.as_slice()).ok());
+    if roles.map_or(true, |roles| roles.authority != bank.authority) {
+        return Err(anyhow!(
+            "{signer} is not the authority of this bank ({}), which the market's roles do not cover",
+            bank.authority
+        ));
+    }
+    Ok(Some(address))
+}
+
+fn load_pending_config(rpc: &RpcClient, mint: &Pubkey) -> Result<PendingConfig> {
+    let address = pending_config_address(&bank_address(mint));
+    let account = rpc
+        .get_account(&address)
+        .map_err(|_| anyhow!("no config change is queued for this bank"))?;
+    Ok(PendingConfig::try_deserialize(
+        &mut account.data.as_slice(),
+    )?)
+}
+
+fn load_keypair(path: &str) -> Result<solana_sdk::signature::Keypair> {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => format!("{}/{rest}", std::env::var("HOME")?),
+        None => path.to_string(),
+    };
+    read_keypair_file(&path).map_err(|err| anyhow!("reading keypair {path}: {err}"))
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction, message::Message, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::Transaction,
+};
+
+/// How a built transaction leaves the CLI.
+pub enum Output {
+    /// Sign with the keypair and send it.
+    Signed(Keypair),
+    /// Print it unsigned, with this authority as fee payer, for a multisig to sign.
+    Unsigned(Pubkey),
+}
+
+impl Output {
+    pub fn authority(&self) -> Pubkey {
+        match self {
+            Output::Signed(keypair) => keypair.pubkey(),
+            Output::Unsigned(authority) => *authority,
+        }
+    }
+
+    pub fn submit(&self, rpc: &RpcClient, ixs: &[Instruction]) -> Result<()> {
+        let blockhash = rpc.get_latest_blockhash()?;
+        match self {
+            Output::Signed(keypair) => {
+                let tx = Transaction::new_signed_with_payer(
+                    ixs,
+                    Some(&keypair.pubkey()),
+                    &[keypair],
+                    blockhash,
+                );
+                let signature = rpc.send_and_confirm_transaction(&tx)?;
+                println!("{signature}");
+            }
+            Output::Unsigned(authority) => {
+                let message = Message::new_with_blockhash(ixs, Some(authority), &blockhash);
+                let tx = Transaction::new_unsigned(message);
+                println!("{}", STANDARD.encode(bincode::serialize(&tx)?));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use anchor_lang::{AccountDeserialize, Discriminator};
+use anyhow::{anyhow, ensure, Context, Result};
+use lending::{pda::bank_address, state::Bank};
+use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::pubkey::Pubkey;
+
+/// A bank together with everything needed to value positions in it.
+#[derive(Clone)]
+pub struct Asset {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub token_program: Pubkey,
+    pub bank: Bank,
+    pub price_account: Pubkey,
+    pub price: i64,
+    pub exponent: i32,
+}
+
+impl Asset {
+    /// USD value of one base unit of the asset.
+    pub fn unit_value(&self) -> f64 {
+        lending::math::usd_value(1, self.price, self.exponent, self.decimals)
+    }
+}
+
+/// Every program account of type `T`, selected by its Anchor discriminator.
+pub fn fetch_all<T: AccountDeserialize + Discriminator>(
+    rpc: &RpcClient,
+) -> Result<Vec<(Pubkey, T)>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            0,
+            T::DISCRIMINATOR.to_vec(),
+        ))]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let accounts = rpc.get_program_accounts_with_config(&lending::ID, config)?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(address, account)| {
+            T::try_deserialize(&mut account.data.as_slice())
+                .ok()
+                .map(|decoded| (address, decoded))
+        })
+        .collect())
+}
+
+/// Loads a bank with interest accrued to `now`, its mint and its Pyth price.
+pub fn load_asset(
+    rpc: &RpcClient,
+    mint: &Pubkey,
+    price_account: &Pubkey,
+    feed_id_hex: &str,
+    now: i64,
+) -> Result<Asset> {
+    let mint_account = rpc.get_account(mint).context("loading mint")?;
+    let decoded_mint =
+        anchor_spl::token_interface::Mint::try_deserialize(&mut mint_account.data.as_slice())
+            .map_err(|err| anyhow!("decoding mint {mint}: {err}"))?;
+
+    let bank_account = rpc
+        .get_account(&bank_address(mint))
+        .with_context(|| format!("loading bank for {mint}"))?;
+    let mut bank = Bank::try_deserialize(&mut bank_account.data.as_slice())
+        .map_err(|err| anyhow!("decoding bank for {mint}: {err}"))?;
+    bank.accrue_interest(now)
+        .map_err(|err| anyhow!("accruing interest for {mint}: {err}"))?;
+
+    let price_update_account = rpc.get_account(price_account).context("loading price")?;
+    let price_update = PriceUpdateV2::try_deserialize(&mut price_update_account.data.as_slice())
+        .map_err(|err| anyhow!("decoding price update {price_account}: {err}"))?;
+    let feed_id = get_feed_id_from_hex(feed_id_hex).map_err(|err| anyhow!("{err}"))?;
+    let message = &price_update.price_message;
+    ensure!(
+        message.feed_id == feed_id,
+        "price account {price_account} does not carry feed {feed_id_hex}"
+    );
+    ensure!(
+        message.publish_time + lending::constants::MAX_AGE as i64 >= now,
+        "price account {price_account} is stale"
+    );
+
+    Ok(Asset {
+        mint: *mint,
+        decimals: decoded_mint.decimals,
+        token_program: mint_account.owner,
+        bank,
+        price_account: *price_account,
+        price: message.price,
+        exponent: message.exponent,
+    })
+}
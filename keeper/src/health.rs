@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Result};
+use lending::{math, state::User};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::accounts::Asset;
+
+/// A liquidation `liquidate` would currently accept, priced from the keeper's view.
+#[derive(Debug, Clone)]
+pub struct Opportunity {
+    pub owner: Pubkey,
+    /// Sub-account of `owner` holding the position.
+    pub index: u8,
+    pub collateral_mint: Pubkey,
+    pub borrowed_mint: Pubkey,
+    pub collateral_price_account: Pubkey,
+    pub borrowed_price_account: Pubkey,
+    /// `liquidate` takes a single token program, so both mints must share it.
+    pub token_program: Pubkey,
+    pub health_factor: f64,
+    pub repay_amount: u64,
+    pub seize_amount: u64,
+    pub profit_usd: f64,
+}
+
+/// Mirrors the valuation in `process_liquidate` for one collateral/debt pair, with the
+/// position's stable debt accrued to `now` as the program accrues it before valuing. Only
+/// variable and stable debt is repaid; term loans need their pool and are left alone.
+pub fn evaluate(
+    user: &User,
+    collateral: &Asset,
+    borrowed: &Asset,
+    now: i64,
+) -> Result<Option<Opportunity>> {
+    let (Ok(collateral_is_usdc), Ok(borrowed_is_usdc)) =
+        (user.is_usdc(&collateral.bank), user.is_usdc(&borrowed.bank))
+    else {
+        return Ok(None);
+    };
+    if collateral_is_usdc == borrowed_is_usdc {
+        return Ok(None);
+    }
+    let mut user = user.clone();
+    user.accrue_stable(now).map_err(|err| anyhow!("{err}"))?;
+    let (collateral_shares, _) = user
+        .shares(&collateral.bank)
+        .map_err(|err| anyhow!("{err}"))?;
+    let (_, borrowed_shares) = user
+        .shares(&borrowed.bank)
+        .map_err(|err| anyhow!("{err}"))?;
+    let stable_borrowed = user
+        .stable_debt(&borrowed.bank)
+        .map_err(|err| anyhow!("{err}"))?
+        .0;
+    if (borrowed_shares == 0 && stable_borrowed == 0)
+        || collateral.token_program != borrowed.token_program
+    {
+        return Ok(None);
+    }
+
+    let total_collateral = math::shares_to_amount(
+        collateral_shares,
+        collateral.bank.total_deposits,
+        collateral.bank.total_deposit_shares,
+        false,
+    )
+    .map_err(|err| anyhow!("{err}"))?;
+    let total_borrowed = math::shares_to_amount(
+        borrowed_shares,
+        borrowed.bank.total_borrowed,
+        borrowed.bank.total_borrowed_shares,
+        true,
+    )
+    .map_err(|err| anyhow!("{err}"))?
+        + stable_borrowed;
+
+    let health_factor = user
+        .health_factor([
+            (&collateral.bank, collateral.unit_value()),
+            (&borrowed.bank, borrowed.unit_value()),
+        ])
+        .map_err(|err| anyhow!("{err}"))?;
+    if health_factor >= 1.0 {
+        return Ok(None);
+    }
+
+    let (repay_amount, seize_amount) = math::liquidation_amounts(
+        total_borrowed,
+        total_collateral,
+        borrowed.bank.liquidation_close_factor,
+        collateral.bank.liquidation_bonus,
+        borrowed.unit_value(),
+        collateral.unit_value(),
+    )
+    .map_err(|err| anyhow!("{err}"))?;
+    if repay_amount == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Opportunity {
+        owner: user.owner,
+        index: user.index,
+        collateral_mint: collateral.mint,
+        borrowed_mint: borrowed.mint,
+        collateral_price_account: collateral.price_account,
+        borrowed_price_account: borrowed.price_account,
+        token_program: borrowed.token_program,
+        health_factor,
+        repay_amount,
+        seize_amount,
+        profit_usd: seize_amount as f64 * collateral.unit_value()
+            - repay_amount as f64 * borrowed.unit_value(),
+    }))
+}
@@ -0,0 +1,93 @@
+//! Off-chain liquidation keeper for the lending program.
+//!
+//! Positions are valued with `lending::math`, the same code the program runs,
+//! so a position the keeper flags is one `liquidate` will accept.
+
+pub mod accounts;
+pub mod health;
+pub mod liquidator;
+
+use anyhow::Result;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::keypair::Keypair};
+
+use crate::health::Opportunity;
+
+pub struct Config {
+    pub usdc_mint: Pubkey,
+    pub sol_mint: Pubkey,
+    pub usdc_price_account: Pubkey,
+    pub sol_price_account: Pubkey,
+    /// Liquidations whose bonus is worth less than this many USD are skipped.
+    pub min_profit_usd: f64,
+    pub dry_run: bool,
+}
+
+/// Loads every position and returns the ones that can be liquidated profitably.
+pub fn scan(rpc: &RpcClient, config: &Config) -> Result<Vec<Opportunity>> {
+    let now = rpc.get_block_time(rpc.get_slot()?)?;
+    let usdc = accounts::load_asset(
+        rpc,
+        &config.usdc_mint,
+        &config.usdc_price_account,
+        lending::constants::USDC_USD_FEED_ID,
+        now,
+    )?;
+    let sol = accounts::load_asset(
+        rpc,
+        &config.sol_mint,
+        &config.sol_price_account,
+        lending::constants::SOL_USD_FEED_ID,
+        now,
+    )?;
+
+    let mut opportunities = Vec::new();
+    for (_, user) in accounts::fetch_all::<lending::state::User>(rpc)? {
+        for (collateral, borrowed) in [(&usdc, &sol), (&sol, &usdc)] {
+            if let Some(opportunity) = health::evaluate(&user, collateral, borrowed, now)? {
+                if opportunity.profit_usd >= config.min_profit_usd {
+                    opportunities.push(opportunity);
+                }
+            }
+        }
+    }
+    opportunities.sort_by(|a, b| b.profit_usd.total_cmp(&a.profit_usd));
+    Ok(opportunities)
+}
+
+/// Scans once and, unless running dry, submits a liquidation for every opportunity found.
+pub fn run_once(
+    rpc: &RpcClient,
+    payer: &Keypair,
+    config: &Config,
+) -> Result<Vec<(Opportunity, Option<Signature>)>> {
+    let mut results = Vec::new();
+    for opportunity in scan(rpc, config)? {
+        println!(
+            "{}/{} health {:.4}: repay {} of {} for {} of {} (~${:.2} profit)",
+            opportunity.owner,
+            opportunity.index,
+            opportunity.health_factor,
+            opportunity.repay_amount,
+            opportunity.borrowed_mint,
+            opportunity.seize_amount,
+            opportunity.collateral_mint,
+            opportunity.profit_usd,
+        );
+        if config.dry_run {
+            results.push((opportunity, None));
+            continue;
+        }
+        match liquidator::execute(rpc, payer, &opportunity) {
+            Ok(signature) => {
+                println!("  liquidated: {signature}");
+                results.push((opportunity, Some(signature)));
+            }
+            Err(err) => {
+                eprintln!("  liquidation failed: {err:#}");
+                results.push((opportunity, None));
+            }
+        }
+    }
+    Ok(results)
+}
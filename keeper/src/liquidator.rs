@@ -0,0 +1,81 @@
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::{
+    get_associated_token_address_with_program_id, spl_associated_token_account,
+};
+use anyhow::{ensure, Result};
+use lending::pda::{bank_address, treasury_address, user_address};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction, signature::Signature, signer::keypair::Keypair, signer::Signer,
+    system_program, transaction::Transaction,
+};
+
+use crate::health::Opportunity;
+
+pub fn liquidate_ix(
+    liquidator: &solana_sdk::pubkey::Pubkey,
+    opportunity: &Opportunity,
+) -> Instruction {
+    let token_program = opportunity.token_program;
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::Liquidate {
+            liquidator: *liquidator,
+            collateral_price_update: opportunity.collateral_price_account,
+            borrowed_price_update: opportunity.borrowed_price_account,
+            collateral_mint: opportunity.collateral_mint,
+            borrowed_mint: opportunity.borrowed_mint,
+            collateral_bank: bank_address(&opportunity.collateral_mint),
+            borrowed_bank: bank_address(&opportunity.borrowed_mint),
+            collateral_bank_token_account: treasury_address(&opportunity.collateral_mint),
+            borrowed_bank_token_account: treasury_address(&opportunity.borrowed_mint),
+            user_account: user_address(&opportunity.owner, opportunity.index),
+            liquidator_collateral_token_account: get_associated_token_address_with_program_id(
+                liquidator,
+                &opportunity.collateral_mint,
+                &token_program,
+            ),
+            liquidator_borrowed_token_account: get_associated_token_address_with_program_id(
+                liquidator,
+                &opportunity.borrowed_mint,
+                &token_program,
+            ),
+            term_pool: None,
+            term_loan: None,
+            term_vault: None,
+            associated_token_program: spl_associated_token_account::id(),
+            token_program,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::Liquidate {}.data(),
+    }
+}
+
+/// Submits the liquidation after checking the liquidator can cover the repayment.
+pub fn execute(rpc: &RpcClient, payer: &Keypair, opportunity: &Opportunity) -> Result<Signature> {
+    let source = get_associated_token_address_with_program_id(
+        &payer.pubkey(),
+        &opportunity.borrowed_mint,
+        &opportunity.token_program,
+    );
+    let balance = rpc
+        .get_token_account_balance(&source)
+        .map(|balance| balance.amount.parse::<u64>().unwrap_or_default())
+        .unwrap_or_default();
+    ensure!(
+        balance >= opportunity.repay_amount,
+        "liquidator holds {balance} of {}, needs {}",
+        opportunity.borrowed_mint,
+        opportunity.repay_amount
+    );
+
+    let blockhash = rpc.get_latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(
+        &[liquidate_ix(&payer.pubkey(), opportunity)],
+        Some(&payer.pubkey()),
+        &[payer],
+        blockhash,
+    );
+    Ok(rpc.send_and_confirm_transaction(&tx)?)
+}
@@ -0,0 +1,84 @@
+use std::{thread, time::Duration};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use lending_keeper::{run_once, Config};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, pubkey::Pubkey, signature::read_keypair_file,
+};
+
+/// Scans lending positions and liquidates the unhealthy ones.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[arg(long, env = "RPC_URL", default_value = "http://127.0.0.1:8899")]
+    rpc_url: String,
+
+    /// Keypair that signs liquidations and holds the repayment tokens.
+    #[arg(
+        long,
+        env = "KEEPER_KEYPAIR",
+        default_value = "~/.config/solana/id.json"
+    )]
+    keypair: String,
+
+    #[arg(long)]
+    usdc_mint: Pubkey,
+
+    #[arg(long)]
+    sol_mint: Pubkey,
+
+    /// PriceUpdateV2 account carrying the USDC/USD feed.
+    #[arg(long)]
+    usdc_price: Pubkey,
+
+    /// PriceUpdateV2 account carrying the SOL/USD feed.
+    #[arg(long)]
+    sol_price: Pubkey,
+
+    /// Skip liquidations whose bonus is worth less than this many USD.
+    #[arg(long, default_value_t = 0.0)]
+    min_profit_usd: f64,
+
+    /// Report opportunities without sending transactions.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Seconds between scans.
+    #[arg(long, default_value_t = 10)]
+    interval: u64,
+
+    /// Scan a single time and exit.
+    #[arg(long)]
+    once: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let keypair_path = match args.keypair.strip_prefix("~/") {
+        Some(rest) => format!("{}/{rest}", std::env::var("HOME")?),
+        None => args.keypair.clone(),
+    };
+    let payer = read_keypair_file(&keypair_path)
+        .map_err(|err| anyhow!("reading keypair {keypair_path}: {err}"))?;
+    let rpc = RpcClient::new_with_commitment(args.rpc_url.clone(), CommitmentConfig::confirmed());
+    let config = Config {
+        usdc_mint: args.usdc_mint,
+        sol_mint: args.sol_mint,
+        usdc_price_account: args.usdc_price,
+        sol_price_account: args.sol_price,
+        min_profit_usd: args.min_profit_usd,
+        dry_run: args.dry_run,
+    };
+
+    loop {
+        if let Err(err) = run_once(&rpc, &payer, &config) {
+            eprintln!("scan failed: {err:#}");
+        }
+        if args.once {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_secs(args.interval));
+    }
+}
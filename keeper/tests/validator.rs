@@ -0,0 +1,342 @@
+//! End-to-end run of the keeper against `solana-test-validator`.
+//!
+//! Loads the compiled `lending.so`, so run `anchor build` first.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anchor_lang::{
+    prelude::Pubkey, solana_program::program_pack::Pack, AccountDeserialize, AccountSerialize,
+    InstructionData, ToAccountMetas,
+};
+use anchor_spl::{
+    associated_token::{get_associated_token_address, spl_associated_token_account},
+    token::spl_token,
+};
+use lending::{
+    pda::{bank_address, roles_address, treasury_address, user_address},
+    state::User,
+};
+use lending_keeper::{run_once, Config};
+use pyth_solana_receiver_sdk::price_update::{
+    get_feed_id_from_hex, PriceFeedMessage, PriceUpdateV2, VerificationLevel,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    instruction::Instruction,
+    signature::Keypair,
+    signer::Signer,
+    system_instruction, system_program,
+    transaction::Transaction,
+};
+use solana_test_validator::TestValidatorGenesis;
+
+fn price_account(feed_id_hex: &str, price: i64, publish_time: i64) -> AccountSharedData {
+    let update = PriceUpdateV2 {
+        write_authority: Pubkey::new_unique(),
+        verification_level: VerificationLevel::Full,
+        price_message: PriceFeedMessage {
+            feed_id: get_feed_id_from_hex(feed_id_hex).unwrap(),
+            price,
+            conf: 0,
+            exponent: 0,
+            publish_time,
+            prev_publish_time: publish_time,
+            ema_price: price,
+            ema_conf: 0,
+        },
+        posted_slot: 0,
+    };
+    let mut data = Vec::new();
+    update.try_serialize(&mut data).unwrap();
+    AccountSharedData::from(Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: pyth_solana_receiver_sdk::ID,
+        executable: false,
+        rent_epoch: 0,
+    })
+}
+
+fn send(rpc: &RpcClient, payer: &Keypair, ixs: &[Instruction], signers: &[&Keypair]) {
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        ixs,
+        Some(&payer.pubkey()),
+        &all_signers,
+        rpc.get_latest_blockhash().unwrap(),
+    );
+    rpc.send_and_confirm_transaction(&tx).unwrap();
+}
+
+fn create_mint(rpc: &RpcClient, payer: &Keypair, decimals: u8) -> Pubkey {
+    let mint = Keypair::new();
+    let lamports = rpc
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)
+        .unwrap();
+    send(
+        rpc,
+        payer,
+        &[
+            system_instruction::create_account(
+                &payer.pubkey(),
+                &mint.pubkey(),
+                lamports,
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_mint2(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &payer.pubkey(),
+                None,
+                decimals,
+            )
+            .unwrap(),
+        ],
+        &[&mint],
+    );
+    mint.pubkey()
+}
+
+fn fund_ata(rpc: &RpcClient, payer: &Keypair, mint: &Pubkey, owner: &Pubkey, amount: u64) {
+    send(
+        rpc,
+        payer,
+        &[
+            spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                &payer.pubkey(),
+                owner,
+                mint,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &get_associated_token_address(owner, mint),
+                &payer.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        ],
+        &[],
+    );
+}
+
+fn lending_ix(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+#[test]
+fn keeper_liquidates_underwater_position() {
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        std::env::set_var(
+            "BPF_OUT_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../target/deploy"),
+        );
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let (usdc_price, sol_price, sol_price_after_pump) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+
+    let mut genesis = TestValidatorGenesis::default();
+    genesis
+        .add_program("lending", lending::ID)
+        .add_account(
+            usdc_price,
+            price_account(lending::constants::USDC_USD_FEED_ID, 1, now),
+        )
+        .add_account(
+            sol_price,
+            price_account(lending::constants::SOL_USD_FEED_ID, 100, now),
+        )
+        .add_account(
+            sol_price_after_pump,
+            price_account(lending::constants::SOL_USD_FEED_ID, 200, now),
+        );
+    let (validator, payer) = genesis.start();
+    let rpc = validator.get_rpc_client();
+
+    let usdc_mint = create_mint(&rpc, &payer, 6);
+    let sol_mint = create_mint(&rpc, &payer, 9);
+    send(
+        &rpc,
+        &payer,
+        &[lending_ix(
+            lending::accounts::SetRoles {
+                signer: payer.pubkey(),
+                roles: roles_address(),
+                system_program: system_program::id(),
+            },
+            lending::instruction::SetRoles {
+                risk_admin: Pubkey::default(),
+                guardian: Pubkey::default(),
+                fee_admin: Pubkey::default(),
+                listing_admin: Pubkey::default(),
+            },
+        )],
+        &[],
+    );
+    for mint in [usdc_mint, sol_mint] {
+        send(
+            &rpc,
+            &payer,
+            &[lending_ix(
+                lending::accounts::InitBank {
+                    signer: payer.pubkey(),
+                    mint,
+                    bank: bank_address(&mint),
+                    bank_token_account: treasury_address(&mint),
+                    roles: roles_address(),
+                    token_program: spl_token::id(),
+                    system_program: system_program::id(),
+                },
+                lending::instruction::InitBank {
+                    liquidation_threshold: 80,
+                    max_ltv: 75,
+                    allow_unsafe_mint: false,
+                },
+            )],
+            &[],
+        );
+    }
+    send(
+        &rpc,
+        &payer,
+        &[spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &sol_mint,
+            &treasury_address(&sol_mint),
+            &payer.pubkey(),
+            &[],
+            1_000_000_000,
+        )
+        .unwrap()],
+        &[],
+    );
+
+    let borrower = Keypair::new();
+    send(
+        &rpc,
+        &payer,
+        &[system_instruction::transfer(
+            &payer.pubkey(),
+            &borrower.pubkey(),
+            1_000_000_000,
+        )],
+        &[],
+    );
+    fund_ata(&rpc, &payer, &usdc_mint, &borrower.pubkey(), 1_000_000);
+    send(
+        &rpc,
+        &payer,
+        &[
+            lending_ix(
+                lending::accounts::InitUser {
+                    signer: borrower.pubkey(),
+                    user_account: user_address(&borrower.pubkey(), 0),
+                    system_program: system_program::id(),
+                },
+                lending::instruction::InitUser {
+                    usdc_address: usdc_mint,
+                    sol_address: sol_mint,
+                    index: 0,
+                    referrer: None,
+                },
+            ),
+            lending_ix(
+                lending::accounts::Deposit {
+                    signer: borrower.pubkey(),
+                    mint: usdc_mint,
+                    bank: bank_address(&usdc_mint),
+                    bank_token_account: treasury_address(&usdc_mint),
+                    user: user_address(&borrower.pubkey(), 0),
+                    user_token_account: Some(get_associated_token_address(
+                        &borrower.pubkey(),
+                        &usdc_mint,
+                    )),
+                    wsol_account: None,
+                    token_program: spl_token::id(),
+                    system_program: system_program::id(),
+                    associated_token_program: spl_associated_token_account::id(),
+                    reward_pool: None,
+                    reward_position: None,
+                },
+                lending::instruction::Deposit { amount: 1_000_000 },
+            ),
+            lending_ix(
+                lending::accounts::Borrow {
+                    signer: borrower.pubkey(),
+                    owner: borrower.pubkey(),
+                    borrow_mint: sol_mint,
+                    other_mint: usdc_mint,
+                    borrow_bank: bank_address(&sol_mint),
+                    other_bank: bank_address(&usdc_mint),
+                    borrow_bank_token_account: treasury_address(&sol_mint),
+                    user: user_address(&borrower.pubkey(), 0),
+                    delegation: None,
+                    user_token_account: Some(get_associated_token_address(
+                        &borrower.pubkey(),
+                        &sol_mint,
+                    )),
+                    wsol_account: None,
+                    host_fee_receiver: None,
+                    referral_fees: None,
+                    associated_token_program: spl_associated_token_account::id(),
+                    token_program: spl_token::id(),
+                    system_program: system_program::id(),
+                    price_update: sol_price,
+                    other_price_update: usdc_price,
+                    reward_pool: None,
+                    reward_position: None,
+                },
+                lending::instruction::Borrow { amount: 5_000_000 },
+            ),
+        ],
+        &[&borrower],
+    );
+    fund_ata(&rpc, &payer, &sol_mint, &payer.pubkey(), 10_000_000);
+
+    let mut config = Config {
+        usdc_mint,
+        sol_mint,
+        usdc_price_account: usdc_price,
+        sol_price_account: sol_price,
+        min_profit_usd: 0.0,
+        dry_run: false,
+    };
+    assert!(run_once(&rpc, &payer, &config).unwrap().is_empty());
+
+    config.sol_price_account = sol_price_after_pump;
+    config.dry_run = true;
+    let dry_run = run_once(&rpc, &payer, &config).unwrap();
+    assert_eq!(dry_run.len(), 1);
+    assert!(dry_run[0].1.is_none());
+
+    config.min_profit_usd = 1_000.0;
+    assert!(run_once(&rpc, &payer, &config).unwrap().is_empty());
+
+    config.min_profit_usd = 0.0;
+    config.dry_run = false;
+    let live = run_once(&rpc, &payer, &config).unwrap();
+    assert_eq!(live.len(), 1);
+    assert!(live[0].1.is_some());
+
+    let account = rpc
+        .get_account(&user_address(&borrower.pubkey(), 0))
+        .unwrap();
+    let position = User::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(position.borrowed_sol, 2_500_000);
+}
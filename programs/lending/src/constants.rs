@@ -6,3 +6,17 @@ pub const SOL_USD_FEED_ID: &str =
 pub const USDC_USD_FEED_ID: &str =
     "0xeaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a";
 pub const MAX_AGE: u64 = 60; //不能超过100, 不然会报错
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+pub const DEFAULT_LIQUIDATION_BONUS: u64 = 5;
+pub const DEFAULT_LIQUIDATION_CLOSE_FACTOR: u64 = 50;
+/// Longest a guardian's outflow limit increase may last.
+pub const MAX_OUTFLOW_BOOST_DURATION: i64 = 24 * 60 * 60;
+/// Highest origination fee a bank may charge, in basis points.
+pub const MAX_BORROW_FEE_BPS: u64 = 1_000;
+/// Fixed-point scale of reward pool indexes.
+pub const REWARD_INDEX_SCALE: u128 = 1_000_000_000_000;
+/// Longest a bank may make its config changes wait.
+pub const MAX_CONFIG_DELAY: i64 = 30 * 24 * 60 * 60;
+/// Amount that tells `withdraw` and `repay` to close out the whole balance.
+#[constant]
+pub const AMOUNT_ALL: u64 = u64::MAX;
@@ -4,10 +4,84 @@ use anchor_lang::prelude::*;
 pub enum ErrorCode {
     #[msg("Insufficient Funds")]
     InsufficientFunds,
+    /// No longer returned; kept so the codes after it keep their numbers.
     #[msg("Over Borrowable Amount")]
     OverBorrowableAmount,
     #[msg("Over Repay")]
     OverRepay,
     #[msg("User is not Under collateralized,can't be liquidated")]
     NotUnderCollateralized,
+    #[msg("Math Overflow")]
+    MathOverflow,
+    #[msg("Amount is too small")]
+    AmountTooSmall,
+    #[msg("Signer is neither the bank authority nor holds the required role")]
+    Unauthorized,
+    #[msg("Invalid bank config")]
+    InvalidConfig,
+    #[msg("Bank is paused")]
+    BankPaused,
+    /// A guard against token programs that move more than asked; SPL Token and Token-2022
+    /// never trip it.
+    #[msg("Vault balance moved by an unexpected amount")]
+    TransferAmountMismatch,
+    #[msg("Mint has extensions or authorities that put vault funds at risk")]
+    UnsafeMint,
+    #[msg("Signer is neither the owner nor an approved delegate")]
+    NotDelegated,
+    #[msg("Borrow exceeds the delegated allowance")]
+    InsufficientAllowance,
+    #[msg("Position would exceed its borrow limit")]
+    PositionUnhealthy,
+    #[msg("Sub-accounts are the same or track different assets")]
+    SubAccountMismatch,
+    #[msg("Account is not a bank or position")]
+    UnknownAccount,
+    #[msg("Position still has deposits or debt")]
+    PositionNotEmpty,
+    #[msg("Bank still has deposits or debt")]
+    BankNotEmpty,
+    #[msg("Outflow limit for this window reached")]
+    OutflowLimitExceeded,
+    #[msg("Signer is not the bank guardian")]
+    NotGuardian,
+    #[msg("Change must be queued and wait out the bank's config delay")]
+    TimelockRequired,
+    #[msg("Queued change cannot be executed yet")]
+    TimelockNotElapsed,
+    #[msg("Referral fee account must be passed exactly when the position has a referrer")]
+    ReferralAccountMismatch,
+    #[msg("Reward pool and position must be passed while the bank has rewards on that side")]
+    RewardAccountsMissing,
+    #[msg("Bank does not allow stable-rate borrowing")]
+    StableBorrowDisabled,
+    #[msg("Utilization is below the bank's stable rebalance threshold")]
+    RebalanceNotAllowed,
+    #[msg("Term pool has matured")]
+    TermPoolMatured,
+    #[msg("Term pool has not matured yet")]
+    TermNotMatured,
+    #[msg("Term pool still has loans outstanding")]
+    TermLoansOutstanding,
+    #[msg("Swap must move between the position's two assets through another program")]
+    InvalidSwap,
+    #[msg("Swap returned less than the minimum amount out")]
+    SlippageExceeded,
+    #[msg("Native SOL can only move through the wrapped SOL bank")]
+    NotNativeMint,
+    #[msg("Bank is not the position's USDC or SOL bank")]
+    UnknownBank,
+    #[msg("Position has no stable debt in this asset")]
+    NoStableDebt,
+    #[msg("Bank still holds protocol fees")]
+    FeesUnclaimed,
+    /// No longer returned; kept so the codes after it keep their numbers.
+    #[msg("Reward vault still holds rewards")]
+    RewardsUnclaimed,
+    #[msg("Native paths take the wrapped SOL account, the others the token account")]
+    PaymentAccountMismatch,
+    #[msg("Leverage must be above 1x")]
+    InvalidLeverage,
+    #[msg("Term pool, loan and vault must be passed together and belong to the position")]
+    TermLoanMismatch,
 }
@@ -1,6 +1,16 @@
-use crate::state::*;
+use crate::{constants, error::ErrorCode, pda, state::*};
 use anchor_lang::prelude::*;
-use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use anchor_spl::{
+    token_2022::spl_token_2022::{
+        self,
+        extension::{
+            default_account_state::DefaultAccountState, transfer_hook::TransferHook,
+            BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+        },
+        state::AccountState,
+    },
+    token_interface::{self, Mint, TokenAccount, TokenInterface},
+};
 
 #[derive(Accounts)]
 pub struct InitBank<'info> {
@@ -13,7 +23,7 @@ pub struct InitBank<'info> {
         init,
         payer = signer,
         space = 8 + Bank::INIT_SPACE,
-        seeds = [mint.key().as_ref()],
+        seeds = [b"bank", mint.key().as_ref()],
         bump
     )]
     pub bank: Account<'info, Bank>,
@@ -28,11 +38,36 @@ pub struct InitBank<'info> {
     )]
     pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
 
+    /// Lists the bank under the market authority, which must be the signer or have made the
+    /// signer its listing admin.
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Account<'info, Roles>,
+
     pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
+/// Creates or updates the market's roles account. Whoever creates it, as part of deploying
+/// the market, becomes the market authority; only that key may change it afterwards.
 #[derive(Accounts)]
+pub struct SetRoles<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + Roles::INIT_SPACE,
+        seeds = [b"roles"],
+        bump
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(usdc_address: Pubkey, sol_address: Pubkey, index: u8)]
 pub struct InitUser<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
@@ -41,7 +76,7 @@ pub struct InitUser<'info> {
         init,
         payer = signer,
         space = 8 + User::INIT_SPACE,
-        seeds = [signer.key().as_ref()],
+        seeds = [b"user", signer.key().as_ref(), index.to_le_bytes().as_ref()],
         bump
     )]
     pub user_account: Account<'info, User>,
@@ -49,23 +84,627 @@ pub struct InitUser<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct UpdateBank<'info> {
+    pub signer: Signer<'info>,
+
+    #[account(mut)]
+    pub bank: Account<'info, Bank>,
+
+    /// Needed when the signer acts through a role rather than as the bank authority.
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseOutflowLimit<'info> {
+    pub signer: Signer<'info>,
+
+    #[account(mut)]
+    pub bank: Account<'info, Bank>,
+
+    /// Needed when the signer is the market guardian rather than the bank's own.
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program
+    )]
+    pub fee_destination: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUser<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        close = signer,
+        seeds = [b"user", signer.key().as_ref(), user_account.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, User>,
+}
+
+/// Delists an empty bank once its protocol fees are collected. Whatever is left in the
+/// treasury, such as rounding dust or referral fees never claimed, is swept to `destination`
+/// before the treasury is closed, and unclaimed rewards are swept out of each reward vault
+/// the same way.
+#[derive(Accounts)]
+pub struct CloseBank<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        close = signer,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump,
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Required while the bank rewards deposits, with the reward mint and an account of it
+    /// to sweep the vault into.
+    #[account(mut)]
+    pub deposit_reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub deposit_reward_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub deposit_reward_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required while the bank rewards borrows, like the deposit side.
+    #[account(mut)]
+    pub borrow_reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub borrow_reward_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub borrow_reward_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+
+/// Risk and rate parameters an authority may change after a bank is created.
+/// Fields left as `None` keep their current value.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, InitSpace)]
+pub struct BankConfigParams {
+    pub liquidation_threshold: Option<u64>,
+    pub liquidation_bonus: Option<u64>,
+    pub liquidation_close_factor: Option<u64>,
+    pub max_ltv: Option<u64>,
+    pub interest_rate: Option<f64>,
+    pub reserve_factor: Option<u64>,
+    pub guardian: Option<Pubkey>,
+    pub outflow_window: Option<i64>,
+    pub outflow_limit: Option<u64>,
+    pub outflow_limit_percent: Option<u64>,
+    pub config_delay: Option<i64>,
+    pub borrow_fee_bps: Option<u64>,
+    pub host_fee_percent: Option<u64>,
+    pub referral_fee_percent: Option<u64>,
+    pub stable_borrow_enabled: Option<bool>,
+    pub stable_rate_premium: Option<f64>,
+    pub stable_rebalance_utilization: Option<u64>,
+}
+
+impl BankConfigParams {
+    /// Writes every field that is set onto `bank` and checks the resulting config.
+    pub fn apply(&self, bank: &mut Bank) -> Result<()> {
+        if let Some(liquidation_threshold) = self.liquidation_threshold {
+            bank.liquidation_threshold = liquidation_threshold;
+        }
+        if let Some(liquidation_bonus) = self.liquidation_bonus {
+            bank.liquidation_bonus = liquidation_bonus;
+        }
+        if let Some(liquidation_close_factor) = self.liquidation_close_factor {
+            bank.liquidation_close_factor = liquidation_close_factor;
+        }
+        if let Some(max_ltv) = self.max_ltv {
+            bank.max_ltv = max_ltv;
+        }
+        if let Some(interest_rate) = self.interest_rate {
+            bank.instrest_rate = interest_rate;
+        }
+        if let Some(reserve_factor) = self.reserve_factor {
+            bank.reserve_factor = reserve_factor;
+        }
+        if let Some(guardian) = self.guardian {
+            bank.guardian = guardian;
+        }
+        if let Some(outflow_window) = self.outflow_window {
+            bank.outflow_window = outflow_window;
+        }
+        if let Some(outflow_limit) = self.outflow_limit {
+            bank.outflow_limit = outflow_limit;
+        }
+        if let Some(outflow_limit_percent) = self.outflow_limit_percent {
+            bank.outflow_limit_percent = outflow_limit_percent;
+        }
+        if let Some(config_delay) = self.config_delay {
+            bank.config_delay = config_delay;
+        }
+        if let Some(borrow_fee_bps) = self.borrow_fee_bps {
+            bank.borrow_fee_bps = borrow_fee_bps;
+        }
+        if let Some(host_fee_percent) = self.host_fee_percent {
+            bank.host_fee_percent = host_fee_percent;
+        }
+        if let Some(referral_fee_percent) = self.referral_fee_percent {
+            bank.referral_fee_percent = referral_fee_percent;
+        }
+        if let Some(stable_borrow_enabled) = self.stable_borrow_enabled {
+            bank.stable_borrow_enabled = stable_borrow_enabled;
+        }
+        if let Some(stable_rate_premium) = self.stable_rate_premium {
+            bank.stable_rate_premium = stable_rate_premium;
+        }
+        if let Some(stable_rebalance_utilization) = self.stable_rebalance_utilization {
+            bank.stable_rebalance_utilization = stable_rebalance_utilization;
+        }
+        bank.validate_config()
+    }
+
+    /// Whether the change can only make `bank` safer for its users: lowering `max_ltv` or
+    /// lengthening `config_delay`. Such changes skip the timelock.
+    pub fn reduces_risk(&self, bank: &Bank) -> bool {
+        let BankConfigParams {
+            liquidation_threshold,
+            liquidation_bonus,
+            liquidation_close_factor,
+            max_ltv,
+            interest_rate,
+            reserve_factor,
+            guardian,
+            outflow_window,
+            outflow_limit,
+            outflow_limit_percent,
+            config_delay,
+            borrow_fee_bps,
+            host_fee_percent,
+            referral_fee_percent,
+            stable_borrow_enabled,
+            stable_rate_premium,
+            stable_rebalance_utilization,
+        } = self;
+        liquidation_threshold.is_none()
+            && liquidation_bonus.is_none()
+            && liquidation_close_factor.is_none()
+            && interest_rate.is_none()
+            && reserve_factor.is_none()
+            && guardian.is_none()
+            && outflow_window.is_none()
+            && outflow_limit.is_none()
+            && outflow_limit_percent.is_none()
+            && borrow_fee_bps.is_none()
+            && host_fee_percent.is_none()
+            && referral_fee_percent.is_none()
+            && stable_borrow_enabled.is_none()
+            && stable_rate_premium.is_none()
+            && stable_rebalance_utilization.is_none()
+            && max_ltv.map_or(true, |max_ltv| max_ltv <= bank.max_ltv)
+            && config_delay.map_or(true, |delay| delay >= bank.config_delay)
+    }
+}
+
 pub fn process_init_bank(
     ctx: Context<InitBank>,
     liquidation_threshold: u64,
     max_ltv: u64,
+    allow_unsafe_mint: bool,
 ) -> Result<()> {
+    if let Some(reason) = mint_risk(&ctx.accounts.mint)? {
+        msg!("unsafe mint: {}", reason);
+        require!(allow_unsafe_mint, ErrorCode::UnsafeMint);
+        ctx.accounts.bank.unsafe_mint_allowed = true;
+    }
+
+    let roles = &ctx.accounts.roles;
+    roles.authorize(&ctx.accounts.signer.key(), &[Role::Listing])?;
+    let authority = roles.authority;
+
     let bank = &mut ctx.accounts.bank;
     bank.mint_address = ctx.accounts.mint.key();
-    bank.authority = ctx.accounts.signer.key();
+    bank.authority = authority;
     bank.liquidation_threshold = liquidation_threshold;
     bank.max_ltv = max_ltv;
+    bank.liquidation_bonus = constants::DEFAULT_LIQUIDATION_BONUS;
+    bank.liquidation_close_factor = constants::DEFAULT_LIQUIDATION_CLOSE_FACTOR;
     bank.instrest_rate = 0.05_f64; //Something wrong here
+    bank.last_updated = Clock::get()?.unix_timestamp;
+    bank.version = Bank::VERSION;
+    bank.validate_config()
+}
+
+pub fn process_set_roles(
+    ctx: Context<SetRoles>,
+    risk_admin: Pubkey,
+    guardian: Pubkey,
+    fee_admin: Pubkey,
+    listing_admin: Pubkey,
+) -> Result<()> {
+    let roles = &mut ctx.accounts.roles;
+    if roles.authority == Pubkey::default() {
+        roles.authority = ctx.accounts.signer.key();
+    }
+    require_keys_eq!(
+        ctx.accounts.signer.key(),
+        roles.authority,
+        ErrorCode::Unauthorized
+    );
+    roles.risk_admin = risk_admin;
+    roles.guardian = guardian;
+    roles.fee_admin = fee_admin;
+    roles.listing_admin = listing_admin;
     Ok(())
 }
 
-pub fn process_init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
+pub fn process_init_user(
+    ctx: Context<InitUser>,
+    usdc_address: Pubkey,
+    sol_address: Pubkey,
+    index: u8,
+    referrer: Option<Pubkey>,
+) -> Result<()> {
+    require_keys_neq!(usdc_address, sol_address, ErrorCode::InvalidConfig);
+    // a referrer must be someone else, or a wallet would rebate its own borrow fees
+    if let Some(referrer) = referrer {
+        require_keys_neq!(
+            referrer,
+            ctx.accounts.signer.key(),
+            ErrorCode::InvalidConfig
+        );
+    }
     let user_account = &mut ctx.accounts.user_account;
     user_account.owner = ctx.accounts.signer.key();
     user_account.usdc_address = usdc_address;
+    user_account.sol_address = sol_address;
+    user_account.index = index;
+    user_account.referrer = referrer.unwrap_or_default();
+    user_account.version = User::VERSION;
+    Ok(())
+}
+
+pub fn process_update_bank_config(ctx: Context<UpdateBank>, params: BankConfigParams) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    // the guardian may only make the bank safer
+    let allowed: &[Role] = if params.reduces_risk(bank) {
+        &[Role::Risk, Role::Guardian]
+    } else {
+        &[Role::Risk]
+    };
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        allowed,
+    )?;
+    // anything users could be hurt by has to go through queue_bank_config instead
+    require!(
+        bank.config_delay == 0 || params.reduces_risk(bank),
+        ErrorCode::TimelockRequired
+    );
+    // settle interest at the old rate before anything changes
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+    params.apply(bank)
+}
+
+pub fn process_set_paused(ctx: Context<UpdateBank>, paused: bool) -> Result<()> {
+    let allowed: &[Role] = if paused {
+        &[Role::Risk, Role::Guardian]
+    } else {
+        &[Role::Risk]
+    };
+    let bank = &mut ctx.accounts.bank;
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        allowed,
+    )?;
+    bank.paused = paused;
+    Ok(())
+}
+
+/// Hands the bank to `new_authority`. Roles appointed by the old authority stop applying to it.
+pub fn process_transfer_authority(ctx: Context<UpdateBank>, new_authority: Pubkey) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.authorize(None, &ctx.accounts.signer.key(), &[])?;
+    bank.authority = new_authority;
     Ok(())
 }
+
+/// Lets `extra` more flow out per window for the next `duration` seconds, replacing any
+/// earlier increase.
+pub fn process_raise_outflow_limit(
+    ctx: Context<RaiseOutflowLimit>,
+    extra: u64,
+    duration: i64,
+) -> Result<()> {
+    let signer = ctx.accounts.signer.key();
+    let bank = &mut ctx.accounts.bank;
+    let market_guardian = ctx
+        .accounts
+        .roles
+        .as_ref()
+        .filter(|roles| roles.authority == bank.authority)
+        .map(|roles| roles.guardian);
+    require!(
+        signer == bank.guardian || Some(signer) == market_guardian,
+        ErrorCode::NotGuardian
+    );
+    require!(
+        (0..=constants::MAX_OUTFLOW_BOOST_DURATION).contains(&duration),
+        ErrorCode::InvalidConfig
+    );
+    bank.outflow_boost = extra;
+    bank.outflow_boost_expires_at = Clock::get()?.unix_timestamp + duration;
+    Ok(())
+}
+
+pub fn process_collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        &[Role::Fee],
+    )?;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
+
+    let amount = bank.protocol_fees.min(ctx.accounts.bank_token_account.amount);
+    bank.protocol_fees -= amount;
+
+    let transfer_cpi_account = token_interface::TransferChecked {
+        from: ctx.accounts.bank_token_account.to_account_info(),
+        mint: ctx.accounts.mint.to_account_info(),
+        to: ctx.accounts.fee_destination.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"treasury",
+        mint_key.as_ref(),
+        &[ctx.bumps.bank_token_account],
+    ]];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        transfer_cpi_account,
+        signer_seeds,
+    );
+
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)
+}
+
+pub fn process_close_user(ctx: Context<CloseUser>) -> Result<()> {
+    let user = &ctx.accounts.user_account;
+    require!(
+        user.deposited_sol_shares == 0
+            && user.borrowed_sol_shares == 0
+            && user.deposited_usdc_shares == 0
+            && user.borrowed_usdc_shares == 0
+            && user.deposited_sol == 0
+            && user.borrowed_sol == 0
+            && user.deposited_usdc == 0
+            && user.borrowed_usdc == 0
+            && user.stable_borrowed_sol == 0
+            && user.stable_borrowed_usdc == 0
+            && user.term_borrowed_sol == 0
+            && user.term_borrowed_usdc == 0,
+        ErrorCode::PositionNotEmpty
+    );
+    Ok(())
+}
+
+pub fn process_close_bank(ctx: Context<CloseBank>) -> Result<()> {
+    let bank = &ctx.accounts.bank;
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        &[Role::Listing],
+    )?;
+    require!(
+        bank.total_deposit_shares == 0
+            && bank.total_borrowed_shares == 0
+            && bank.total_stable_borrowed == 0
+            && bank.total_term_lent == 0
+            && bank.total_term_borrowed == 0,
+        ErrorCode::BankNotEmpty
+    );
+    // referral fees still owed sit in the treasury and are written off with the sweep below
+    require!(bank.protocol_fees == 0, ErrorCode::FeesUnclaimed);
+    for (active, side, vault, mint, destination) in [
+        (
+            bank.deposit_rewards,
+            RewardSide::Deposit,
+            ctx.accounts.deposit_reward_vault.as_ref(),
+            ctx.accounts.deposit_reward_mint.as_ref(),
+            ctx.accounts.deposit_reward_destination.as_ref(),
+        ),
+        (
+            bank.borrow_rewards,
+            RewardSide::Borrow,
+            ctx.accounts.borrow_reward_vault.as_ref(),
+            ctx.accounts.borrow_reward_mint.as_ref(),
+            ctx.accounts.borrow_reward_destination.as_ref(),
+        ),
+    ] {
+        if !active {
+            continue;
+        }
+        let (Some(vault), Some(mint), Some(destination)) = (vault, mint, destination) else {
+            return err!(ErrorCode::RewardAccountsMissing);
+        };
+        sweep_reward_vault(
+            &pda::reward_pool_address(&bank.key(), side),
+            vault,
+            mint,
+            destination,
+            &ctx.accounts.signer,
+            &ctx.accounts.token_program,
+        )?;
+    }
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"treasury",
+        mint_key.as_ref(),
+        &[ctx.bumps.bank_token_account],
+    ]];
+    let token_program = ctx.accounts.token_program.to_account_info();
+
+    let remaining = ctx.accounts.bank_token_account.amount;
+    if remaining > 0 {
+        let transfer_cpi_account = token_interface::TransferChecked {
+            from: ctx.accounts.bank_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.bank_token_account.to_account_info(),
+        };
+        let cpi_ctx =
+            CpiContext::new_with_signer(token_program.clone(), transfer_cpi_account, signer_seeds);
+        token_interface::transfer_checked(cpi_ctx, remaining, ctx.accounts.mint.decimals)?;
+    }
+
+    let close_cpi_account = token_interface::CloseAccount {
+        account: ctx.accounts.bank_token_account.to_account_info(),
+        destination: ctx.accounts.signer.to_account_info(),
+        authority: ctx.accounts.bank_token_account.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program, close_cpi_account, signer_seeds);
+    token_interface::close_account(cpi_ctx)
+}
+
+/// Pays whatever is left in `reward_pool`'s vault to `destination` and closes the vault,
+/// returning its rent to `signer`.
+fn sweep_reward_vault<'info>(
+    reward_pool: &Pubkey,
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    signer: &Signer<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    let (address, bump) =
+        Pubkey::find_program_address(&[b"reward_vault", reward_pool.as_ref()], &crate::ID);
+    require_keys_eq!(vault.key(), address, ErrorCode::RewardAccountsMissing);
+    let signer_seeds: &[&[&[u8]]] = &[&[b"reward_vault", reward_pool.as_ref(), &[bump]]];
+
+    if vault.amount > 0 {
+        let cpi_ctx = CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            token_interface::TransferChecked {
+                from: vault.to_account_info(),
+                mint: mint.to_account_info(),
+                to: destination.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token_interface::transfer_checked(cpi_ctx, vault.amount, mint.decimals)?;
+    }
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::CloseAccount {
+            account: vault.to_account_info(),
+            destination: signer.to_account_info(),
+            authority: vault.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token_interface::close_account(cpi_ctx)
+}
+
+/// Token-2022 extensions that leave vault balances under the bank's sole control.
+const SAFE_MINT_EXTENSIONS: &[ExtensionType] = &[
+    ExtensionType::TransferFeeConfig,
+    ExtensionType::MintCloseAuthority,
+    ExtensionType::InterestBearingConfig,
+    ExtensionType::MetadataPointer,
+    ExtensionType::TokenMetadata,
+    ExtensionType::GroupPointer,
+    ExtensionType::TokenGroup,
+    ExtensionType::GroupMemberPointer,
+    ExtensionType::TokenGroupMember,
+];
+
+/// Describes why `mint` could freeze, claw back or block transfers out of a vault, if it can.
+fn mint_risk(mint: &InterfaceAccount<Mint>) -> Result<Option<String>> {
+    if mint.freeze_authority.is_some() {
+        return Ok(Some("freeze authority is set".to_string()));
+    }
+    let info = mint.to_account_info();
+    if *info.owner != spl_token_2022::ID {
+        return Ok(None);
+    }
+
+    let data = info.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    for extension in state.get_extension_types()? {
+        let risky = match extension {
+            // a default state of Initialized is harmless; Frozen locks every new account
+            ExtensionType::DefaultAccountState => {
+                state.get_extension::<DefaultAccountState>()?.state == AccountState::Frozen as u8
+            }
+            // a hook pointer is only inert while no program is set and nobody can set one
+            ExtensionType::TransferHook => {
+                let hook = state.get_extension::<TransferHook>()?;
+                Option::<Pubkey>::from(hook.program_id).is_some()
+                    || Option::<Pubkey>::from(hook.authority).is_some()
+            }
+            extension => !SAFE_MINT_EXTENSIONS.contains(&extension),
+        };
+        if risky {
+            return Ok(Some(format!("{extension:?} extension")));
+        }
+    }
+    Ok(None)
+}
@@ -1,32 +1,57 @@
-use std::f64::consts::E;
-
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{self, Mint, TokenAccount, TokenInterface},
 };
-use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
 
 use crate::{
-    constants,
     error::ErrorCode,
-    state::{Bank, User},
+    math,
+    state::{Bank, Delegation, ReferralFees, RewardPool, RewardPosition, RewardSide, User},
+};
+
+use super::{
+    native::{payment_account, unwrap_sol},
+    price::unit_value,
+    rewards::settle_rewards,
 };
 
+/// Borrows against `owner`'s collateral. The signer is either the owner or a delegate
+/// holding an allowance for `borrow_mint`; the tokens go to the signer either way.
+/// The bank's origination fee is added to the debt, and `host_fee_receiver`, when passed,
+/// is paid the bank's host share of it. Positions with a referrer must pass `referral_fees`,
+/// which is credited the referral share of what the protocol keeps.
+/// `borrow_stable` uses the same accounts and locks the debt at the bank's stable rate.
+/// `borrow_native` pays the wrapped SOL bank out in lamports, closing `wsol_account` once the
+/// tokens land in it.
+/// `other_mint` and its bank and price are needed to value the rest of the position.
 #[derive(Accounts)]
 pub struct Borrow<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
 
+    /// CHECK: only used to derive the position being borrowed against
+    pub owner: UncheckedAccount<'info>,
+
     pub borrow_mint: InterfaceAccount<'info, Mint>,
 
+    pub other_mint: InterfaceAccount<'info, Mint>,
+
     #[account(
         mut,
-        seeds = [borrow_mint.key().as_ref()],
+        seeds = [b"bank", borrow_mint.key().as_ref()],
         bump
     )]
     pub borrow_bank: Account<'info, Bank>,
 
+    #[account(
+        mut,
+        seeds = [b"bank", other_mint.key().as_ref()],
+        bump
+    )]
+    pub other_bank: Account<'info, Bank>,
+
     #[account(
         mut,
         seeds = [b"treasury",borrow_mint.key().as_ref()],
@@ -36,11 +61,25 @@ pub struct Borrow<'info> {
 
     #[account(
         mut,
-        seeds = [signer.key().as_ref()],
+        seeds = [b"user", owner.key().as_ref(), user.index.to_le_bytes().as_ref()],
         bump
     )]
     pub user: Account<'info, User>,
 
+    #[account(
+        mut,
+        seeds = [
+            b"delegation",
+            owner.key().as_ref(),
+            signer.key().as_ref(),
+            borrow_mint.key().as_ref(),
+            user.index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
+
+    /// Required by `borrow`.
     #[account(
         init_if_needed,
         payer = signer,
@@ -48,66 +87,129 @@ pub struct Borrow<'info> {
         associated_token::mint = borrow_mint,
         associated_token::token_program = token_program
     )]
-    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required by `borrow_native`: a wrapped SOL account that lives only for the instruction,
+    /// so the signer's own token account is neither needed nor closed.
+    #[account(
+        init,
+        payer = signer,
+        seeds = [b"wsol", signer.key().as_ref()],
+        bump,
+        token::mint = borrow_mint,
+        token::authority = signer,
+        token::token_program = token_program
+    )]
+    pub wsol_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = borrow_mint,
+        token::token_program = token_program
+    )]
+    pub host_fee_receiver: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + ReferralFees::INIT_SPACE,
+        seeds = [b"referral", user.referrer.as_ref(), borrow_mint.key().as_ref()],
+        bump
+    )]
+    pub referral_fees: Option<Account<'info, ReferralFees>>,
 
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 
     pub price_update: Account<'info, PriceUpdateV2>,
-}
 
-pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
-    let borrow_bank = &mut ctx.accounts.borrow_bank;
-    let user = &mut ctx.accounts.user;
+    pub other_price_update: Account<'info, PriceUpdateV2>,
+
+    /// Required while the bank rewards borrows.
+    #[account(
+        mut,
+        seeds = [b"rewards", borrow_bank.key().as_ref(), [RewardSide::Borrow as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Option<Account<'info, RewardPool>>,
 
-    let price_update = &mut ctx.accounts.price_update;
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            borrow_bank.key().as_ref(),
+            [RewardSide::Borrow as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub reward_position: Option<Account<'info, RewardPosition>>,
+}
 
-    let total_collateral = match ctx.accounts.borrow_mint.key() {
-        key if key == user.usdc_address => {
-            let sol_fee_id = get_feed_id_from_hex(constants::SOL_USD_FEED_ID)?;
-            let sol_price = price_update.get_price_no_older_than(
-                &Clock::get()?,
-                constants::MAX_AGE,
-                &sol_fee_id,
-            )?;
-            let new_value = caculate_accrued_interest(
-                user.deposited_sol,
-                borrow_bank.instrest_rate,
-                user.last_updated,
-            )?;
-            sol_price.price as u64 * new_value
-        }
-        _ => {
-            let usdc_fee_id = get_feed_id_from_hex(constants::USDC_USD_FEED_ID)?;
-            let usdc_price = price_update.get_price_no_older_than(
-                &Clock::get()?,
-                constants::MAX_AGE,
-                &usdc_fee_id,
-            )?;
-            let new_value = caculate_accrued_interest(
-                user.deposited_usdc,
-                borrow_bank.instrest_rate,
-                user.last_updated,
-            )?;
-            usdc_price.price as u64 * new_value
-        }
-    };
+pub fn process_borrow(ctx: Context<Borrow>, amount: u64, stable: bool, native: bool) -> Result<()> {
+    if ctx.accounts.borrow_bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
+    require!(
+        !stable || ctx.accounts.borrow_bank.stable_borrow_enabled,
+        ErrorCode::StableBorrowDisabled
+    );
 
-    let borrowable_amount = total_collateral
-        .checked_mul(borrow_bank.liquidation_threshold)
-        .unwrap();
+    let fee = math::bps_of(amount, ctx.accounts.borrow_bank.borrow_fee_bps)?;
+    let host_fee = match ctx.accounts.host_fee_receiver {
+        Some(_) => math::percent_of(fee, ctx.accounts.borrow_bank.host_fee_percent)?,
+        None => 0,
+    };
+    let debt = amount + fee;
+    require!(
+        ctx.accounts.referral_fees.is_some() == (ctx.accounts.user.referrer != Pubkey::default()),
+        ErrorCode::ReferralAccountMismatch
+    );
 
-    if borrowable_amount < amount {
-        return Err(ErrorCode::OverBorrowableAmount.into());
+    if ctx.accounts.signer.key() != ctx.accounts.owner.key() {
+        let delegation = ctx
+            .accounts
+            .delegation
+            .as_mut()
+            .ok_or(ErrorCode::NotDelegated)?;
+        delegation.allowance = delegation
+            .allowance
+            .checked_sub(debt)
+            .ok_or(ErrorCode::InsufficientAllowance)?;
     }
 
+    let now = Clock::get()?.unix_timestamp;
+    let borrow_bank = &mut ctx.accounts.borrow_bank;
+    borrow_bank.accrue_interest(now)?;
+    let user = &mut ctx.accounts.user;
+    let (_, shares) = user.shares(borrow_bank)?;
+    settle_rewards(
+        borrow_bank.borrow_rewards,
+        ctx.accounts.reward_pool.as_deref_mut(),
+        ctx.accounts.reward_position.as_deref_mut(),
+        user.key(),
+        shares,
+        borrow_bank.total_borrowed_shares,
+        now,
+    )?;
+
+    borrow_bank.record_outflow(amount + host_fee, now)?;
+
+    let token_account = payment_account(
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.wsol_account,
+        native,
+    )?;
     msg!("transfer from borrow_bank_token_account to user_token_account");
+    let balance_before = ctx.accounts.borrow_bank_token_account.amount;
     let transfer_cpi_account = token_interface::TransferChecked {
-        from: borrow_bank.to_account_info(),
+        from: ctx.accounts.borrow_bank_token_account.to_account_info(),
         mint: ctx.accounts.borrow_mint.to_account_info(),
-        to: ctx.accounts.user_token_account.to_account_info(),
-        authority: borrow_bank.to_account_info(),
+        to: token_account.to_account_info(),
+        authority: ctx.accounts.borrow_bank_token_account.to_account_info(),
     };
 
     let mint_key = ctx.accounts.borrow_mint.key();
@@ -125,37 +227,110 @@ pub fn process_borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
 
     token_interface::transfer_checked(cip_ctx, amount, ctx.accounts.borrow_mint.decimals)?;
 
-    if borrow_bank.total_borrowed == 0 {
-        borrow_bank.total_borrowed = amount;
-        borrow_bank.total_borrowed_shares = amount;
+    if let Some(host_fee_receiver) = &ctx.accounts.host_fee_receiver {
+        if host_fee > 0 {
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::TransferChecked {
+                    from: ctx.accounts.borrow_bank_token_account.to_account_info(),
+                    mint: ctx.accounts.borrow_mint.to_account_info(),
+                    to: host_fee_receiver.to_account_info(),
+                    authority: ctx.accounts.borrow_bank_token_account.to_account_info(),
+                },
+                signer_seeds,
+            );
+            token_interface::transfer_checked(
+                cpi_ctx,
+                host_fee,
+                ctx.accounts.borrow_mint.decimals,
+            )?;
+        }
     }
 
-    let borrow_radio = amount.checked_div(borrow_bank.total_borrowed).unwrap();
-    let user_shares = borrow_bank
-        .total_borrowed_shares
-        .checked_mul(borrow_radio)
-        .unwrap();
-
-    match ctx.accounts.borrow_mint.key() {
-        key if key == ctx.accounts.borrow_mint.key() => {
-            user.borrowed_usdc += amount;
-            user.borrowed_usdc_shares += user_shares;
-        }
-        _ => {
-            user.borrowed_sol += amount;
-            user.borrowed_sol_shares += user_shares;
-        }
+    // the borrower bears any Token-2022 transfer fee; the vault releases exactly the payouts
+    ctx.accounts.borrow_bank_token_account.reload()?;
+    require_eq!(
+        balance_before - ctx.accounts.borrow_bank_token_account.amount,
+        amount + host_fee,
+        ErrorCode::TransferAmountMismatch
+    );
+    if native {
+        unwrap_sol(
+            &ctx.accounts.signer,
+            &ctx.accounts.borrow_mint,
+            token_account,
+            &ctx.accounts.token_program,
+        )?;
     }
 
-    user.last_updated_borrowed = Clock::get()?.unix_timestamp;
+    // the fee is owed like the rest of the debt; what the host did not take is the protocol's
+    book_borrow(
+        borrow_bank,
+        user,
+        ctx.accounts.referral_fees.as_deref_mut(),
+        debt,
+        fee - host_fee,
+        stable,
+        now,
+    )?;
 
-    Ok(())
+    // the new debt is booked, so the limit covers it along with everything already owed
+    let is_usdc = user.is_usdc(borrow_bank)?;
+    let other_bank = &mut ctx.accounts.other_bank;
+    other_bank.accrue_interest(now)?;
+    let borrow_unit_value = unit_value(
+        &ctx.accounts.price_update,
+        &ctx.accounts.borrow_mint,
+        is_usdc,
+    )?;
+    let other_unit_value = unit_value(
+        &ctx.accounts.other_price_update,
+        &ctx.accounts.other_mint,
+        !is_usdc,
+    )?;
+    user.check_borrow_limit([
+        (&**borrow_bank, borrow_unit_value),
+        (&**other_bank, other_unit_value),
+    ])
 }
 
-fn caculate_accrued_interest(deposited: u64, interest_rate: f64, last_updated: i64) -> Result<u64> {
-    let current_time = Clock::get()?.unix_timestamp;
-    let time_diff = current_time - last_updated;
+/// Books `debt`, fee included, against the position: variable, or locked at the bank's stable
+/// rate with `stable`. Of the fee, `protocol_fee` is kept for the protocol less the referrer's
+/// cut, which is credited to `referral_fees`. The bank's interest and the position's rewards
+/// must be settled first; health is left to the caller.
+pub(crate) fn book_borrow(
+    bank: &mut Bank,
+    user: &mut User,
+    referral_fees: Option<&mut ReferralFees>,
+    debt: u64,
+    protocol_fee: u64,
+    stable: bool,
+    now: i64,
+) -> Result<()> {
+    let referral_fee = match referral_fees {
+        Some(referral_fees) => {
+            let referral_fee = math::percent_of(protocol_fee, bank.referral_fee_percent)?;
+            referral_fees.referrer = user.referrer;
+            referral_fees.mint = bank.mint_address;
+            referral_fees.owed += referral_fee;
+            bank.referral_fees_owed += referral_fee;
+            referral_fee
+        }
+        None => 0,
+    };
+    bank.protocol_fees += protocol_fee - referral_fee;
+
+    if stable {
+        user.add_stable_debt(bank, debt, now)?;
+    } else {
+        // debt shares round up so the protocol never under-records what is owed
+        let user_shares =
+            math::amount_to_shares(debt, bank.total_borrowed, bank.total_borrowed_shares, true)?;
+        user.add_borrow(bank, debt, user_shares)?;
+        bank.total_borrowed += debt;
+        bank.total_borrowed_shares += user_shares;
+    }
 
-    let new_value = (deposited as f64 * E.powf(interest_rate * time_diff as f64)) as u64;
-    Ok(new_value)
+    user.last_updated_borrowed = now;
+    Ok(())
 }
@@ -0,0 +1,74 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+use crate::state::{Delegation, User};
+
+#[derive(Accounts)]
+pub struct ApproveDelegation<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// CHECK: only recorded as the wallet allowed to borrow
+    pub delegate: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"user", owner.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + Delegation::INIT_SPACE,
+        seeds = [
+            b"delegation",
+            owner.key().as_ref(),
+            delegate.key().as_ref(),
+            mint.key().as_ref(),
+            user.index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub delegation: Account<'info, Delegation>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegation<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [
+            b"delegation",
+            owner.key().as_ref(),
+            delegation.delegate.as_ref(),
+            delegation.mint.as_ref(),
+            delegation.index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub delegation: Account<'info, Delegation>,
+}
+
+/// Sets the delegate's remaining allowance on the owner's sub-account `user`, replacing
+/// whatever was left of the last one.
+pub fn process_approve_delegation(ctx: Context<ApproveDelegation>, allowance: u64) -> Result<()> {
+    let delegation = &mut ctx.accounts.delegation;
+    delegation.owner = ctx.accounts.owner.key();
+    delegation.index = ctx.accounts.user.index;
+    delegation.delegate = ctx.accounts.delegate.key();
+    delegation.mint = ctx.accounts.mint.key();
+    delegation.allowance = allowance;
+    Ok(())
+}
+
+pub fn process_revoke_delegation(_ctx: Context<RevokeDelegation>) -> Result<()> {
+    Ok(())
+}
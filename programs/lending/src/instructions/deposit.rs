@@ -4,8 +4,19 @@ use anchor_spl::{
     token_interface::{self, Mint, TokenAccount, TokenInterface},
 };
 
-use crate::state::{Bank, User};
+use crate::{
+    error::ErrorCode,
+    math,
+    state::{Bank, RewardPool, RewardPosition, RewardSide, User},
+};
+
+use super::{
+    native::{payment_account, unwrap_sol, wrap_sol},
+    rewards::settle_rewards,
+};
 
+/// Deposits from the signer's token account. `deposit_native` takes lamports instead, wrapping
+/// them into `wsol_account` and closing it once the deposit has moved out.
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     #[account(mut)]
@@ -15,7 +26,7 @@ pub struct Deposit<'info> {
 
     #[account(
         mut,
-        seeds = [mint.key().as_ref()],
+        seeds = [b"bank", mint.key().as_ref()],
         bump
     )]
     pub bank: Account<'info, Bank>,
@@ -29,32 +40,88 @@ pub struct Deposit<'info> {
 
     #[account(
         mut,
-        seeds = [signer.key().as_ref()],
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
         bump
     )]
     pub user: Account<'info, User>,
 
+    /// Required by `deposit`.
     #[account(
         mut,
         associated_token::authority = signer,
         associated_token::mint = mint,
         associated_token::token_program = token_program
     )]
-    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required by `deposit_native`: a wrapped SOL account that lives only for the instruction,
+    /// so the signer's own token account is neither needed nor closed.
+    #[account(
+        init,
+        payer = signer,
+        seeds = [b"wsol", signer.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = signer,
+        token::token_program = token_program
+    )]
+    pub wsol_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
     pub token_program: Interface<'info, TokenInterface>,
 
     pub system_program: Program<'info, System>,
 
     pub associated_token_program: Program<'info, AssociatedToken>,
+
+    /// Required while the bank rewards deposits.
+    #[account(
+        mut,
+        seeds = [b"rewards", bank.key().as_ref(), [RewardSide::Deposit as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub reward_position: Option<Account<'info, RewardPosition>>,
 }
 
-pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+pub fn process_deposit(ctx: Context<Deposit>, amount: u64, native: bool) -> Result<()> {
+    if ctx.accounts.bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
+    let token_account = payment_account(
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.wsol_account,
+        native,
+    )?;
+    if native {
+        wrap_sol(
+            &ctx.accounts.signer,
+            &ctx.accounts.mint,
+            token_account,
+            amount,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+        )?;
+    }
+
     msg!("Transfer from userTokenAccount to bankTokenAccount");
+    let balance_before = ctx.accounts.bank_token_account.amount;
     let transfer_cpi_account = token_interface::TransferChecked {
-        from: ctx.accounts.user_token_account.to_account_info(),
+        from: token_account.to_account_info(),
         mint: ctx.accounts.mint.to_account_info(),
-        to: ctx.accounts.user_token_account.to_account_info(),
+        to: ctx.accounts.bank_token_account.to_account_info(),
         authority: ctx.accounts.signer.to_account_info(),
     };
 
@@ -65,36 +132,54 @@ pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
 
     token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
 
+    // a Token-2022 transfer fee is withheld from what arrives, so credit only the vault's gain
+    ctx.accounts.bank_token_account.reload()?;
+    let amount = ctx.accounts.bank_token_account.amount - balance_before;
+    if native {
+        unwrap_sol(
+            &ctx.accounts.signer,
+            &ctx.accounts.mint,
+            token_account,
+            &ctx.accounts.token_program,
+        )?;
+    }
+
+    let now = Clock::get()?.unix_timestamp;
     let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(now)?;
+
+    let (shares, _) = ctx.accounts.user.shares(bank)?;
+    settle_rewards(
+        bank.deposit_rewards,
+        ctx.accounts.reward_pool.as_deref_mut(),
+        ctx.accounts.reward_position.as_deref_mut(),
+        ctx.accounts.user.key(),
+        shares,
+        bank.total_deposit_shares,
+        now,
+    )?;
+
+    book_deposit(bank, &mut ctx.accounts.user, amount, now)
+}
 
-    if bank.total_deposits == 0 {
-        bank.total_deposits = amount;
-        bank.total_deposit_shares = amount;
-    }
+/// Credits `amount`, already in the vault, to the position. The bank's interest and the
+/// position's rewards must be settled first.
+pub(crate) fn book_deposit(bank: &mut Bank, user: &mut User, amount: u64, now: i64) -> Result<()> {
+    bank.record_inflow(amount, now)?;
 
-    let deposit_radio = amount.checked_add(bank.total_deposits).unwrap();
-    let user_shares = bank
-        .total_deposit_shares
-        .checked_mul(deposit_radio)
-        .unwrap();
-
-    let user = &mut ctx.accounts.user;
-
-    match ctx.accounts.mint.key() {
-        key if key == user.usdc_address => {
-            user.deposited_usdc = amount;
-            user.deposited_usdc_shares = user_shares;
-        }
-        _ => {
-            user.deposited_sol = amount;
-            user.deposited_sol_shares = user_shares;
-        }
+    // shares round down so they are never worth more than what came in
+    let user_shares =
+        math::amount_to_shares(amount, bank.total_deposits, bank.total_deposit_shares, false)?;
+    if user_shares == 0 {
+        return Err(ErrorCode::AmountTooSmall.into());
     }
 
+    user.add_deposit(bank, amount, user_shares)?;
+
     bank.total_deposits += amount;
     bank.total_deposit_shares += user_shares;
 
-    user.last_updated = Clock::get()?.unix_timestamp;
+    user.last_updated = now;
 
     Ok(())
 }
@@ -0,0 +1,342 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
+
+use crate::{error::ErrorCode, math, state::*};
+
+use super::{
+    borrow::book_borrow,
+    deposit::book_deposit,
+    price::unit_value,
+    repay::{book_repayment, debt_owed},
+    rewards::settle_rewards,
+    swap::swap_and_measure,
+    vault::{pay_into_vault, pay_out_of_vault},
+    withdraw::book_withdrawal,
+};
+
+/// A leveraged position of the signer's: `collateral_mint` deposited against `debt_mint`
+/// borrowed. `open_leverage` flash-borrows the debt asset out of its vault, swaps it into
+/// collateral and deposits it along with the signer's own funds; `close_leverage` flash-
+/// withdraws collateral, swaps it back and repays. Either way the swap runs through
+/// `swap_program` using the remaining accounts, and health is only checked against `max_ltv`
+/// once everything has landed. `referral_fees` is only used when opening.
+#[derive(Accounts)]
+pub struct Leverage<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    pub debt_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", collateral_mint.key().as_ref()],
+        bump
+    )]
+    pub collateral_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", debt_mint.key().as_ref()],
+        bump
+    )]
+    pub debt_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",collateral_mint.key().as_ref()],
+        bump
+    )]
+    pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",debt_mint.key().as_ref()],
+        bump
+    )]
+    pub debt_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = collateral_mint,
+        associated_token::token_program = token_program
+    )]
+    pub collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = debt_mint,
+        associated_token::token_program = token_program
+    )]
+    pub debt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + ReferralFees::INIT_SPACE,
+        seeds = [b"referral", user.referrer.as_ref(), debt_mint.key().as_ref()],
+        bump
+    )]
+    pub referral_fees: Option<Account<'info, ReferralFees>>,
+
+    pub collateral_price_update: Account<'info, PriceUpdateV2>,
+
+    pub debt_price_update: Account<'info, PriceUpdateV2>,
+
+    /// CHECK: any program but this one; it only ever acts with the signer's authority
+    #[account(executable, constraint = swap_program.key() != crate::ID @ ErrorCode::InvalidSwap)]
+    pub swap_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// Required while the collateral bank rewards deposits.
+    #[account(
+        mut,
+        seeds = [b"rewards", collateral_bank.key().as_ref(), [RewardSide::Deposit as u8].as_ref()],
+        bump
+    )]
+    pub deposit_reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            collateral_bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub deposit_reward_position: Option<Account<'info, RewardPosition>>,
+
+    /// Required while the debt bank rewards borrows.
+    #[account(
+        mut,
+        seeds = [b"rewards", debt_bank.key().as_ref(), [RewardSide::Borrow as u8].as_ref()],
+        bump
+    )]
+    pub borrow_reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            debt_bank.key().as_ref(),
+            [RewardSide::Borrow as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub borrow_reward_position: Option<Account<'info, RewardPosition>>,
+}
+
+impl<'info> Leverage<'info> {
+    /// Checks the pair and brings both banks, the position and its rewards up to `now`.
+    fn prepare(&mut self, now: i64) -> Result<bool> {
+        if self.collateral_bank.paused || self.debt_bank.paused {
+            return Err(ErrorCode::BankPaused.into());
+        }
+        let collateral_is_usdc = self.user.is_usdc(&self.collateral_bank)?;
+        require!(
+            collateral_is_usdc != self.user.is_usdc(&self.debt_bank)?,
+            ErrorCode::InvalidSwap
+        );
+
+        self.collateral_bank.accrue_interest(now)?;
+        self.debt_bank.accrue_interest(now)?;
+        let user = &mut self.user;
+        user.accrue_stable(now)?;
+
+        let (deposit_shares, _) = user.shares(&self.collateral_bank)?;
+        settle_rewards(
+            self.collateral_bank.deposit_rewards,
+            self.deposit_reward_pool.as_deref_mut(),
+            self.deposit_reward_position.as_deref_mut(),
+            user.key(),
+            deposit_shares,
+            self.collateral_bank.total_deposit_shares,
+            now,
+        )?;
+        let (_, borrow_shares) = user.shares(&self.debt_bank)?;
+        settle_rewards(
+            self.debt_bank.borrow_rewards,
+            self.borrow_reward_pool.as_deref_mut(),
+            self.borrow_reward_position.as_deref_mut(),
+            user.key(),
+            borrow_shares,
+            self.debt_bank.total_borrowed_shares,
+            now,
+        )?;
+        Ok(collateral_is_usdc)
+    }
+
+    /// USD values of one base unit of the collateral and of the debt asset.
+    fn unit_values(&self, collateral_is_usdc: bool) -> Result<(f64, f64)> {
+        let collateral_unit_value = unit_value(
+            &self.collateral_price_update,
+            &self.collateral_mint,
+            collateral_is_usdc,
+        )?;
+        let debt_unit_value = unit_value(
+            &self.debt_price_update,
+            &self.debt_mint,
+            !collateral_is_usdc,
+        )?;
+        Ok((collateral_unit_value, debt_unit_value))
+    }
+
+    fn check_health(&self, collateral_is_usdc: bool) -> Result<()> {
+        let (collateral_unit_value, debt_unit_value) = self.unit_values(collateral_is_usdc)?;
+        self.user.check_borrow_limit([
+            (&*self.collateral_bank, collateral_unit_value),
+            (&*self.debt_bank, debt_unit_value),
+        ])
+    }
+}
+
+/// Deposits `amount` of the signer's own collateral together with whatever the swap makes of
+/// the flash-borrowed debt asset, for `leverage_bps / 10_000` times the exposure of `amount`
+/// alone. The borrow is `leverage - 1` times the value of `amount` at the oracle prices, and
+/// is what `swap_data` has to sell.
+pub fn process_open_leverage<'info>(
+    ctx: Context<'_, '_, 'info, 'info, Leverage<'info>>,
+    amount: u64,
+    leverage_bps: u64,
+    min_amount_out: u64,
+    swap_data: Vec<u8>,
+) -> Result<()> {
+    require!(
+        ctx.accounts.referral_fees.is_some() == (ctx.accounts.user.referrer != Pubkey::default()),
+        ErrorCode::ReferralAccountMismatch
+    );
+    let now = Clock::get()?.unix_timestamp;
+    let collateral_is_usdc = ctx.accounts.prepare(now)?;
+    let (collateral_unit_value, debt_unit_value) = ctx.accounts.unit_values(collateral_is_usdc)?;
+    let borrow_amount =
+        math::leverage_borrow_amount(amount, leverage_bps, collateral_unit_value, debt_unit_value)?;
+
+    // the flash borrow leaves the vault before the debt is recorded, and is backed by the
+    // collateral it buys by the time health is checked
+    let debt_bank = &mut ctx.accounts.debt_bank;
+    let fee = math::bps_of(borrow_amount, debt_bank.borrow_fee_bps)?;
+    let debt = borrow_amount + fee;
+    debt_bank.record_outflow(borrow_amount, now)?;
+    pay_out_of_vault(
+        &mut ctx.accounts.debt_bank_token_account,
+        ctx.bumps.debt_bank_token_account,
+        &ctx.accounts.debt_mint,
+        &ctx.accounts.debt_token_account,
+        &ctx.accounts.token_program,
+        borrow_amount,
+    )?;
+
+    let received = swap_and_measure(
+        &ctx.accounts.swap_program,
+        ctx.remaining_accounts,
+        swap_data,
+        &mut ctx.accounts.collateral_token_account,
+        min_amount_out,
+    )?;
+    let deposit = pay_into_vault(
+        &ctx.accounts.collateral_token_account,
+        &ctx.accounts.signer,
+        &ctx.accounts.collateral_mint,
+        &mut ctx.accounts.collateral_bank_token_account,
+        &ctx.accounts.token_program,
+        amount + received,
+    )?;
+    let user = &mut ctx.accounts.user;
+    book_deposit(&mut ctx.accounts.collateral_bank, user, deposit, now)?;
+    book_borrow(
+        &mut ctx.accounts.debt_bank,
+        user,
+        ctx.accounts.referral_fees.as_deref_mut(),
+        debt,
+        fee,
+        false,
+        now,
+    )?;
+
+    ctx.accounts.check_health(collateral_is_usdc)
+}
+
+/// Flash-withdraws `amount` of collateral, swaps it and repays debt with the proceeds, up to
+/// all of it; anything left over stays with the signer. The rest of the collateral can be
+/// withdrawn in the same transaction once the debt is gone.
+pub fn process_close_leverage<'info>(
+    ctx: Context<'_, '_, 'info, 'info, Leverage<'info>>,
+    amount: u64,
+    min_amount_out: u64,
+    swap_data: Vec<u8>,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let collateral_is_usdc = ctx.accounts.prepare(now)?;
+
+    let amount = book_withdrawal(
+        &mut ctx.accounts.collateral_bank,
+        &mut ctx.accounts.user,
+        amount,
+        now,
+    )?;
+
+    pay_out_of_vault(
+        &mut ctx.accounts.collateral_bank_token_account,
+        ctx.bumps.collateral_bank_token_account,
+        &ctx.accounts.collateral_mint,
+        &ctx.accounts.collateral_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    let received = swap_and_measure(
+        &ctx.accounts.swap_program,
+        ctx.remaining_accounts,
+        swap_data,
+        &mut ctx.accounts.debt_token_account,
+        min_amount_out,
+    )?;
+    let repay = received.min(debt_owed(
+        &ctx.accounts.debt_bank,
+        &ctx.accounts.user,
+        false,
+    )?);
+    let repaid = pay_into_vault(
+        &ctx.accounts.debt_token_account,
+        &ctx.accounts.signer,
+        &ctx.accounts.debt_mint,
+        &mut ctx.accounts.debt_bank_token_account,
+        &ctx.accounts.token_program,
+        repay,
+    )?;
+    book_repayment(
+        &mut ctx.accounts.debt_bank,
+        &mut ctx.accounts.user,
+        repaid,
+        false,
+        now,
+    )?;
+
+    ctx.accounts.check_health(collateral_is_usdc)
+}
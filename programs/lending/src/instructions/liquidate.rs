@@ -1,16 +1,20 @@
-use std::f64::consts::E;
-
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token_interface::{self, Mint, TokenAccount, TokenInterface},
+    token_interface::{Mint, TokenAccount, TokenInterface},
 };
-use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
 
 use crate::{
-    constants::{self, SOL_USD_FEED_ID, USDC_USD_FEED_ID},
     error::ErrorCode,
-    state::{Bank, User},
+    math, pda,
+    state::{Bank, TermLoan, TermPool, User},
+};
+
+use super::{
+    price::unit_value,
+    term::book_term_repayment,
+    vault::{pay_into_vault, pay_out_of_vault},
 };
 
 #[derive(Accounts)]
@@ -18,7 +22,9 @@ pub struct Liquidate<'info> {
     #[account(mut)]
     pub liquidator: Signer<'info>,
 
-    pub price_update: Account<'info, PriceUpdateV2>,
+    pub collateral_price_update: Account<'info, PriceUpdateV2>,
+
+    pub borrowed_price_update: Account<'info, PriceUpdateV2>,
 
     pub collateral_mint: InterfaceAccount<'info, Mint>,
 
@@ -26,14 +32,14 @@ pub struct Liquidate<'info> {
 
     #[account(
         mut,
-        seeds = [collateral_mint.key().as_ref()],
+        seeds = [b"bank", collateral_mint.key().as_ref()],
         bump
     )]
     pub collateral_bank: Account<'info, Bank>,
 
     #[account(
         mut,
-        seeds = [borrowed_mint.key().as_ref()],
+        seeds = [b"bank", borrowed_mint.key().as_ref()],
         bump
     )]
     pub borrowed_bank: Account<'info, Bank>,
@@ -54,7 +60,7 @@ pub struct Liquidate<'info> {
 
     #[account(
         mut,
-        seeds = [liquidator.key().as_ref()],
+        seeds = [b"user", user_account.owner.as_ref(), user_account.index.to_le_bytes().as_ref()],
         bump
     )]
     pub user_account: Account<'info, User>,
@@ -77,6 +83,18 @@ pub struct Liquidate<'info> {
     )]
     pub liquidator_borrowed_token_account: InterfaceAccount<'info, TokenAccount>,
 
+    /// A term loan of the position in the borrowed bank, repaid into its pool's vault instead of
+    /// the variable and stable debt. Past maturity it may be liquidated even while the position
+    /// is healthy. The pool, loan and vault are passed together or not at all.
+    #[account(mut)]
+    pub term_pool: Option<Account<'info, TermPool>>,
+
+    #[account(mut)]
+    pub term_loan: Option<Account<'info, TermLoan>>,
+
+    #[account(mut)]
+    pub term_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
@@ -88,102 +106,153 @@ pub fn process_liquidate(ctx: Context<Liquidate>) -> Result<()> {
 
     let user = &mut ctx.accounts.user_account;
 
-    let sol_fee_id = get_feed_id_from_hex(SOL_USD_FEED_ID)?;
-    let usdc_fee_id = get_feed_id_from_hex(USDC_USD_FEED_ID)?;
-
-    let price_update = &mut ctx.accounts.price_update;
-    let sol_price =
-        price_update.get_price_no_older_than(&Clock::get()?, constants::MAX_AGE, &sol_fee_id)?;
-    let usdc_price =
-        price_update.get_price_no_older_than(&Clock::get()?, constants::MAX_AGE, &usdc_fee_id)?;
-
-    let total_collateral: u64;
-    let total_borrowed: u64;
-
-    match ctx.accounts.collateral_mint.key() {
-        key if key == user.usdc_address => {
-            let new_usdc = caculate_accrued_interest(
-                user.deposited_usdc,
-                collateral_bank.instrest_rate,
-                user.last_updated,
-            )?;
-            total_collateral = new_usdc * usdc_price.price as u64;
-            let new_sol = caculate_accrued_interest(
-                user.borrowed_sol,
-                borrowed_bank.instrest_rate,
-                user.last_updated_borrowed,
-            )?;
-            total_borrowed = new_sol * sol_price.price as u64;
+    let collateral_is_usdc = user.is_usdc(collateral_bank)?;
+    require!(
+        collateral_is_usdc != user.is_usdc(borrowed_bank)?,
+        ErrorCode::UnknownBank
+    );
+
+    // a PriceUpdateV2 account only carries a single feed, so each side has its own account
+    let collateral_unit_value = unit_value(
+        &ctx.accounts.collateral_price_update,
+        &ctx.accounts.collateral_mint,
+        collateral_is_usdc,
+    )?;
+    let borrowed_unit_value = unit_value(
+        &ctx.accounts.borrowed_price_update,
+        &ctx.accounts.borrowed_mint,
+        !collateral_is_usdc,
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    collateral_bank.accrue_interest(now)?;
+    borrowed_bank.accrue_interest(now)?;
+    user.accrue_stable(now)?;
+
+    let (collateral_shares, _) = user.shares(collateral_bank)?;
+    let (_, borrowed_shares) = user.shares(borrowed_bank)?;
+
+    let total_collateral = math::shares_to_amount(
+        collateral_shares,
+        collateral_bank.total_deposits,
+        collateral_bank.total_deposit_shares,
+        false,
+    )?;
+    let variable_borrowed = math::shares_to_amount(
+        borrowed_shares,
+        borrowed_bank.total_borrowed,
+        borrowed_bank.total_borrowed_shares,
+        true,
+    )?;
+    let total_borrowed = variable_borrowed + user.stable_debt(borrowed_bank)?.0;
+
+    let term = match (
+        ctx.accounts.term_pool.as_mut(),
+        ctx.accounts.term_loan.as_mut(),
+        ctx.accounts.term_vault.as_mut(),
+    ) {
+        (Some(pool), Some(loan), Some(vault)) => {
+            require_keys_eq!(pool.bank, borrowed_bank.key(), ErrorCode::TermLoanMismatch);
+            require_keys_eq!(
+                loan.key(),
+                pda::term_loan_address(&pool.key(), &user.key()),
+                ErrorCode::TermLoanMismatch
+            );
+            require_keys_eq!(
+                vault.key(),
+                pda::term_vault_address(&pool.key()),
+                ErrorCode::TermLoanMismatch
+            );
+            Some((pool, loan, vault))
         }
-        _ => {
-            let new_sol = caculate_accrued_interest(
-                user.deposited_sol,
-                collateral_bank.instrest_rate,
-                user.last_updated,
-            )?;
-            total_collateral = new_sol * sol_price.price as u64;
-            let new_usdc = caculate_accrued_interest(
-                user.borrowed_usdc,
-                borrowed_bank.instrest_rate,
-                user.last_updated_borrowed,
-            )?;
-            total_borrowed = new_usdc * usdc_price.price as u64;
-        }
-    }
-
-    let health_factor = total_collateral as f64 * collateral_bank.liquidation_threshold as f64
-        / total_borrowed as f64;
-
-    if health_factor >= 1.0 {
+        (None, None, None) => None,
+        _ => return err!(ErrorCode::TermLoanMismatch),
+    };
+    let overdue = term
+        .as_ref()
+        .is_some_and(|(pool, loan, _)| now >= pool.maturity && loan.owed > 0);
+
+    // health counts every deposit and debt in both banks, term debt included, as borrowing
+    // does; a loan past its maturity may be liquidated regardless
+    let health_factor = user.health_factor([
+        (&**collateral_bank, collateral_unit_value),
+        (&**borrowed_bank, borrowed_unit_value),
+    ])?;
+
+    if health_factor >= 1.0 && !overdue {
         return Err(ErrorCode::NotUnderCollateralized.into());
     }
 
-    msg!("transfer from liquidator borrowed token Account to borrowed bank Account");
-    let transfer_to_bank = token_interface::TransferChecked {
-        from: ctx.accounts.liquidator_borrowed_token_account.to_account_info(),
-        mint: ctx.accounts.borrowed_mint.to_account_info(),
-        to: ctx.accounts.borrowed_bank_token_account.to_account_info(),
-        authority: ctx.accounts.liquidator.to_account_info(),
+    let close_factor = borrowed_bank.liquidation_close_factor;
+    let repay_amount = match &term {
+        Some((_, loan, _)) => math::percent_of(loan.owed, close_factor)?,
+        None => math::percent_of(total_borrowed, close_factor)?,
     };
 
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-
-    let cpi_context = CpiContext::new(cpi_program.clone(), transfer_to_bank);
-
-    let liquidation_amount = total_borrowed.checked_mul(borrowed_bank.liquidation_close_factor).unwrap();
-
-    token_interface::transfer_checked(cpi_context, liquidation_amount, ctx.accounts.borrowed_mint.decimals)?;
-
-
-    msg!("transfer from liquidator borrowed token Account to borrowed bank Account");
-    let liquidator_amount = liquidation_amount * collateral_bank.liquidation_bonus + liquidation_amount;
-
-    let transfer_to_liquidator = token_interface::TransferChecked{
-        from: ctx.accounts.collateral_bank_token_account.to_account_info(),
-        mint: ctx.accounts.collateral_mint.to_account_info(),
-        to: ctx.accounts.liquidator_collateral_token_account.to_account_info(),
-        authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+    // debt is cleared, and collateral paid out, only for what reaches the vault after any
+    // Token-2022 transfer fee
+    let vault = match &mut term {
+        Some((_, _, vault)) => &mut **vault,
+        None => &mut ctx.accounts.borrowed_bank_token_account,
     };
+    let liquidation_amount = pay_into_vault(
+        &ctx.accounts.liquidator_borrowed_token_account,
+        &ctx.accounts.liquidator,
+        &ctx.accounts.borrowed_mint,
+        vault,
+        &ctx.accounts.token_program,
+        repay_amount,
+    )?;
+    let liquidator_amount = math::seize_amount(
+        liquidation_amount,
+        total_collateral,
+        collateral_bank.liquidation_bonus,
+        borrowed_unit_value,
+        collateral_unit_value,
+    )?;
+
+    if let Some((pool, loan, _)) = term {
+        book_term_repayment(borrowed_bank, user, pool, loan, liquidation_amount)?;
+    } else {
+        // variable debt is cleared first, then stable
+        let variable_amount = liquidation_amount.min(variable_borrowed);
+        user.remove_stable_debt(borrowed_bank, liquidation_amount - variable_amount)?;
+        let borrowed_shares_to_remove = if variable_amount == variable_borrowed {
+            borrowed_shares
+        } else {
+            math::amount_to_shares(
+                variable_amount,
+                borrowed_bank.total_borrowed,
+                borrowed_bank.total_borrowed_shares,
+                false,
+            )?
+        };
+        user.remove_borrow(borrowed_bank, variable_amount, borrowed_shares_to_remove)?;
+        borrowed_bank.total_borrowed = borrowed_bank.total_borrowed.saturating_sub(variable_amount);
+        borrowed_bank.total_borrowed_shares -= borrowed_shares_to_remove;
+    }
 
-    let mint_key = ctx.accounts.collateral_mint.key();
-    let signer_seeds : &[&[&[u8]]] = &[&[
-        b"treasury",
-        mint_key.as_ref(),
-        &[ctx.bumps.collateral_bank_token_account]
-    ]];
-
-    let cpi_ctx_to_liquidator = CpiContext::new_with_signer(cpi_program.clone(), transfer_to_liquidator, signer_seeds);
-
-    token_interface::transfer_checked(cpi_ctx_to_liquidator, liquidator_amount, ctx.accounts.collateral_mint.decimals)?;
-
-
-    Ok(())
-}
-
-fn caculate_accrued_interest(deposited: u64, interest_rate: f64, last_updated: i64) -> Result<u64> {
-    let current_time = Clock::get()?.unix_timestamp;
-    let time_diff = current_time - last_updated;
-
-    let new_value = (deposited as f64 * E.powf(interest_rate * time_diff as f64)) as u64;
-    Ok(new_value)
+    let collateral_shares_to_remove = math::amount_to_shares(
+        liquidator_amount,
+        collateral_bank.total_deposits,
+        collateral_bank.total_deposit_shares,
+        true,
+    )?
+    .min(collateral_shares);
+    user.remove_deposit(
+        collateral_bank,
+        liquidator_amount,
+        collateral_shares_to_remove,
+    )?;
+    collateral_bank.total_deposits -= liquidator_amount;
+    collateral_bank.total_deposit_shares -= collateral_shares_to_remove;
+
+    pay_out_of_vault(
+        &mut ctx.accounts.collateral_bank_token_account,
+        ctx.bumps.collateral_bank_token_account,
+        &ctx.accounts.collateral_mint,
+        &ctx.accounts.liquidator_collateral_token_account,
+        &ctx.accounts.token_program,
+        liquidator_amount,
+    )
 }
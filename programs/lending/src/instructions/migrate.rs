@@ -0,0 +1,123 @@
+use anchor_lang::{prelude::*, system_program};
+use anchor_spl::token_interface::Mint;
+
+use crate::{
+    error::ErrorCode,
+    state::{Bank, User},
+};
+
+/// Moves a bank from its unprefixed `[mint]` address to `[b"bank", mint]`. The treasury
+/// was always namespaced, so the vault stays where it is.
+#[derive(Accounts)]
+pub struct MigrateBank<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: still in the layout it was created with, so it is decoded by hand
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [mint.key().as_ref()],
+        bump
+    )]
+    pub legacy_bank: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + Bank::INIT_SPACE,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Moves `owner`'s position from `[owner]` to sub-account 0 at `[b"user", owner, 0]`. Anyone
+/// may do it, paying for the new account and taking back the legacy one's rent, so positions
+/// whose owners never return can still be liquidated.
+#[derive(Accounts)]
+pub struct MigrateUser<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK: only used to derive the position being moved
+    pub owner: UncheckedAccount<'info>,
+
+    /// CHECK: still in the layout it was created with, so it is decoded by hand
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [owner.key().as_ref()],
+        bump
+    )]
+    pub legacy_user: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + User::INIT_SPACE,
+        seeds = [b"user", owner.key().as_ref(), 0u8.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    /// Mint of the SOL bank, which legacy positions did not record.
+    pub sol_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"bank", sol_mint.key().as_ref()],
+        bump
+    )]
+    pub sol_bank: Account<'info, Bank>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn process_migrate_bank(ctx: Context<MigrateBank>) -> Result<()> {
+    let legacy = ctx.accounts.legacy_bank.to_account_info();
+    let mut bank: Bank = decode_legacy(&legacy)?;
+    require_keys_eq!(
+        bank.authority,
+        ctx.accounts.signer.key(),
+        ErrorCode::Unauthorized
+    );
+    bank.upgrade();
+    ctx.accounts.bank.set_inner(bank);
+    close_legacy(&legacy, &ctx.accounts.signer)
+}
+
+pub fn process_migrate_user(ctx: Context<MigrateUser>) -> Result<()> {
+    let legacy = ctx.accounts.legacy_user.to_account_info();
+    let mut user: User = decode_legacy(&legacy)?;
+    // legacy positions predate sub-accounts, so the index decodes as 0 like the new seeds
+    user.upgrade();
+    let sol_mint = ctx.accounts.sol_mint.key();
+    require_keys_neq!(sol_mint, user.usdc_address, ErrorCode::InvalidConfig);
+    if user.sol_address == Pubkey::default() {
+        user.sol_address = sol_mint;
+    }
+    require_keys_eq!(user.sol_address, sol_mint, ErrorCode::InvalidConfig);
+    ctx.accounts.user.set_inner(user);
+    close_legacy(&legacy, &ctx.accounts.signer)
+}
+
+/// Decodes an account written in any older layout. Layouts only grow at the end, so the
+/// missing trailing fields decode as zero, as they would after `upgrade_account`.
+fn decode_legacy<T: AccountDeserialize + Space>(info: &AccountInfo) -> Result<T> {
+    let mut data = info.try_borrow_data()?.to_vec();
+    data.resize(data.len().max(8 + T::INIT_SPACE), 0);
+    T::try_deserialize(&mut &data[..])
+}
+
+fn close_legacy<'info>(legacy: &AccountInfo<'info>, signer: &Signer<'info>) -> Result<()> {
+    let lamports = legacy.lamports();
+    **legacy.try_borrow_mut_lamports()? = 0;
+    **signer.to_account_info().try_borrow_mut_lamports()? += lamports;
+    legacy.assign(&system_program::ID);
+    legacy.realloc(0, false)?;
+    Ok(())
+}
@@ -21,3 +21,53 @@ pub use repay::*;
 mod liquidate;
 
 pub use liquidate::*;
+
+mod delegation;
+
+pub use delegation::*;
+
+mod transfer_collateral;
+
+pub use transfer_collateral::*;
+
+mod migrate;
+
+pub use migrate::*;
+
+mod upgrade;
+
+pub use upgrade::*;
+
+mod timelock;
+
+pub use timelock::*;
+
+mod referral;
+
+pub use referral::*;
+
+mod rewards;
+
+pub use rewards::*;
+
+mod stable;
+
+pub use stable::*;
+
+mod term;
+
+pub use term::*;
+
+mod native;
+
+mod price;
+
+mod vault;
+
+mod swap;
+
+pub use swap::*;
+
+mod leverage;
+
+pub use leverage::*;
@@ -0,0 +1,69 @@
+use anchor_lang::{prelude::*, system_program};
+use anchor_spl::{
+    token::spl_token::native_mint,
+    token_interface::{self, Mint, TokenAccount, TokenInterface},
+};
+
+use crate::error::ErrorCode;
+
+/// The account tokens move through: the instruction's own wrapped SOL account on the native
+/// paths, the signer's token account otherwise.
+pub(crate) fn payment_account<'a, 'info>(
+    user_token_account: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+    wsol_account: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+    native: bool,
+) -> Result<&'a InterfaceAccount<'info, TokenAccount>> {
+    match (native, user_token_account, wsol_account) {
+        (true, _, Some(wsol_account)) => Ok(wsol_account),
+        (false, Some(user_token_account), None) => Ok(user_token_account),
+        _ => err!(ErrorCode::PaymentAccountMismatch),
+    }
+}
+
+/// Wraps `amount` of the signer's lamports into `token_account`, the instruction's wrapped SOL
+/// account, ahead of a transfer out of it.
+pub(crate) fn wrap_sol<'info>(
+    signer: &Signer<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_account: &InterfaceAccount<'info, TokenAccount>,
+    amount: u64,
+    token_program: &Interface<'info, TokenInterface>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    require_keys_eq!(mint.key(), native_mint::ID, ErrorCode::NotNativeMint);
+    system_program::transfer(
+        CpiContext::new(
+            system_program.to_account_info(),
+            system_program::Transfer {
+                from: signer.to_account_info(),
+                to: token_account.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+    token_interface::sync_native(CpiContext::new(
+        token_program.to_account_info(),
+        token_interface::SyncNative {
+            account: token_account.to_account_info(),
+        },
+    ))
+}
+
+/// Closes the instruction's wrapped SOL account, paying everything it holds back to the signer
+/// as lamports along with its rent.
+pub(crate) fn unwrap_sol<'info>(
+    signer: &Signer<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_account: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    require_keys_eq!(mint.key(), native_mint::ID, ErrorCode::NotNativeMint);
+    token_interface::close_account(CpiContext::new(
+        token_program.to_account_info(),
+        token_interface::CloseAccount {
+            account: token_account.to_account_info(),
+            destination: signer.to_account_info(),
+            authority: signer.to_account_info(),
+        },
+    ))
+}
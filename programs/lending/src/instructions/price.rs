@@ -0,0 +1,32 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+use pyth_solana_receiver_sdk::price_update::{get_feed_id_from_hex, PriceUpdateV2};
+
+use crate::{
+    constants::{self, SOL_USD_FEED_ID, USDC_USD_FEED_ID},
+    math,
+};
+
+/// USD value of one base unit of `mint`, read from the feed of the position's asset.
+pub(crate) fn unit_value(
+    price_update: &PriceUpdateV2,
+    mint: &InterfaceAccount<Mint>,
+    is_usdc: bool,
+) -> Result<f64> {
+    let feed_id = if is_usdc {
+        USDC_USD_FEED_ID
+    } else {
+        SOL_USD_FEED_ID
+    };
+    let price = price_update.get_price_no_older_than(
+        &Clock::get()?,
+        constants::MAX_AGE,
+        &get_feed_id_from_hex(feed_id)?,
+    )?;
+    Ok(math::usd_value(
+        1,
+        price.price,
+        price.exponent,
+        mint.decimals,
+    ))
+}
@@ -0,0 +1,74 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+
+use crate::state::{Bank, ReferralFees};
+
+/// Pays a referrer the borrow fees it has been credited in one mint.
+#[derive(Accounts)]
+pub struct ClaimReferralFees<'info> {
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"referral", signer.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub referral_fees: Account<'info, ReferralFees>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury", mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+    let referral_fees = &mut ctx.accounts.referral_fees;
+    // borrowed-out liquidity may leave the vault short for now; the rest stays owed
+    let amount = referral_fees
+        .owed
+        .min(ctx.accounts.bank_token_account.amount);
+    referral_fees.owed -= amount;
+    // fees credited before the bank tracked them were never counted
+    let bank = &mut ctx.accounts.bank;
+    bank.referral_fees_owed = bank.referral_fees_owed.saturating_sub(amount);
+
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"treasury",
+        mint_key.as_ref(),
+        &[ctx.bumps.bank_token_account],
+    ]];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: ctx.accounts.bank_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.bank_token_account.to_account_info(),
+        },
+        signer_seeds,
+    );
+
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)
+}
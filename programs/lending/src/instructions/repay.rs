@@ -1,26 +1,44 @@
-use std::f64::consts::E;
-
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
+    token_2022::spl_token_2022::{
+        self,
+        extension::{
+            transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions,
+        },
+    },
     token_interface::{self, Mint, TokenAccount, TokenInterface},
 };
 
 use crate::{
+    constants,
     error::ErrorCode,
-    state::{Bank, User},
+    math,
+    state::{Bank, RewardPool, RewardPosition, RewardSide, User},
+};
+
+use super::{
+    native::{payment_account, unwrap_sol, wrap_sol},
+    rewards::settle_rewards,
 };
 
+/// Anyone may repay a position; `signer` pays from their own token account and
+/// `owner` selects whose debt goes down. `repay_stable` uses the same accounts and pays
+/// down stable-rate debt instead of variable. `repay_native` wraps the signer's lamports into
+/// `wsol_account` instead and closes it afterwards.
 #[derive(Accounts)]
 pub struct Repay<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
 
+    /// CHECK: only used to derive the position being repaid
+    pub owner: UncheckedAccount<'info>,
+
     pub repay_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
         mut,
-        seeds = [repay_mint.key().as_ref()],
+        seeds = [b"bank", repay_mint.key().as_ref()],
         bump
     )]
     pub repay_bank: Account<'info, Bank>,
@@ -34,53 +52,114 @@ pub struct Repay<'info> {
 
     #[account(
         mut,
-        seeds = [signer.key().as_ref()],
+        seeds = [b"user", owner.key().as_ref(), user.index.to_le_bytes().as_ref()],
         bump
     )]
     pub user: Account<'info, User>,
 
+    /// Required by `repay`.
     #[account(
-        mut,
+        init_if_needed,
+        payer = signer,
         associated_token::authority = signer,
         associated_token::mint = repay_mint,
         associated_token::token_program = token_program
     )]
-    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required by `repay_native`: a wrapped SOL account that lives only for the instruction,
+    /// so the signer's own token account is neither needed nor closed.
+    #[account(
+        init,
+        payer = signer,
+        seeds = [b"wsol", signer.key().as_ref()],
+        bump,
+        token::mint = repay_mint,
+        token::authority = signer,
+        token::token_program = token_program
+    )]
+    pub wsol_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
-}
 
-pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
-    let user = &mut ctx.accounts.user;
-
-    let borrow_value = if ctx.accounts.repay_mint.key() == user.usdc_address {
-        user.borrowed_usdc
-    } else {
-        user.borrowed_sol
-    };
+    /// Required while the bank rewards borrows.
+    #[account(
+        mut,
+        seeds = [b"rewards", repay_bank.key().as_ref(), [RewardSide::Borrow as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Option<Account<'info, RewardPool>>,
 
-    let time_diff = -user.last_updated_borrowed - Clock::get()?.unix_timestamp;
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            repay_bank.key().as_ref(),
+            [RewardSide::Borrow as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub reward_position: Option<Account<'info, RewardPosition>>,
+}
 
+pub fn process_repay(ctx: Context<Repay>, amount: u64, stable: bool, native: bool) -> Result<()> {
     let bank = &mut ctx.accounts.repay_bank;
-    bank.total_borrowed =
-        (bank.total_borrowed as f64 * (E.powf(bank.instrest_rate * time_diff as f64))) as u64;
+    bank.accrue_interest(Clock::get()?.unix_timestamp)?;
 
-    let value_per_share = bank.total_borrowed as f64 / bank.total_borrowed_shares as f64;
+    let user = &mut ctx.accounts.user;
 
-    let user_value = borrow_value as f64 / value_per_share;
+    let (_, user_shares) = user.shares(bank)?;
+    settle_rewards(
+        bank.borrow_rewards,
+        ctx.accounts.reward_pool.as_deref_mut(),
+        ctx.accounts.reward_position.as_deref_mut(),
+        user.key(),
+        user_shares,
+        bank.total_borrowed_shares,
+        Clock::get()?.unix_timestamp,
+    )?;
 
-    if amount as f64 > user_value {
-        return Err(ErrorCode::OverRepay.into());
+    if stable {
+        user.accrue_stable(Clock::get()?.unix_timestamp)?;
+    }
+    let borrowed_value = debt_owed(bank, user, stable)?;
+
+    // repaying everything asks for the rounded-up debt, which clears every share below, plus
+    // whatever transfer fee the mint will withhold on the way to the vault
+    let repay_all = amount == constants::AMOUNT_ALL;
+    let amount = if repay_all {
+        with_transfer_fee(&ctx.accounts.repay_mint, borrowed_value)?
+    } else {
+        amount
+    };
+    let token_account = payment_account(
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.wsol_account,
+        native,
+    )?;
+    if native {
+        wrap_sol(
+            &ctx.accounts.signer,
+            &ctx.accounts.repay_mint,
+            token_account,
+            amount,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+        )?;
     }
 
     //transfer token
+    let balance_before = ctx.accounts.repay_bank_token_account.amount;
     let transfer_cpi_account = token_interface::TransferChecked {
-        from: ctx.accounts.user_token_account.to_account_info(),
+        from: token_account.to_account_info(),
         mint: ctx.accounts.repay_mint.to_account_info(),
         to: ctx.accounts.repay_bank_token_account.to_account_info(),
-        authority: ctx.accounts.repay_bank_token_account.to_account_info(),
+        authority: ctx.accounts.signer.to_account_info(),
     };
 
     let cip_ctx = CpiContext::new(
@@ -90,22 +169,89 @@ pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
 
     token_interface::transfer_checked(cip_ctx, amount, ctx.accounts.repay_mint.decimals)?;
 
-    let borrow_radio = amount.checked_div(bank.total_borrowed).unwrap();
-    let user_shares = bank
-        .total_borrowed_shares
-        .checked_mul(borrow_radio)
-        .unwrap();
+    // only what reaches the vault after any Token-2022 transfer fee pays down debt
+    ctx.accounts.repay_bank_token_account.reload()?;
+    let mut amount = ctx.accounts.repay_bank_token_account.amount - balance_before;
+    if repay_all {
+        // a fee rounded in the payer's favor can land a unit more than owed; the bank keeps it
+        amount = amount.min(borrowed_value);
+    }
+    if native {
+        unwrap_sol(
+            &ctx.accounts.signer,
+            &ctx.accounts.repay_mint,
+            token_account,
+            &ctx.accounts.token_program,
+        )?;
+    }
 
-    if ctx.accounts.repay_mint.key() == user.usdc_address {
-        user.borrowed_usdc -= amount;
-        user.borrowed_usdc_shares -= user_shares;
-    } else {
-        user.deposited_sol -= amount;
-        user.deposited_sol_shares -= user_shares;
+    book_repayment(bank, user, amount, stable, Clock::get()?.unix_timestamp)
+}
+
+/// What to send so that `amount` of `mint` arrives once any Token-2022 transfer fee for the
+/// current epoch is withheld.
+fn with_transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> Result<u64> {
+    let info = mint.to_account_info();
+    if *info.owner != spl_token_2022::ID {
+        return Ok(amount);
+    }
+
+    let data = info.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    let Ok(config) = state.get_extension::<TransferFeeConfig>() else {
+        return Ok(amount);
+    };
+    config
+        .get_epoch_fee(Clock::get()?.epoch)
+        .calculate_inverse_fee(amount)
+        .and_then(|fee| amount.checked_add(fee))
+        .ok_or_else(|| ErrorCode::MathOverflow.into())
+}
+
+/// What the position owes the bank, variable debt rounded up or, with `stable`, its stable
+/// balance as last accrued.
+pub(crate) fn debt_owed(bank: &Bank, user: &User, stable: bool) -> Result<u64> {
+    if stable {
+        return Ok(user.stable_debt(bank)?.0);
     }
+    let (_, user_shares) = user.shares(bank)?;
+    math::shares_to_amount(
+        user_shares,
+        bank.total_borrowed,
+        bank.total_borrowed_shares,
+        true,
+    )
+}
+
+/// Pays `amount`, already in the vault, off the position's debt. The bank's interest and the
+/// position's rewards must be settled first.
+pub(crate) fn book_repayment(
+    bank: &mut Bank,
+    user: &mut User,
+    amount: u64,
+    stable: bool,
+    now: i64,
+) -> Result<()> {
+    let borrowed_value = debt_owed(bank, user, stable)?;
+    if amount > borrowed_value {
+        return Err(ErrorCode::OverRepay.into());
+    }
+    bank.record_inflow(amount, now)?;
+    if stable {
+        return user.remove_stable_debt(bank, amount);
+    }
+
+    // a full repayment clears every share; otherwise burn rounded down in the protocol's favor
+    let (_, user_shares) = user.shares(bank)?;
+    let shares_to_remove = if amount == borrowed_value {
+        user_shares
+    } else {
+        math::amount_to_shares(amount, bank.total_borrowed, bank.total_borrowed_shares, false)?
+    };
 
-    bank.total_borrowed -= amount;
-    bank.total_borrowed_shares -= user_shares;
+    user.remove_borrow(bank, amount, shares_to_remove)?;
+    bank.total_borrowed = bank.total_borrowed.saturating_sub(amount);
+    bank.total_borrowed_shares -= shares_to_remove;
 
     Ok(())
 }
@@ -0,0 +1,198 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+
+use crate::{error::ErrorCode, state::*};
+
+/// Starts or retunes liquidity mining on one side of a bank. The first call creates the pool
+/// and its reward vault; anyone may then fund the vault with plain token transfers.
+#[derive(Accounts)]
+#[instruction(side: RewardSide)]
+pub struct ConfigureRewards<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(mut)]
+    pub bank: Account<'info, Bank>,
+
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPool::INIT_SPACE,
+        seeds = [b"rewards", bank.key().as_ref(), [side as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Account<'info, RewardPool>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        token::authority = reward_vault,
+        token::mint = reward_mint,
+        token::token_program = token_program,
+        seeds = [b"reward_vault", reward_pool.key().as_ref()],
+        bump
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Pays out the rewards one of the signer's positions has earned on one side of a bank.
+#[derive(Accounts)]
+#[instruction(side: RewardSide)]
+pub struct ClaimRewards<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(
+        mut,
+        seeds = [b"rewards", bank.key().as_ref(), [side as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Account<'info, RewardPool>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            bank.key().as_ref(),
+            [side as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub reward_position: Account<'info, RewardPosition>,
+
+    #[account(address = reward_pool.reward_mint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault", reward_pool.key().as_ref()],
+        bump
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        token::token_program = token_program
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Brings a reward pool and one position's claim on it up to `now`, ahead of a change to the
+/// position's `shares` out of `total_shares` on the pool's side. While the bank has rewards on
+/// that side both accounts are required, so no balance change can skip settlement.
+pub(crate) fn settle_rewards(
+    active: bool,
+    pool: Option<&mut RewardPool>,
+    position: Option<&mut RewardPosition>,
+    user: Pubkey,
+    shares: u64,
+    total_shares: u64,
+    now: i64,
+) -> Result<()> {
+    match (pool, position) {
+        (Some(pool), Some(position)) => {
+            pool.accrue(total_shares, now)?;
+            position.settle(pool, user, shares)
+        }
+        (None, None) if !active => Ok(()),
+        _ => err!(ErrorCode::RewardAccountsMissing),
+    }
+}
+
+fn side_totals(bank: &Bank, side: RewardSide) -> u64 {
+    match side {
+        RewardSide::Deposit => bank.total_deposit_shares,
+        RewardSide::Borrow => bank.total_borrowed_shares,
+    }
+}
+
+pub fn process_configure_rewards(
+    ctx: Context<ConfigureRewards>,
+    side: RewardSide,
+    rate: u64,
+    end_time: i64,
+) -> Result<()> {
+    let bank = &mut ctx.accounts.bank;
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        &[Role::Risk],
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let pool = &mut ctx.accounts.reward_pool;
+    if pool.bank == Pubkey::default() {
+        pool.bank = bank.key();
+        pool.side = side;
+        pool.reward_mint = ctx.accounts.reward_mint.key();
+        pool.last_updated = now;
+        match side {
+            RewardSide::Deposit => bank.deposit_rewards = true,
+            RewardSide::Borrow => bank.borrow_rewards = true,
+        }
+    }
+    // everything emitted so far stays at the old rate
+    pool.accrue(side_totals(bank, side), now)?;
+    pool.rate = rate;
+    pool.end_time = end_time;
+    Ok(())
+}
+
+pub fn process_claim_rewards(ctx: Context<ClaimRewards>, side: RewardSide) -> Result<()> {
+    let bank = &ctx.accounts.bank;
+    // only the position's own two banks resolve to shares
+    let (deposit_shares, borrow_shares) = ctx.accounts.user.shares(bank)?;
+    let shares = match side {
+        RewardSide::Deposit => deposit_shares,
+        RewardSide::Borrow => borrow_shares,
+    };
+    let pool = &mut ctx.accounts.reward_pool;
+    pool.accrue(side_totals(bank, side), Clock::get()?.unix_timestamp)?;
+    let position = &mut ctx.accounts.reward_position;
+    position.settle(pool, ctx.accounts.user.key(), shares)?;
+
+    // an underfunded vault pays what it holds; the rest stays claimable
+    let amount = position.accrued.min(ctx.accounts.reward_vault.amount);
+    position.accrued -= amount;
+
+    let pool_key = pool.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"reward_vault",
+        pool_key.as_ref(),
+        &[ctx.bumps.reward_vault],
+    ]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: ctx.accounts.reward_vault.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.reward_vault.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)
+}
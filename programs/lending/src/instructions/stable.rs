@@ -0,0 +1,38 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+use crate::{error::ErrorCode, state::*};
+
+/// Re-prices one position's stable debt in `mint`'s asset to the current stable rate.
+/// Permissionless, but only while the bank's utilization is at or above its rebalance
+/// threshold.
+#[derive(Accounts)]
+pub struct RebalanceStableRate<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"user", user.owner.as_ref(), user.index.to_le_bytes().as_ref()],
+        bump,
+        constraint = user.stable_debt(&bank)?.0 > 0 @ ErrorCode::NoStableDebt
+    )]
+    pub user: Account<'info, User>,
+}
+
+pub fn process_rebalance_stable_rate(ctx: Context<RebalanceStableRate>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(now)?;
+    require!(
+        bank.stable_rebalance_allowed(),
+        ErrorCode::RebalanceNotAllowed
+    );
+    ctx.accounts.user.reprice_stable_debt(bank, now)
+}
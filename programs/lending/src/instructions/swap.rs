@@ -0,0 +1,518 @@
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program::invoke,
+    },
+};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
+
+use crate::{error::ErrorCode, math, state::*};
+
+use super::{
+    borrow::book_borrow,
+    deposit::book_deposit,
+    price::unit_value,
+    repay::{book_repayment, debt_owed},
+    rewards::settle_rewards,
+    vault::{pay_into_vault, pay_out_of_vault},
+    withdraw::book_withdrawal,
+};
+
+/// Moves `amount` of the signer's collateral from one bank to the other in one go: the
+/// collateral is withdrawn to the signer, swapped by `swap_program` using the remaining
+/// accounts, and whatever the swap returned is deposited. Health is only checked at the end,
+/// so a position too close to its limit to withdraw can still change collateral.
+#[derive(Accounts)]
+pub struct SwapCollateral<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub from_mint: InterfaceAccount<'info, Mint>,
+
+    pub to_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", from_mint.key().as_ref()],
+        bump
+    )]
+    pub from_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", to_mint.key().as_ref()],
+        bump
+    )]
+    pub to_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",from_mint.key().as_ref()],
+        bump
+    )]
+    pub from_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",to_mint.key().as_ref()],
+        bump
+    )]
+    pub to_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = from_mint,
+        associated_token::token_program = token_program
+    )]
+    pub from_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = to_mint,
+        associated_token::token_program = token_program
+    )]
+    pub to_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub from_price_update: Account<'info, PriceUpdateV2>,
+
+    pub to_price_update: Account<'info, PriceUpdateV2>,
+
+    /// CHECK: any program but this one; it only ever acts with the signer's authority
+    #[account(executable, constraint = swap_program.key() != crate::ID @ ErrorCode::InvalidSwap)]
+    pub swap_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// Required while the source bank rewards deposits.
+    #[account(
+        mut,
+        seeds = [b"rewards", from_bank.key().as_ref(), [RewardSide::Deposit as u8].as_ref()],
+        bump
+    )]
+    pub from_reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            from_bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub from_reward_position: Option<Account<'info, RewardPosition>>,
+
+    /// Required while the destination bank rewards deposits.
+    #[account(
+        mut,
+        seeds = [b"rewards", to_bank.key().as_ref(), [RewardSide::Deposit as u8].as_ref()],
+        bump
+    )]
+    pub to_reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            to_bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub to_reward_position: Option<Account<'info, RewardPosition>>,
+}
+
+/// Refinances variable debt from one asset into the other: `amount` is borrowed from
+/// `to_bank`, swapped by `swap_program` using the remaining accounts, and whatever the swap
+/// returned repays debt in `from_bank`, up to all of it. Anything left over stays with the
+/// signer. As with a plain borrow, the bank's origination fee is added to the new debt, and
+/// the position must end within its borrow limit.
+#[derive(Accounts)]
+pub struct SwapDebt<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub from_mint: InterfaceAccount<'info, Mint>,
+
+    pub to_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", from_mint.key().as_ref()],
+        bump
+    )]
+    pub from_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", to_mint.key().as_ref()],
+        bump
+    )]
+    pub to_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",from_mint.key().as_ref()],
+        bump
+    )]
+    pub from_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",to_mint.key().as_ref()],
+        bump
+    )]
+    pub to_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = from_mint,
+        associated_token::token_program = token_program
+    )]
+    pub from_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = to_mint,
+        associated_token::token_program = token_program
+    )]
+    pub to_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + ReferralFees::INIT_SPACE,
+        seeds = [b"referral", user.referrer.as_ref(), to_mint.key().as_ref()],
+        bump
+    )]
+    pub referral_fees: Option<Account<'info, ReferralFees>>,
+
+    pub from_price_update: Account<'info, PriceUpdateV2>,
+
+    pub to_price_update: Account<'info, PriceUpdateV2>,
+
+    /// CHECK: any program but this one; it only ever acts with the signer's authority
+    #[account(executable, constraint = swap_program.key() != crate::ID @ ErrorCode::InvalidSwap)]
+    pub swap_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// Required while the repaid bank rewards borrows.
+    #[account(
+        mut,
+        seeds = [b"rewards", from_bank.key().as_ref(), [RewardSide::Borrow as u8].as_ref()],
+        bump
+    )]
+    pub from_reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            from_bank.key().as_ref(),
+            [RewardSide::Borrow as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub from_reward_position: Option<Account<'info, RewardPosition>>,
+
+    /// Required while the borrowed bank rewards borrows.
+    #[account(
+        mut,
+        seeds = [b"rewards", to_bank.key().as_ref(), [RewardSide::Borrow as u8].as_ref()],
+        bump
+    )]
+    pub to_reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            to_bank.key().as_ref(),
+            [RewardSide::Borrow as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub to_reward_position: Option<Account<'info, RewardPosition>>,
+}
+
+/// Hands `accounts` to `swap_program` as a single instruction carrying `data`. Signer and
+/// writable flags pass through as the transaction set them; no treasury authority is lent.
+fn invoke_swap<'info>(
+    swap_program: &AccountInfo<'info>,
+    accounts: &[AccountInfo<'info>],
+    data: Vec<u8>,
+) -> Result<()> {
+    let ix = Instruction {
+        program_id: swap_program.key(),
+        accounts: accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: account.key(),
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data,
+    };
+    let mut infos = accounts.to_vec();
+    infos.push(swap_program.clone());
+    invoke(&ix, &infos)?;
+    Ok(())
+}
+
+/// Runs the swap and returns what it left in `output`, which has to be at least
+/// `min_amount_out`.
+pub(crate) fn swap_and_measure<'info>(
+    swap_program: &AccountInfo<'info>,
+    accounts: &[AccountInfo<'info>],
+    data: Vec<u8>,
+    output: &mut InterfaceAccount<'info, TokenAccount>,
+    min_amount_out: u64,
+) -> Result<u64> {
+    let received_before = output.amount;
+    invoke_swap(swap_program, accounts, data)?;
+    output.reload()?;
+    let received = output.amount.saturating_sub(received_before);
+    require!(received >= min_amount_out, ErrorCode::SlippageExceeded);
+    Ok(received)
+}
+
+pub fn process_swap_collateral<'info>(
+    ctx: Context<'_, '_, 'info, 'info, SwapCollateral<'info>>,
+    amount: u64,
+    min_amount_out: u64,
+    swap_data: Vec<u8>,
+) -> Result<()> {
+    if ctx.accounts.from_bank.paused || ctx.accounts.to_bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
+    let user = &ctx.accounts.user;
+    let from_is_usdc = user.is_usdc(&ctx.accounts.from_bank)?;
+    require!(
+        from_is_usdc != user.is_usdc(&ctx.accounts.to_bank)?,
+        ErrorCode::InvalidSwap
+    );
+
+    let now = Clock::get()?.unix_timestamp;
+    let from_bank = &mut ctx.accounts.from_bank;
+    let to_bank = &mut ctx.accounts.to_bank;
+    from_bank.accrue_interest(now)?;
+    to_bank.accrue_interest(now)?;
+    let user = &mut ctx.accounts.user;
+    user.accrue_stable(now)?;
+
+    // both deposit sides settle before shares move between them
+    for (bank, pool, position) in [
+        (
+            &**from_bank,
+            ctx.accounts.from_reward_pool.as_deref_mut(),
+            ctx.accounts.from_reward_position.as_deref_mut(),
+        ),
+        (
+            &**to_bank,
+            ctx.accounts.to_reward_pool.as_deref_mut(),
+            ctx.accounts.to_reward_position.as_deref_mut(),
+        ),
+    ] {
+        let (shares, _) = user.shares(bank)?;
+        settle_rewards(
+            bank.deposit_rewards,
+            pool,
+            position,
+            user.key(),
+            shares,
+            bank.total_deposit_shares,
+            now,
+        )?;
+    }
+
+    let amount = book_withdrawal(from_bank, user, amount, now)?;
+    pay_out_of_vault(
+        &mut ctx.accounts.from_bank_token_account,
+        ctx.bumps.from_bank_token_account,
+        &ctx.accounts.from_mint,
+        &ctx.accounts.from_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    let received = swap_and_measure(
+        &ctx.accounts.swap_program,
+        ctx.remaining_accounts,
+        swap_data,
+        &mut ctx.accounts.to_token_account,
+        min_amount_out,
+    )?;
+    let deposit = pay_into_vault(
+        &ctx.accounts.to_token_account,
+        &ctx.accounts.signer,
+        &ctx.accounts.to_mint,
+        &mut ctx.accounts.to_bank_token_account,
+        &ctx.accounts.token_program,
+        received,
+    )?;
+    book_deposit(to_bank, user, deposit, now)?;
+
+    let from_unit_value = unit_value(
+        &ctx.accounts.from_price_update,
+        &ctx.accounts.from_mint,
+        from_is_usdc,
+    )?;
+    let to_unit_value = unit_value(
+        &ctx.accounts.to_price_update,
+        &ctx.accounts.to_mint,
+        !from_is_usdc,
+    )?;
+    user.check_borrow_limit([(&**from_bank, from_unit_value), (&**to_bank, to_unit_value)])
+}
+
+pub fn process_swap_debt<'info>(
+    ctx: Context<'_, '_, 'info, 'info, SwapDebt<'info>>,
+    amount: u64,
+    min_amount_out: u64,
+    swap_data: Vec<u8>,
+) -> Result<()> {
+    if ctx.accounts.from_bank.paused || ctx.accounts.to_bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
+    let user = &ctx.accounts.user;
+    let from_is_usdc = user.is_usdc(&ctx.accounts.from_bank)?;
+    require!(
+        from_is_usdc != user.is_usdc(&ctx.accounts.to_bank)?,
+        ErrorCode::InvalidSwap
+    );
+    require!(
+        ctx.accounts.referral_fees.is_some() == (ctx.accounts.user.referrer != Pubkey::default()),
+        ErrorCode::ReferralAccountMismatch
+    );
+
+    let now = Clock::get()?.unix_timestamp;
+    let from_bank = &mut ctx.accounts.from_bank;
+    let to_bank = &mut ctx.accounts.to_bank;
+    from_bank.accrue_interest(now)?;
+    to_bank.accrue_interest(now)?;
+    let user = &mut ctx.accounts.user;
+    user.accrue_stable(now)?;
+
+    // both borrow sides settle before shares move between them
+    for (bank, pool, position) in [
+        (
+            &**from_bank,
+            ctx.accounts.from_reward_pool.as_deref_mut(),
+            ctx.accounts.from_reward_position.as_deref_mut(),
+        ),
+        (
+            &**to_bank,
+            ctx.accounts.to_reward_pool.as_deref_mut(),
+            ctx.accounts.to_reward_position.as_deref_mut(),
+        ),
+    ] {
+        let (_, shares) = user.shares(bank)?;
+        settle_rewards(
+            bank.borrow_rewards,
+            pool,
+            position,
+            user.key(),
+            shares,
+            bank.total_borrowed_shares,
+            now,
+        )?;
+    }
+
+    // the new debt carries the origination fee exactly as a borrow would; no host is paid
+    let fee = math::bps_of(amount, to_bank.borrow_fee_bps)?;
+    let debt = amount + fee;
+    to_bank.record_outflow(amount, now)?;
+    pay_out_of_vault(
+        &mut ctx.accounts.to_bank_token_account,
+        ctx.bumps.to_bank_token_account,
+        &ctx.accounts.to_mint,
+        &ctx.accounts.to_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    book_borrow(
+        to_bank,
+        user,
+        ctx.accounts.referral_fees.as_deref_mut(),
+        debt,
+        fee,
+        false,
+        now,
+    )?;
+
+    let received = swap_and_measure(
+        &ctx.accounts.swap_program,
+        ctx.remaining_accounts,
+        swap_data,
+        &mut ctx.accounts.from_token_account,
+        min_amount_out,
+    )?;
+    let repay = received.min(debt_owed(from_bank, user, false)?);
+    let repaid = pay_into_vault(
+        &ctx.accounts.from_token_account,
+        &ctx.accounts.signer,
+        &ctx.accounts.from_mint,
+        &mut ctx.accounts.from_bank_token_account,
+        &ctx.accounts.token_program,
+        repay,
+    )?;
+    book_repayment(from_bank, user, repaid, false, now)?;
+
+    let from_unit_value = unit_value(
+        &ctx.accounts.from_price_update,
+        &ctx.accounts.from_mint,
+        from_is_usdc,
+    )?;
+    let to_unit_value = unit_value(
+        &ctx.accounts.to_price_update,
+        &ctx.accounts.to_mint,
+        !from_is_usdc,
+    )?;
+    user.check_borrow_limit([(&**from_bank, from_unit_value), (&**to_bank, to_unit_value)])
+}
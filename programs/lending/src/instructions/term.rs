@@ -0,0 +1,652 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{self, Mint, TokenAccount, TokenInterface},
+};
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
+
+use crate::{constants, error::ErrorCode, math, state::*};
+
+use super::{price::unit_value, rewards::settle_rewards};
+
+/// Opens a term pool on a bank at a fixed `rate` until `maturity`, with its own vault so
+/// term liquidity never mixes with the bank's variable liquidity.
+#[derive(Accounts)]
+#[instruction(maturity: i64)]
+pub struct CreateTermPool<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + TermPool::INIT_SPACE,
+        seeds = [b"term_pool", bank.key().as_ref(), maturity.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub term_pool: Account<'info, TermPool>,
+
+    #[account(
+        init,
+        payer = signer,
+        token::authority = term_vault,
+        token::mint = mint,
+        token::token_program = token_program,
+        seeds = [b"term_vault", term_pool.key().as_ref()],
+        bump
+    )]
+    pub term_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Puts the signer's tokens into a term pool until it matures.
+#[derive(Accounts)]
+pub struct LendTerm<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(mut, has_one = bank)]
+    pub term_pool: Account<'info, TermPool>,
+
+    #[account(
+        mut,
+        seeds = [b"term_vault", term_pool.key().as_ref()],
+        bump
+    )]
+    pub term_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + TermDeposit::INIT_SPACE,
+        seeds = [b"term_deposit", term_pool.key().as_ref(), signer.key().as_ref()],
+        bump
+    )]
+    pub term_deposit: Account<'info, TermDeposit>,
+
+    #[account(
+        mut,
+        associated_token::authority = signer,
+        associated_token::mint = mint,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+/// Pays a lender its share of what has come back into a matured term pool's vault. Shares
+/// backing loans still outstanding stay with the lender until those are repaid or rolled.
+#[derive(Accounts)]
+pub struct WithdrawTerm<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(mut, has_one = bank)]
+    pub term_pool: Account<'info, TermPool>,
+
+    #[account(
+        mut,
+        seeds = [b"term_vault", term_pool.key().as_ref()],
+        bump
+    )]
+    pub term_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"term_deposit", term_pool.key().as_ref(), signer.key().as_ref()],
+        bump
+    )]
+    pub term_deposit: Account<'info, TermDeposit>,
+
+    #[account(
+        mut,
+        associated_token::authority = signer,
+        associated_token::mint = mint,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+/// Borrows from a term pool against the signer's collateral at the pool's fixed rate.
+/// `other_mint` and its bank and price are needed to value the whole position.
+#[derive(Accounts)]
+pub struct BorrowTerm<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub other_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", other_mint.key().as_ref()],
+        bump
+    )]
+    pub other_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(mut, has_one = bank)]
+    pub term_pool: Account<'info, TermPool>,
+
+    #[account(
+        mut,
+        seeds = [b"term_vault", term_pool.key().as_ref()],
+        bump
+    )]
+    pub term_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + TermLoan::INIT_SPACE,
+        seeds = [b"term_loan", term_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub term_loan: Account<'info, TermLoan>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::authority = signer,
+        associated_token::mint = mint,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    pub other_price_update: Account<'info, PriceUpdateV2>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+/// Anyone may repay a term loan; `signer` pays and `owner` selects whose loan goes down.
+#[derive(Accounts)]
+pub struct RepayTerm<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK: only used to derive the position being repaid
+    pub owner: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"user", owner.key().as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(mut, has_one = bank)]
+    pub term_pool: Account<'info, TermPool>,
+
+    #[account(
+        mut,
+        seeds = [b"term_vault", term_pool.key().as_ref()],
+        bump
+    )]
+    pub term_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"term_loan", term_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub term_loan: Account<'info, TermLoan>,
+
+    #[account(
+        mut,
+        associated_token::authority = signer,
+        associated_token::mint = mint,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+/// Once a pool matures, anyone may roll a loan that is still owed into variable debt on the
+/// position, which repays the pool's vault out of the bank's variable liquidity. From then on
+/// the debt accrues at the variable rate and is liquidated like any other.
+#[derive(Accounts)]
+pub struct RollTermLoan<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury",mint.key().as_ref()],
+        bump
+    )]
+    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"user", user.owner.as_ref(), user.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, User>,
+
+    #[account(mut, has_one = bank)]
+    pub term_pool: Account<'info, TermPool>,
+
+    #[account(
+        mut,
+        seeds = [b"term_vault", term_pool.key().as_ref()],
+        bump
+    )]
+    pub term_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"term_loan", term_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub term_loan: Account<'info, TermLoan>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+
+    /// Required while the bank rewards borrows.
+    #[account(
+        mut,
+        seeds = [b"rewards", bank.key().as_ref(), [RewardSide::Borrow as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            bank.key().as_ref(),
+            [RewardSide::Borrow as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub reward_position: Option<Account<'info, RewardPosition>>,
+}
+
+pub fn process_create_term_pool(
+    ctx: Context<CreateTermPool>,
+    maturity: i64,
+    rate: f64,
+) -> Result<()> {
+    let bank = &ctx.accounts.bank;
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        &[Role::Risk],
+    )?;
+    require!(
+        maturity > Clock::get()?.unix_timestamp && rate >= 0.0 && rate.is_finite(),
+        ErrorCode::InvalidConfig
+    );
+
+    let pool = &mut ctx.accounts.term_pool;
+    pool.bank = bank.key();
+    pool.maturity = maturity;
+    pool.rate = rate;
+    Ok(())
+}
+
+pub fn process_lend_term(ctx: Context<LendTerm>, amount: u64) -> Result<()> {
+    if ctx.accounts.bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now < ctx.accounts.term_pool.maturity,
+        ErrorCode::TermPoolMatured
+    );
+
+    let balance_before = ctx.accounts.term_vault.amount;
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.term_vault.to_account_info(),
+            authority: ctx.accounts.signer.to_account_info(),
+        },
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+    // only what reaches the vault after any Token-2022 transfer fee is lent
+    ctx.accounts.term_vault.reload()?;
+    let amount = ctx.accounts.term_vault.amount - balance_before;
+
+    let pool = &mut ctx.accounts.term_pool;
+    // shares are priced against interest already booked, so late lenders do not dilute it
+    let shares = math::amount_to_shares(amount, pool.total_value, pool.total_shares, false)?;
+    pool.total_value += amount;
+    pool.total_shares += shares;
+    pool.available += amount;
+
+    let deposit = &mut ctx.accounts.term_deposit;
+    deposit.pool = pool.key();
+    deposit.owner = ctx.accounts.signer.key();
+    deposit.shares += shares;
+
+    ctx.accounts.bank.total_term_lent += amount;
+    Ok(())
+}
+
+pub fn process_withdraw_term(ctx: Context<WithdrawTerm>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let pool = &mut ctx.accounts.term_pool;
+    require!(now >= pool.maturity, ErrorCode::TermNotMatured);
+
+    // every lender takes the same fraction of the vault, never more than the shares are worth
+    let deposit_shares = ctx.accounts.term_deposit.shares;
+    let vault_share = math::shares_to_amount(
+        deposit_shares,
+        ctx.accounts.term_vault.amount,
+        pool.total_shares,
+        false,
+    )?;
+    let value = math::shares_to_amount(deposit_shares, pool.total_value, pool.total_shares, false)?;
+    let amount = vault_share.min(value);
+    require!(amount > 0, ErrorCode::TermLoansOutstanding);
+    // shares burn at the pool's full value, rounded up, so those left behind are not diluted
+    let shares = math::amount_to_shares(amount, pool.total_value, pool.total_shares, true)?
+        .min(deposit_shares);
+    pool.total_value -= amount;
+    pool.total_shares -= shares;
+    ctx.accounts.term_deposit.shares -= shares;
+
+    let bank = &mut ctx.accounts.bank;
+    bank.total_term_lent = bank.total_term_lent.saturating_sub(amount);
+
+    pay_from_vault(
+        &ctx.accounts.term_pool,
+        &mut ctx.accounts.term_vault,
+        ctx.bumps.term_vault,
+        &ctx.accounts.mint,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    if ctx.accounts.term_deposit.shares == 0 {
+        ctx.accounts
+            .term_deposit
+            .close(ctx.accounts.signer.to_account_info())?;
+    }
+    Ok(())
+}
+
+pub fn process_borrow_term(ctx: Context<BorrowTerm>, amount: u64) -> Result<()> {
+    if ctx.accounts.bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let pool = &mut ctx.accounts.term_pool;
+    require!(now < pool.maturity, ErrorCode::TermPoolMatured);
+    require!(amount <= pool.available, ErrorCode::InsufficientFunds);
+
+    // interest to maturity is owed in full from the start
+    let owed = math::accrue_interest(amount, pool.rate, pool.maturity - now)?;
+    pool.available -= amount;
+    pool.outstanding += owed;
+    pool.total_value += owed - amount;
+
+    let loan = &mut ctx.accounts.term_loan;
+    loan.pool = pool.key();
+    loan.user = ctx.accounts.user.key();
+    loan.owed += owed;
+
+    let bank = &mut ctx.accounts.bank;
+    let other_bank = &mut ctx.accounts.other_bank;
+    bank.accrue_interest(now)?;
+    other_bank.accrue_interest(now)?;
+    bank.total_term_borrowed += owed;
+
+    let user = &mut ctx.accounts.user;
+    user.accrue_stable(now)?;
+    *user.term_debt_mut(bank)? += owed;
+
+    pay_from_vault(
+        &ctx.accounts.term_pool,
+        &mut ctx.accounts.term_vault,
+        ctx.bumps.term_vault,
+        &ctx.accounts.mint,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    let is_usdc = user.is_usdc(bank)?;
+    let bank_unit_value = unit_value(&ctx.accounts.price_update, &ctx.accounts.mint, is_usdc)?;
+    let other_unit_value = unit_value(
+        &ctx.accounts.other_price_update,
+        &ctx.accounts.other_mint,
+        !is_usdc,
+    )?;
+
+    user.check_borrow_limit([
+        (&**bank, bank_unit_value),
+        (&**other_bank, other_unit_value),
+    ])
+}
+
+pub fn process_repay_term(ctx: Context<RepayTerm>, amount: u64) -> Result<()> {
+    let owed = ctx.accounts.term_loan.owed;
+    let amount = if amount == constants::AMOUNT_ALL {
+        owed
+    } else {
+        amount
+    };
+
+    let balance_before = ctx.accounts.term_vault.amount;
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.term_vault.to_account_info(),
+            authority: ctx.accounts.signer.to_account_info(),
+        },
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+    // only what reaches the vault after any Token-2022 transfer fee pays down the loan
+    ctx.accounts.term_vault.reload()?;
+    let amount = ctx.accounts.term_vault.amount - balance_before;
+    book_term_repayment(
+        &mut ctx.accounts.bank,
+        &mut ctx.accounts.user,
+        &mut ctx.accounts.term_pool,
+        &mut ctx.accounts.term_loan,
+        amount,
+    )
+}
+
+/// Pays `amount`, already in the pool's vault, off a term loan.
+pub(crate) fn book_term_repayment(
+    bank: &mut Bank,
+    user: &mut User,
+    pool: &mut TermPool,
+    loan: &mut TermLoan,
+    amount: u64,
+) -> Result<()> {
+    require!(amount <= loan.owed, ErrorCode::OverRepay);
+
+    loan.owed -= amount;
+    pool.outstanding -= amount;
+    *user.term_debt_mut(bank)? -= amount;
+    bank.total_term_borrowed = bank.total_term_borrowed.saturating_sub(amount);
+    Ok(())
+}
+
+pub fn process_roll_term_loan(ctx: Context<RollTermLoan>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now >= ctx.accounts.term_pool.maturity,
+        ErrorCode::TermNotMatured
+    );
+
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(now)?;
+    let user = &mut ctx.accounts.user;
+    let (_, shares) = user.shares(bank)?;
+    settle_rewards(
+        bank.borrow_rewards,
+        ctx.accounts.reward_pool.as_deref_mut(),
+        ctx.accounts.reward_position.as_deref_mut(),
+        user.key(),
+        shares,
+        bank.total_borrowed_shares,
+        now,
+    )?;
+
+    let owed = ctx.accounts.term_loan.owed;
+    ctx.accounts.term_loan.owed = 0;
+    ctx.accounts.term_pool.outstanding -= owed;
+    *user.term_debt_mut(bank)? -= owed;
+    bank.total_term_borrowed = bank.total_term_borrowed.saturating_sub(owed);
+
+    // debt shares round up so the protocol never under-records what is owed
+    let user_shares =
+        math::amount_to_shares(owed, bank.total_borrowed, bank.total_borrowed_shares, true)?;
+    user.add_borrow(bank, owed, user_shares)?;
+    bank.total_borrowed += owed;
+    bank.total_borrowed_shares += user_shares;
+    bank.record_outflow(owed, now)?;
+
+    // the variable side lends the pool what the position still owed it
+    let balance_before = ctx.accounts.bank_token_account.amount;
+    let mint_key = ctx.accounts.mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"treasury",
+        mint_key.as_ref(),
+        &[ctx.bumps.bank_token_account],
+    ]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: ctx.accounts.bank_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.term_vault.to_account_info(),
+            authority: ctx.accounts.bank_token_account.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token_interface::transfer_checked(cpi_ctx, owed, ctx.accounts.mint.decimals)?;
+
+    ctx.accounts.bank_token_account.reload()?;
+    require_eq!(
+        balance_before - ctx.accounts.bank_token_account.amount,
+        owed,
+        ErrorCode::TransferAmountMismatch
+    );
+    Ok(())
+}
+
+/// Pays `amount` out of a term pool's vault, which must release exactly that much.
+fn pay_from_vault<'info>(
+    term_pool: &Account<'info, TermPool>,
+    term_vault: &mut InterfaceAccount<'info, TokenAccount>,
+    bump: u8,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let balance_before = term_vault.amount;
+    let pool_key = term_pool.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[b"term_vault", pool_key.as_ref(), &[bump]]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: term_vault.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: term_vault.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+
+    term_vault.reload()?;
+    require_eq!(
+        balance_before - term_vault.amount,
+        amount,
+        ErrorCode::TransferAmountMismatch
+    );
+    Ok(())
+}
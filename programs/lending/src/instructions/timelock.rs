@@ -0,0 +1,108 @@
+use crate::{error::ErrorCode, instructions::BankConfigParams, state::*};
+use anchor_lang::prelude::*;
+
+/// Queues a config change that anyone may execute once the bank's `config_delay` has passed.
+/// A bank holds at most one queued change at a time.
+#[derive(Accounts)]
+pub struct QueueBankConfig<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub bank: Account<'info, Bank>,
+
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + PendingConfig::INIT_SPACE,
+        seeds = [b"pending_config", bank.key().as_ref()],
+        bump
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteBankConfig<'info> {
+    #[account(mut)]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        close = payer,
+        has_one = payer,
+        seeds = [b"pending_config", bank.key().as_ref()],
+        bump
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+
+    /// CHECK: only receives the rent back; must match `pending_config.payer`.
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelBankConfig<'info> {
+    pub signer: Signer<'info>,
+
+    pub bank: Account<'info, Bank>,
+
+    #[account(seeds = [b"roles"], bump)]
+    pub roles: Option<Account<'info, Roles>>,
+
+    #[account(
+        mut,
+        close = payer,
+        has_one = payer,
+        seeds = [b"pending_config", bank.key().as_ref()],
+        bump
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+
+    /// CHECK: only receives the rent back; must match `pending_config.payer`.
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+pub fn process_queue_bank_config(
+    ctx: Context<QueueBankConfig>,
+    params: BankConfigParams,
+) -> Result<()> {
+    let bank = &ctx.accounts.bank;
+    bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        &[Role::Risk],
+    )?;
+    // reject a change that could never be executed as the config stands today
+    params.apply(&mut Bank::clone(bank))?;
+
+    let pending = &mut ctx.accounts.pending_config;
+    pending.bank = bank.key();
+    pending.payer = ctx.accounts.signer.key();
+    pending.params = params;
+    pending.execute_after = Clock::get()?.unix_timestamp + bank.config_delay;
+    Ok(())
+}
+
+pub fn process_execute_bank_config(ctx: Context<ExecuteBankConfig>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let pending = &ctx.accounts.pending_config;
+    require!(now >= pending.execute_after, ErrorCode::TimelockNotElapsed);
+
+    let bank = &mut ctx.accounts.bank;
+    // settle interest at the old rate before anything changes
+    bank.accrue_interest(now)?;
+    pending.params.apply(bank)
+}
+
+pub fn process_cancel_bank_config(ctx: Context<CancelBankConfig>) -> Result<()> {
+    ctx.accounts.bank.authorize(
+        ctx.accounts.roles.as_deref(),
+        &ctx.accounts.signer.key(),
+        &[Role::Risk],
+    )
+}
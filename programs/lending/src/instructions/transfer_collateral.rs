@@ -0,0 +1,156 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
+
+use crate::{
+    error::ErrorCode,
+    math,
+    state::{Bank, RewardPool, RewardPosition, RewardSide, User},
+};
+
+use super::{price::unit_value, rewards::settle_rewards};
+
+/// Moves deposited `mint` between two of the signer's sub-accounts. `other_mint` and its
+/// bank and price are needed to value the rest of the source position.
+#[derive(Accounts)]
+pub struct TransferCollateral<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub other_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", mint.key().as_ref()],
+        bump
+    )]
+    pub bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"bank", other_mint.key().as_ref()],
+        bump
+    )]
+    pub other_bank: Account<'info, Bank>,
+
+    #[account(
+        mut,
+        seeds = [b"user", signer.key().as_ref(), from.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub from: Account<'info, User>,
+
+    #[account(
+        mut,
+        seeds = [b"user", signer.key().as_ref(), to.index.to_le_bytes().as_ref()],
+        bump,
+        constraint = to.key() != from.key() @ ErrorCode::SubAccountMismatch,
+        constraint = to.usdc_address == from.usdc_address @ ErrorCode::SubAccountMismatch,
+        constraint = to.sol_address == from.sol_address @ ErrorCode::SubAccountMismatch
+    )]
+    pub to: Account<'info, User>,
+
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    pub other_price_update: Account<'info, PriceUpdateV2>,
+
+    /// Required while the bank rewards deposits.
+    #[account(
+        mut,
+        seeds = [b"rewards", bank.key().as_ref(), [RewardSide::Deposit as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Option<Account<'info, RewardPool>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            from.key().as_ref()
+        ],
+        bump
+    )]
+    pub from_reward_position: Option<Account<'info, RewardPosition>>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            to.key().as_ref()
+        ],
+        bump
+    )]
+    pub to_reward_position: Option<Account<'info, RewardPosition>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+pub fn process_transfer_collateral(ctx: Context<TransferCollateral>, amount: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    let other_bank = &mut ctx.accounts.other_bank;
+    bank.accrue_interest(now)?;
+    other_bank.accrue_interest(now)?;
+
+    let from = &mut ctx.accounts.from;
+    let to = &mut ctx.accounts.to;
+    let is_usdc = from.is_usdc(bank)?;
+
+    // both sides settle before shares move between them
+    for (user, key, position) in [
+        (
+            &**from,
+            from.key(),
+            ctx.accounts.from_reward_position.as_deref_mut(),
+        ),
+        (
+            &**to,
+            to.key(),
+            ctx.accounts.to_reward_position.as_deref_mut(),
+        ),
+    ] {
+        let (shares, _) = user.shares(bank)?;
+        settle_rewards(
+            bank.deposit_rewards,
+            ctx.accounts.reward_pool.as_deref_mut(),
+            position,
+            key,
+            shares,
+            bank.total_deposit_shares,
+            now,
+        )?;
+    }
+
+    let (deposited, _) = from.balances(bank)?;
+    if deposited < amount {
+        return Err(ErrorCode::InsufficientFunds.into());
+    }
+    // the source gives up shares rounded up, exactly as a withdrawal would burn them
+    let shares =
+        math::amount_to_shares(amount, bank.total_deposits, bank.total_deposit_shares, true)?;
+    require!(shares <= from.shares(bank)?.0, ErrorCode::InsufficientFunds);
+    from.remove_deposit(bank, amount, shares)?;
+    to.add_deposit(bank, amount, shares)?;
+
+    let bank_unit_value = unit_value(&ctx.accounts.price_update, &ctx.accounts.mint, is_usdc)?;
+    let other_unit_value = unit_value(
+        &ctx.accounts.other_price_update,
+        &ctx.accounts.other_mint,
+        !is_usdc,
+    )?;
+
+    from.check_borrow_limit([
+        (&**bank, bank_unit_value),
+        (&**other_bank, other_unit_value),
+    ])
+}
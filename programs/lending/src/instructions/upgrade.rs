@@ -0,0 +1,91 @@
+use anchor_lang::{
+    prelude::*,
+    system_program::{self, Transfer},
+    Discriminator,
+};
+
+use anchor_spl::token_interface::Mint;
+
+use crate::{
+    error::ErrorCode,
+    state::{Bank, User},
+};
+
+#[derive(Accounts)]
+pub struct UpgradeAccount<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: may predate the current layout, so ownership and discriminator are
+    /// checked by hand before it is decoded
+    #[account(mut, owner = crate::ID)]
+    pub account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Mint of the SOL bank, which positions from before version 5 did not record. Only the
+    /// position's owner may supply it, once.
+    pub sol_mint: Option<InterfaceAccount<'info, Mint>>,
+}
+
+/// Grows a `Bank` or `User` to the current layout, with `payer` covering the extra rent,
+/// then runs its version upgrade. Safe to call on an account that is already current.
+pub fn process_upgrade_account(ctx: Context<UpgradeAccount>) -> Result<()> {
+    let info = ctx.accounts.account.to_account_info();
+    let discriminator: [u8; 8] = info
+        .try_borrow_data()?
+        .get(..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ErrorCode::UnknownAccount)?;
+    let is_bank = discriminator == Bank::DISCRIMINATOR;
+    let space = if is_bank {
+        8 + Bank::INIT_SPACE
+    } else if discriminator == User::DISCRIMINATOR {
+        8 + User::INIT_SPACE
+    } else {
+        return Err(ErrorCode::UnknownAccount.into());
+    };
+
+    if info.data_len() < space {
+        let shortfall = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(info.lamports());
+        if shortfall > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: info.clone(),
+            };
+            let cpi_ctx =
+                CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, shortfall)?;
+        }
+        // new trailing fields start zeroed
+        info.realloc(space, true)?;
+    }
+
+    let mut data = info.try_borrow_mut_data()?;
+    if is_bank {
+        let mut bank = Bank::try_deserialize(&mut &data[..])?;
+        bank.upgrade();
+        bank.try_serialize(&mut &mut data[..])?;
+    } else {
+        let mut user = User::try_deserialize(&mut &data[..])?;
+        user.upgrade();
+        if let Some(sol_mint) = &ctx.accounts.sol_mint {
+            require_keys_eq!(
+                ctx.accounts.payer.key(),
+                user.owner,
+                ErrorCode::Unauthorized
+            );
+            require_keys_eq!(
+                user.sol_address,
+                Pubkey::default(),
+                ErrorCode::InvalidConfig
+            );
+            require_keys_neq!(sol_mint.key(), user.usdc_address, ErrorCode::InvalidConfig);
+            user.sol_address = sol_mint.key();
+        }
+        user.try_serialize(&mut &mut data[..])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+
+use crate::error::ErrorCode;
+
+/// Pays `amount` out of the treasury of `mint` to `to`. The vault must release exactly that
+/// much; any Token-2022 transfer fee is the recipient's to bear.
+pub(crate) fn pay_out_of_vault<'info>(
+    vault: &mut InterfaceAccount<'info, TokenAccount>,
+    bump: u8,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let balance_before = vault.amount;
+    let mint_key = mint.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[b"treasury", mint_key.as_ref(), &[bump]]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: vault.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: vault.to_account_info(),
+        },
+        signer_seeds,
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+    vault.reload()?;
+    require_eq!(
+        balance_before - vault.amount,
+        amount,
+        ErrorCode::TransferAmountMismatch
+    );
+    Ok(())
+}
+
+/// Pays `amount` from the signer's `from` into `vault`, a treasury or term vault, and returns
+/// what arrived, which is all that may be credited once any Token-2022 transfer fee is withheld.
+pub(crate) fn pay_into_vault<'info>(
+    from: &InterfaceAccount<'info, TokenAccount>,
+    signer: &Signer<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &mut InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<u64> {
+    let balance_before = vault.amount;
+    let cpi_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        token_interface::TransferChecked {
+            from: from.to_account_info(),
+            mint: mint.to_account_info(),
+            to: vault.to_account_info(),
+            authority: signer.to_account_info(),
+        },
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+    vault.reload()?;
+    Ok(vault.amount - balance_before)
+}
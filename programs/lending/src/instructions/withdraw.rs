@@ -1,16 +1,28 @@
-use std::{f64::consts::E, ops::DerefMut};
+use std::ops::DerefMut;
 
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
     token_interface::{self, Mint, TokenAccount, TokenInterface},
 };
+use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
 
 use crate::{
+    constants,
     error::ErrorCode,
-    state::{Bank, User},
+    math,
+    state::{Bank, RewardPool, RewardPosition, RewardSide, User},
 };
 
+use super::{
+    native::{payment_account, unwrap_sol},
+    price::unit_value,
+    rewards::settle_rewards,
+};
+
+/// Withdraws to the signer's token account. `withdraw_native` pays out lamports instead,
+/// closing `wsol_account` once the tokens land in it.
+/// `other_mint` and its bank and price are needed to value the rest of the position.
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account(mut)]
@@ -18,13 +30,22 @@ pub struct Withdraw<'info> {
 
     pub mint: InterfaceAccount<'info, Mint>,
 
+    pub other_mint: InterfaceAccount<'info, Mint>,
+
     #[account(
         mut,
-        seeds = [mint.key().as_ref()],
+        seeds = [b"bank", mint.key().as_ref()],
         bump
     )]
     pub bank: Account<'info, Bank>,
 
+    #[account(
+        mut,
+        seeds = [b"bank", other_mint.key().as_ref()],
+        bump
+    )]
+    pub other_bank: Account<'info, Bank>,
+
     #[account(
         mut,
         seeds = [b"treasury",mint.key().as_ref()],
@@ -34,53 +55,100 @@ pub struct Withdraw<'info> {
 
     #[account(
         mut,
-        seeds = [signer.key().as_ref()],
+        seeds = [b"user", signer.key().as_ref(), user.index.to_le_bytes().as_ref()],
         bump
     )]
     pub user: Account<'info, User>,
 
+    /// Required by `withdraw`.
     #[account(
         mut,
         associated_token::authority = signer,
         associated_token::mint = mint,
         associated_token::token_program = token_program
     )]
-    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub user_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Required by `withdraw_native`: a wrapped SOL account that lives only for the instruction,
+    /// so the signer's own token account is neither needed nor closed.
+    #[account(
+        init,
+        payer = signer,
+        seeds = [b"wsol", signer.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = signer,
+        token::token_program = token_program
+    )]
+    pub wsol_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
     pub token_program: Interface<'info, TokenInterface>,
 
     pub system_program: Program<'info, System>,
 
     pub associated_token_program: Program<'info, AssociatedToken>,
-}
 
-pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-    let user = &mut ctx.accounts.user;
+    pub price_update: Account<'info, PriceUpdateV2>,
 
-    let deposited_value = if ctx.accounts.mint.key() == user.usdc_address {
-        user.deposited_usdc
-    } else {
-        user.deposited_sol
-    };
+    pub other_price_update: Account<'info, PriceUpdateV2>,
 
-    let time_diff = user.last_updated - Clock::get()?.unix_timestamp;
+    /// Required while the bank rewards deposits.
+    #[account(
+        mut,
+        seeds = [b"rewards", bank.key().as_ref(), [RewardSide::Deposit as u8].as_ref()],
+        bump
+    )]
+    pub reward_pool: Option<Account<'info, RewardPool>>,
 
-    //看不懂
-    let bank = ctx.accounts.bank.deref_mut();
-    bank.total_deposits = (bank.total_deposits as f64 * (E.powf(bank.instrest_rate * time_diff as f64))) as u64;
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + RewardPosition::INIT_SPACE,
+        seeds = [
+            b"reward_position",
+            bank.key().as_ref(),
+            [RewardSide::Deposit as u8].as_ref(),
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub reward_position: Option<Account<'info, RewardPosition>>,
+}
 
-    let value_per_share =  bank.total_deposits as f64 / bank.total_deposit_shares as f64;
+pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64, native: bool) -> Result<()> {
+    if ctx.accounts.bank.paused {
+        return Err(ErrorCode::BankPaused.into());
+    }
 
-    let user_value = deposited_value as f64 / value_per_share;
+    let now = Clock::get()?.unix_timestamp;
+    let bank = &mut ctx.accounts.bank;
+    bank.accrue_interest(now)?;
 
-    if user_value < amount as f64 {
-        return Err(ErrorCode::InsufficientFunds.into())
-    }
+    let user = &mut ctx.accounts.user;
 
+    let (user_shares, _) = user.shares(bank)?;
+    settle_rewards(
+        bank.deposit_rewards,
+        ctx.accounts.reward_pool.as_deref_mut(),
+        ctx.accounts.reward_position.as_deref_mut(),
+        user.key(),
+        user_shares,
+        bank.total_deposit_shares,
+        now,
+    )?;
+
+    let amount = book_withdrawal(bank, user, amount, now)?;
+
+    let token_account = payment_account(
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.wsol_account,
+        native,
+    )?;
+    let balance_before = ctx.accounts.bank_token_account.amount;
     let transfer_cpi_account = token_interface::TransferChecked {
-        from: ctx.accounts.bank.to_account_info(),
+        from: ctx.accounts.bank_token_account.to_account_info(),
         mint: ctx.accounts.mint.to_account_info(),
-        to: ctx.accounts.user.to_account_info(),
+        to: token_account.to_account_info(),
         authority: ctx.accounts.bank_token_account.to_account_info(),
     };
 
@@ -99,20 +167,74 @@ pub fn process_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
 
     token_interface::transfer_checked(cip_ctx, amount, ctx.accounts.mint.decimals)?;
 
-    let bank = ctx.accounts.bank.deref_mut();
-    let shares_to_remove = (amount / bank.total_deposits) * bank.total_deposit_shares;
+    // a Token-2022 transfer fee is withheld from the recipient, so the vault itself must
+    // release exactly what the book does
+    ctx.accounts.bank_token_account.reload()?;
+    require_eq!(
+        balance_before - ctx.accounts.bank_token_account.amount,
+        amount,
+        ErrorCode::TransferAmountMismatch
+    );
+    if native {
+        unwrap_sol(
+            &ctx.accounts.signer,
+            &ctx.accounts.mint,
+            token_account,
+            &ctx.accounts.token_program,
+        )?;
+    }
 
+    let bank = ctx.accounts.bank.deref_mut();
     let user = ctx.accounts.user.deref_mut();
 
-    if ctx.accounts.mint.key() == user.usdc_address {
-        user.deposited_usdc -= amount;
-        user.deposited_usdc_shares -= shares_to_remove;
+    // AMOUNT_ALL included: whatever is left has to carry the debt
+    let is_usdc = user.is_usdc(bank)?;
+    let other_bank = &mut ctx.accounts.other_bank;
+    other_bank.accrue_interest(now)?;
+    let withdraw_unit_value = unit_value(&ctx.accounts.price_update, &ctx.accounts.mint, is_usdc)?;
+    let other_unit_value = unit_value(
+        &ctx.accounts.other_price_update,
+        &ctx.accounts.other_mint,
+        !is_usdc,
+    )?;
+    user.check_borrow_limit([
+        (&*bank, withdraw_unit_value),
+        (&**other_bank, other_unit_value),
+    ])
+}
+
+/// Takes `amount` of the position's deposit off the books, or all of it for `AMOUNT_ALL`, and
+/// returns what has to leave the vault. The bank's interest and the position's rewards must be
+/// settled first; health is left to the caller.
+pub(crate) fn book_withdrawal(
+    bank: &mut Bank,
+    user: &mut User,
+    amount: u64,
+    now: i64,
+) -> Result<u64> {
+    let (user_shares, _) = user.shares(bank)?;
+    let deposited_value =
+        math::shares_to_amount(user_shares, bank.total_deposits, bank.total_deposit_shares, false)?;
+
+    // withdrawing everything pays out the rounded-down value and burns every share
+    let (amount, shares_to_remove) = if amount == constants::AMOUNT_ALL {
+        (deposited_value, user_shares)
     } else {
-        user.deposited_sol -= amount;
-        user.deposited_sol_shares -= shares_to_remove;
-    }
+        if deposited_value < amount {
+            return Err(ErrorCode::InsufficientFunds.into());
+        }
+        // round the burned shares up so a withdrawal can never take more than its shares are worth
+        let shares =
+            math::amount_to_shares(amount, bank.total_deposits, bank.total_deposit_shares, true)?;
+        require!(shares <= user_shares, ErrorCode::InsufficientFunds);
+        (amount, shares)
+    };
+    bank.record_outflow(amount, now)?;
+
+    user.remove_deposit(bank, amount, shares_to_remove)?;
 
     bank.total_deposits -= amount;
     bank.total_deposit_shares -= shares_to_remove;
-    Ok(())
+
+    Ok(amount)
 }
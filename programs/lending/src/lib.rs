@@ -1,10 +1,13 @@
 use anchor_lang::prelude::*;
 use instructions::*;
+use state::RewardSide;
 
-mod constants;
-mod error;
-mod instructions;
-mod state;
+pub mod constants;
+pub mod error;
+pub mod instructions;
+pub mod math;
+pub mod pda;
+pub mod state;
 
 declare_id!("9XCHC5dVRNSkZvmMNj9F9ZQXPfXYjD6BQH2trTtkqBs5");
 
@@ -17,31 +20,225 @@ pub mod lending {
         ctx: Context<InitBank>,
         liquidation_threshold: u64,
         max_ltv: u64,
+        allow_unsafe_mint: bool,
     ) -> Result<()> {
-        instructions::process_init_bank(ctx, liquidation_threshold, max_ltv)
+        instructions::process_init_bank(ctx, liquidation_threshold, max_ltv, allow_unsafe_mint)
     }
 
-    pub fn init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
-        instructions::process_init_user(ctx, usdc_address)
+    pub fn set_roles(
+        ctx: Context<SetRoles>,
+        risk_admin: Pubkey,
+        guardian: Pubkey,
+        fee_admin: Pubkey,
+        listing_admin: Pubkey,
+    ) -> Result<()> {
+        instructions::process_set_roles(ctx, risk_admin, guardian, fee_admin, listing_admin)
+    }
+
+    pub fn init_user(
+        ctx: Context<InitUser>,
+        usdc_address: Pubkey,
+        sol_address: Pubkey,
+        index: u8,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
+        instructions::process_init_user(ctx, usdc_address, sol_address, index, referrer)
     }
 
     pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        instructions::process_deposit(ctx, amount)
+        instructions::process_deposit(ctx, amount, false)
+    }
+
+    pub fn deposit_native(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        instructions::process_deposit(ctx, amount, true)
     }
 
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-        instructions::process_withdraw(ctx, amount)
+        instructions::process_withdraw(ctx, amount, false)
+    }
+
+    pub fn withdraw_native(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        instructions::process_withdraw(ctx, amount, true)
     }
 
     pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()> {
-        instructions::process_borrow(ctx, amount)
+        instructions::process_borrow(ctx, amount, false, false)
+    }
+
+    pub fn borrow_native(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+        instructions::process_borrow(ctx, amount, false, true)
+    }
+
+    pub fn borrow_stable(ctx: Context<Borrow>, amount: u64) -> Result<()> {
+        instructions::process_borrow(ctx, amount, true, false)
     }
 
     pub fn repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
-        instructions::process_repay(ctx, amount)
+        instructions::process_repay(ctx, amount, false, false)
+    }
+
+    pub fn repay_native(ctx: Context<Repay>, amount: u64) -> Result<()> {
+        instructions::process_repay(ctx, amount, false, true)
+    }
+
+    pub fn repay_stable(ctx: Context<Repay>, amount: u64) -> Result<()> {
+        instructions::process_repay(ctx, amount, true, false)
+    }
+
+    pub fn rebalance_stable_rate(ctx: Context<RebalanceStableRate>) -> Result<()> {
+        instructions::process_rebalance_stable_rate(ctx)
     }
 
     pub fn liquidate(ctx: Context<Liquidate>) -> Result<()> {
         instructions::process_liquidate(ctx)
     }
+
+    pub fn update_bank_config(ctx: Context<UpdateBank>, params: BankConfigParams) -> Result<()> {
+        instructions::process_update_bank_config(ctx, params)
+    }
+
+    pub fn set_paused(ctx: Context<UpdateBank>, paused: bool) -> Result<()> {
+        instructions::process_set_paused(ctx, paused)
+    }
+
+    pub fn transfer_authority(ctx: Context<UpdateBank>, new_authority: Pubkey) -> Result<()> {
+        instructions::process_transfer_authority(ctx, new_authority)
+    }
+
+    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+        instructions::process_collect_fees(ctx)
+    }
+
+    pub fn raise_outflow_limit(
+        ctx: Context<RaiseOutflowLimit>,
+        extra: u64,
+        duration: i64,
+    ) -> Result<()> {
+        instructions::process_raise_outflow_limit(ctx, extra, duration)
+    }
+
+    pub fn queue_bank_config(
+        ctx: Context<QueueBankConfig>,
+        params: BankConfigParams,
+    ) -> Result<()> {
+        instructions::process_queue_bank_config(ctx, params)
+    }
+
+    pub fn execute_bank_config(ctx: Context<ExecuteBankConfig>) -> Result<()> {
+        instructions::process_execute_bank_config(ctx)
+    }
+
+    pub fn cancel_bank_config(ctx: Context<CancelBankConfig>) -> Result<()> {
+        instructions::process_cancel_bank_config(ctx)
+    }
+
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+        instructions::process_claim_referral_fees(ctx)
+    }
+
+    pub fn configure_rewards(
+        ctx: Context<ConfigureRewards>,
+        side: RewardSide,
+        rate: u64,
+        end_time: i64,
+    ) -> Result<()> {
+        instructions::process_configure_rewards(ctx, side, rate, end_time)
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>, side: RewardSide) -> Result<()> {
+        instructions::process_claim_rewards(ctx, side)
+    }
+
+    pub fn create_term_pool(ctx: Context<CreateTermPool>, maturity: i64, rate: f64) -> Result<()> {
+        instructions::process_create_term_pool(ctx, maturity, rate)
+    }
+
+    pub fn lend_term(ctx: Context<LendTerm>, amount: u64) -> Result<()> {
+        instructions::process_lend_term(ctx, amount)
+    }
+
+    pub fn withdraw_term(ctx: Context<WithdrawTerm>) -> Result<()> {
+        instructions::process_withdraw_term(ctx)
+    }
+
+    pub fn borrow_term(ctx: Context<BorrowTerm>, amount: u64) -> Result<()> {
+        instructions::process_borrow_term(ctx, amount)
+    }
+
+    pub fn repay_term(ctx: Context<RepayTerm>, amount: u64) -> Result<()> {
+        instructions::process_repay_term(ctx, amount)
+    }
+
+    pub fn roll_term_loan(ctx: Context<RollTermLoan>) -> Result<()> {
+        instructions::process_roll_term_loan(ctx)
+    }
+
+    pub fn approve_delegation(ctx: Context<ApproveDelegation>, allowance: u64) -> Result<()> {
+        instructions::process_approve_delegation(ctx, allowance)
+    }
+
+    pub fn revoke_delegation(ctx: Context<RevokeDelegation>) -> Result<()> {
+        instructions::process_revoke_delegation(ctx)
+    }
+
+    pub fn transfer_collateral(ctx: Context<TransferCollateral>, amount: u64) -> Result<()> {
+        instructions::process_transfer_collateral(ctx, amount)
+    }
+
+    pub fn swap_collateral<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapCollateral<'info>>,
+        amount: u64,
+        min_amount_out: u64,
+        swap_data: Vec<u8>,
+    ) -> Result<()> {
+        instructions::process_swap_collateral(ctx, amount, min_amount_out, swap_data)
+    }
+
+    pub fn swap_debt<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapDebt<'info>>,
+        amount: u64,
+        min_amount_out: u64,
+        swap_data: Vec<u8>,
+    ) -> Result<()> {
+        instructions::process_swap_debt(ctx, amount, min_amount_out, swap_data)
+    }
+
+    pub fn open_leverage<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Leverage<'info>>,
+        amount: u64,
+        leverage_bps: u64,
+        min_amount_out: u64,
+        swap_data: Vec<u8>,
+    ) -> Result<()> {
+        instructions::process_open_leverage(ctx, amount, leverage_bps, min_amount_out, swap_data)
+    }
+
+    pub fn close_leverage<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Leverage<'info>>,
+        amount: u64,
+        min_amount_out: u64,
+        swap_data: Vec<u8>,
+    ) -> Result<()> {
+        instructions::process_close_leverage(ctx, amount, min_amount_out, swap_data)
+    }
+
+    pub fn migrate_bank(ctx: Context<MigrateBank>) -> Result<()> {
+        instructions::process_migrate_bank(ctx)
+    }
+
+    pub fn migrate_user(ctx: Context<MigrateUser>) -> Result<()> {
+        instructions::process_migrate_user(ctx)
+    }
+
+    pub fn upgrade_account(ctx: Context<UpgradeAccount>) -> Result<()> {
+        instructions::process_upgrade_account(ctx)
+    }
+
+    pub fn close_user(ctx: Context<CloseUser>) -> Result<()> {
+        instructions::process_close_user(ctx)
+    }
+
+    pub fn close_bank(ctx: Context<CloseBank>) -> Result<()> {
+        instructions::process_close_bank(ctx)
+    }
 }
@@ -0,0 +1,160 @@
+use std::f64::consts::E;
+
+use anchor_lang::prelude::*;
+
+use crate::{constants::SECONDS_PER_YEAR, error::ErrorCode};
+
+/// Converts a token amount into shares of a pool holding `total_amount` backed by `total_shares`.
+/// An empty pool mints shares one to one.
+pub fn amount_to_shares(
+    amount: u64,
+    total_amount: u64,
+    total_shares: u64,
+    round_up: bool,
+) -> Result<u64> {
+    if total_amount == 0 || total_shares == 0 {
+        return Ok(amount);
+    }
+    mul_div(amount, total_shares, total_amount, round_up)
+}
+
+/// Converts shares back into the token amount they are worth.
+pub fn shares_to_amount(
+    shares: u64,
+    total_amount: u64,
+    total_shares: u64,
+    round_up: bool,
+) -> Result<u64> {
+    if total_shares == 0 {
+        return Ok(0);
+    }
+    mul_div(shares, total_amount, total_shares, round_up)
+}
+
+/// Continuously compounds `amount` at the yearly `interest_rate` over `elapsed` seconds.
+pub fn accrue_interest(amount: u64, interest_rate: f64, elapsed: i64) -> Result<u64> {
+    if elapsed <= 0 {
+        return Ok(amount);
+    }
+    let growth = E.powf(interest_rate * elapsed as f64 / SECONDS_PER_YEAR as f64);
+    let new_value = amount as f64 * growth;
+    if !new_value.is_finite() || new_value > u64::MAX as f64 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+    Ok((new_value as u64).max(amount))
+}
+
+/// `percent` percent of `amount`, rounded down.
+pub fn percent_of(amount: u64, percent: u64) -> Result<u64> {
+    mul_div(amount, percent.min(100), 100, false)
+}
+
+/// `bps` basis points of `amount`, rounded up so fees never round to zero in the borrower's favor.
+pub fn bps_of(amount: u64, bps: u64) -> Result<u64> {
+    mul_div(amount, bps, 10_000, true)
+}
+
+/// Rate of two balances held together, weighted by amount. Zero when both are empty.
+pub fn weighted_rate(amount_a: u64, rate_a: f64, amount_b: u64, rate_b: f64) -> f64 {
+    let total = amount_a as f64 + amount_b as f64;
+    if total == 0.0 {
+        return 0.0;
+    }
+    (amount_a as f64 * rate_a + amount_b as f64 * rate_b) / total
+}
+
+/// What is left of `amount` after decaying linearly to zero over `window` seconds.
+pub fn linear_decay(amount: u64, elapsed: i64, window: i64) -> Result<u64> {
+    if elapsed <= 0 {
+        return Ok(amount);
+    }
+    if elapsed >= window {
+        return Ok(0);
+    }
+    mul_div(amount, (window - elapsed) as u64, window as u64, false)
+}
+
+/// USD value of `amount` base units of a token with `decimals`, priced at `price * 10^exponent`.
+pub fn usd_value(amount: u64, price: i64, exponent: i32, decimals: u8) -> f64 {
+    amount as f64 * price as f64 * 10f64.powi(exponent - decimals as i32)
+}
+
+/// Ratio of threshold-weighted collateral to debt; a position below 1.0 can be liquidated.
+pub fn health_factor(
+    collateral_value: f64,
+    liquidation_threshold: u64,
+    borrowed_value: f64,
+) -> f64 {
+    if borrowed_value <= 0.0 {
+        return f64::INFINITY;
+    }
+    collateral_value * liquidation_threshold as f64 / 100.0 / borrowed_value
+}
+
+/// Splits a liquidation into the debt a liquidator repays and the collateral they seize.
+///
+/// `debt_unit_value` and `collateral_unit_value` are the USD values of a single base unit.
+/// The seized amount includes the bonus and is capped at the position's collateral.
+pub fn liquidation_amounts(
+    debt: u64,
+    collateral: u64,
+    close_factor: u64,
+    liquidation_bonus: u64,
+    debt_unit_value: f64,
+    collateral_unit_value: f64,
+) -> Result<(u64, u64)> {
+    let repay_amount = percent_of(debt, close_factor)?;
+    let seize_amount = seize_amount(
+        repay_amount,
+        collateral,
+        liquidation_bonus,
+        debt_unit_value,
+        collateral_unit_value,
+    )?;
+    Ok((repay_amount, seize_amount))
+}
+
+/// Collateral owed to a liquidator for `repaid` units of debt, bonus included and capped
+/// at the position's collateral.
+pub fn seize_amount(
+    repaid: u64,
+    collateral: u64,
+    liquidation_bonus: u64,
+    debt_unit_value: f64,
+    collateral_unit_value: f64,
+) -> Result<u64> {
+    if collateral_unit_value <= 0.0 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+    let seize_value = repaid as f64 * debt_unit_value * (100 + liquidation_bonus) as f64 / 100.0;
+    let seize_amount = (seize_value / collateral_unit_value) as u64;
+    Ok(seize_amount.min(collateral))
+}
+
+/// Debt to borrow against `amount` of collateral so that, swapped into more collateral, the
+/// position holds `leverage_bps / 10_000` times the exposure of `amount` alone.
+pub fn leverage_borrow_amount(
+    amount: u64,
+    leverage_bps: u64,
+    collateral_unit_value: f64,
+    debt_unit_value: f64,
+) -> Result<u64> {
+    require!(leverage_bps > 10_000, ErrorCode::InvalidLeverage);
+    if debt_unit_value <= 0.0 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+    let borrow_value =
+        amount as f64 * collateral_unit_value * (leverage_bps - 10_000) as f64 / 10_000.0;
+    Ok((borrow_value / debt_unit_value) as u64)
+}
+
+fn mul_div(value: u64, numerator: u64, denominator: u64, round_up: bool) -> Result<u64> {
+    let product = value as u128 * numerator as u128;
+    let denominator = denominator as u128;
+    let result = if round_up {
+        product.div_ceil(denominator)
+    } else {
+        product / denominator
+    };
+    u64::try_from(result).map_err(|_| ErrorCode::MathOverflow.into())
+}
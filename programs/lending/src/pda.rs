@@ -0,0 +1,110 @@
+//! Program-derived addresses, for clients building instructions off-chain.
+//! Seeds must stay in step with the `seeds = [...]` constraints on the instructions.
+
+use anchor_lang::prelude::*;
+
+use crate::state::RewardSide;
+
+pub fn bank_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"bank", mint.as_ref()], &crate::ID).0
+}
+
+pub fn treasury_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"treasury", mint.as_ref()], &crate::ID).0
+}
+
+pub fn user_address(owner: &Pubkey, index: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"user", owner.as_ref(), &index.to_le_bytes()], &crate::ID).0
+}
+
+pub fn delegation_address(owner: &Pubkey, delegate: &Pubkey, mint: &Pubkey, index: u8) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"delegation",
+            owner.as_ref(),
+            delegate.as_ref(),
+            mint.as_ref(),
+            &index.to_le_bytes(),
+        ],
+        &crate::ID,
+    )
+    .0
+}
+
+pub fn referral_fees_address(referrer: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"referral", referrer.as_ref(), mint.as_ref()], &crate::ID).0
+}
+
+pub fn reward_pool_address(bank: &Pubkey, side: RewardSide) -> Pubkey {
+    Pubkey::find_program_address(&[b"rewards", bank.as_ref(), &[side as u8]], &crate::ID).0
+}
+
+pub fn reward_vault_address(reward_pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"reward_vault", reward_pool.as_ref()], &crate::ID).0
+}
+
+/// `user` is the position's `User` account, not its owner.
+pub fn reward_position_address(bank: &Pubkey, side: RewardSide, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"reward_position",
+            bank.as_ref(),
+            &[side as u8],
+            user.as_ref(),
+        ],
+        &crate::ID,
+    )
+    .0
+}
+
+/// The wrapped SOL account the `_native` instructions open and close for `signer`.
+pub fn wsol_address(signer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"wsol", signer.as_ref()], &crate::ID).0
+}
+
+pub fn term_pool_address(bank: &Pubkey, maturity: i64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"term_pool", bank.as_ref(), &maturity.to_le_bytes()],
+        &crate::ID,
+    )
+    .0
+}
+
+pub fn term_vault_address(term_pool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"term_vault", term_pool.as_ref()], &crate::ID).0
+}
+
+pub fn term_deposit_address(term_pool: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"term_deposit", term_pool.as_ref(), owner.as_ref()],
+        &crate::ID,
+    )
+    .0
+}
+
+/// `user` is the position's `User` account, not its owner.
+pub fn term_loan_address(term_pool: &Pubkey, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"term_loan", term_pool.as_ref(), user.as_ref()],
+        &crate::ID,
+    )
+    .0
+}
+
+pub fn roles_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"roles"], &crate::ID).0
+}
+
+pub fn pending_config_address(bank: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"pending_config", bank.as_ref()], &crate::ID).0
+}
+
+/// Unprefixed bank address used before seeds were namespaced; only `migrate_bank` reads it.
+pub fn legacy_bank_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[mint.as_ref()], &crate::ID).0
+}
+
+/// Unprefixed position address used before seeds were namespaced; only `migrate_user` reads it.
+pub fn legacy_user_address(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[owner.as_ref()], &crate::ID).0
+}
@@ -1,5 +1,7 @@
 use anchor_lang::prelude::*;
 
+use crate::{constants, error::ErrorCode, instructions::BankConfigParams, math};
+
 #[account]
 #[derive(InitSpace)]
 pub struct User {
@@ -15,6 +17,245 @@ pub struct User {
     pub usdc_address: Pubkey,
     pub last_updated: i64,
     pub last_updated_borrowed:i64,
+    /// Sub-account number; a wallet may open one position per index.
+    pub index: u8,
+    /// Layout version; see [`User::VERSION`].
+    pub version: u8,
+    /// Integrator credited with a share of this position's borrow fees; default for none.
+    pub referrer: Pubkey,
+    /// Stable-rate debt, compounded at its own locked rate rather than through shares.
+    pub stable_borrowed_sol: u64,
+    pub stable_sol_rate: f64,
+    pub stable_borrowed_usdc: u64,
+    pub stable_usdc_rate: f64,
+    /// When both stable balances were last compounded.
+    pub stable_updated: i64,
+    /// Owed across all of this position's term loans, interest to maturity included.
+    pub term_borrowed_sol: u64,
+    pub term_borrowed_usdc: u64,
+    /// Mint of the position's SOL bank, the only bank besides `usdc_address` it can hold.
+    pub sol_address: Pubkey,
+    /// Zeroed space for future fields, so small additions need no realloc.
+    pub reserved: [u8; 16],
+}
+
+impl User {
+    /// Bumped whenever fields are added. Layouts only ever grow at the end, so an older
+    /// account realloc'd by `upgrade_account` decodes with the new fields zeroed.
+    /// Version 2 carved the referrer out of reserved space, version 3 grew the layout
+    /// for stable-rate debt, version 4 carved out term debt and version 5 grew the layout
+    /// for the SOL mint, which `upgrade_account` asks the owner for.
+    pub const VERSION: u8 = 5;
+
+    /// Brings a decoded account of any older version up to [`User::VERSION`].
+    pub fn upgrade(&mut self) {
+        self.version = Self::VERSION;
+    }
+
+    /// Whether `bank` is the position's USDC or its SOL bank. Any other bank is rejected, so
+    /// shares booked in one can never be redeemed against another.
+    pub fn is_usdc(&self, bank: &Bank) -> Result<bool> {
+        match bank.mint_address {
+            mint if mint == self.usdc_address => Ok(true),
+            mint if mint == self.sol_address => Ok(false),
+            _ => err!(ErrorCode::UnknownBank),
+        }
+    }
+
+    /// Deposit and borrow shares held in `bank`.
+    pub fn shares(&self, bank: &Bank) -> Result<(u64, u64)> {
+        Ok(if self.is_usdc(bank)? {
+            (self.deposited_usdc_shares, self.borrowed_usdc_shares)
+        } else {
+            (self.deposited_sol_shares, self.borrowed_sol_shares)
+        })
+    }
+
+    /// Records `amount` of `bank`'s asset deposited as `shares`; bank totals are the caller's.
+    pub fn add_deposit(&mut self, bank: &Bank, amount: u64, shares: u64) -> Result<()> {
+        if self.is_usdc(bank)? {
+            self.deposited_usdc += amount;
+            self.deposited_usdc_shares += shares;
+        } else {
+            self.deposited_sol += amount;
+            self.deposited_sol_shares += shares;
+        }
+        Ok(())
+    }
+
+    pub fn remove_deposit(&mut self, bank: &Bank, amount: u64, shares: u64) -> Result<()> {
+        if self.is_usdc(bank)? {
+            self.deposited_usdc = self.deposited_usdc.saturating_sub(amount);
+            self.deposited_usdc_shares -= shares;
+        } else {
+            self.deposited_sol = self.deposited_sol.saturating_sub(amount);
+            self.deposited_sol_shares -= shares;
+        }
+        Ok(())
+    }
+
+    /// Records `amount` of variable debt in `bank`'s asset as `shares`; bank totals are the
+    /// caller's.
+    pub fn add_borrow(&mut self, bank: &Bank, amount: u64, shares: u64) -> Result<()> {
+        if self.is_usdc(bank)? {
+            self.borrowed_usdc += amount;
+            self.borrowed_usdc_shares += shares;
+        } else {
+            self.borrowed_sol += amount;
+            self.borrowed_sol_shares += shares;
+        }
+        Ok(())
+    }
+
+    pub fn remove_borrow(&mut self, bank: &Bank, amount: u64, shares: u64) -> Result<()> {
+        if self.is_usdc(bank)? {
+            self.borrowed_usdc = self.borrowed_usdc.saturating_sub(amount);
+            self.borrowed_usdc_shares -= shares;
+        } else {
+            self.borrowed_sol = self.borrowed_sol.saturating_sub(amount);
+            self.borrowed_sol_shares -= shares;
+        }
+        Ok(())
+    }
+
+    /// Stable debt in `bank`'s asset as of `stable_updated`, and the rate it is locked at.
+    pub fn stable_debt(&self, bank: &Bank) -> Result<(u64, f64)> {
+        Ok(if self.is_usdc(bank)? {
+            (self.stable_borrowed_usdc, self.stable_usdc_rate)
+        } else {
+            (self.stable_borrowed_sol, self.stable_sol_rate)
+        })
+    }
+
+    /// Owed on term loans in `bank`'s asset.
+    pub fn term_debt(&self, bank: &Bank) -> Result<u64> {
+        Ok(if self.is_usdc(bank)? {
+            self.term_borrowed_usdc
+        } else {
+            self.term_borrowed_sol
+        })
+    }
+
+    pub fn term_debt_mut(&mut self, bank: &Bank) -> Result<&mut u64> {
+        Ok(if self.is_usdc(bank)? {
+            &mut self.term_borrowed_usdc
+        } else {
+            &mut self.term_borrowed_sol
+        })
+    }
+
+    fn stable_debt_mut(&mut self, bank: &Bank) -> Result<(&mut u64, &mut f64)> {
+        Ok(if self.is_usdc(bank)? {
+            (&mut self.stable_borrowed_usdc, &mut self.stable_usdc_rate)
+        } else {
+            (&mut self.stable_borrowed_sol, &mut self.stable_sol_rate)
+        })
+    }
+
+    /// Compounds both stable balances, each at its own locked rate, up to `now`.
+    pub fn accrue_stable(&mut self, now: i64) -> Result<()> {
+        let elapsed = now - self.stable_updated;
+        self.stable_borrowed_sol =
+            math::accrue_interest(self.stable_borrowed_sol, self.stable_sol_rate, elapsed)?;
+        self.stable_borrowed_usdc =
+            math::accrue_interest(self.stable_borrowed_usdc, self.stable_usdc_rate, elapsed)?;
+        self.stable_updated = now;
+        Ok(())
+    }
+
+    /// Adds `amount` of stable debt at `bank`'s current stable rate. Debt already held is
+    /// not re-priced; the position's rate becomes the amount-weighted blend of the two.
+    pub fn add_stable_debt(&mut self, bank: &mut Bank, amount: u64, now: i64) -> Result<()> {
+        self.accrue_stable(now)?;
+        let rate = bank.stable_rate();
+        let (balance, position_rate) = self.stable_debt_mut(bank)?;
+        *position_rate = math::weighted_rate(*balance, *position_rate, amount, rate);
+        *balance = balance.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        bank.add_stable_debt(amount, rate)
+    }
+
+    /// Pays `amount` off the stable debt in `bank`'s asset, which must already be accrued.
+    pub fn remove_stable_debt(&mut self, bank: &mut Bank, amount: u64) -> Result<()> {
+        let (balance, rate) = self.stable_debt_mut(bank)?;
+        let locked = *rate;
+        *balance = balance.checked_sub(amount).ok_or(ErrorCode::OverRepay)?;
+        if *balance == 0 {
+            *rate = 0.0;
+        }
+        bank.remove_stable_debt(amount, locked);
+        Ok(())
+    }
+
+    /// Moves the stable debt in `bank`'s asset onto the bank's current stable rate.
+    pub fn reprice_stable_debt(&mut self, bank: &mut Bank, now: i64) -> Result<()> {
+        self.accrue_stable(now)?;
+        let (balance, rate) = self.stable_debt(bank)?;
+        bank.remove_stable_debt(balance, rate);
+        let new_rate = bank.stable_rate();
+        *self.stable_debt_mut(bank)?.1 = new_rate;
+        bank.add_stable_debt(balance, new_rate)
+    }
+
+    /// Deposited and borrowed amounts of `bank`'s asset, valued from shares in the
+    /// protocol's favor. Borrowed includes stable and term debt.
+    pub fn balances(&self, bank: &Bank) -> Result<(u64, u64)> {
+        let (deposit_shares, borrow_shares) = self.shares(bank)?;
+        let deposited = math::shares_to_amount(
+            deposit_shares,
+            bank.total_deposits,
+            bank.total_deposit_shares,
+            false,
+        )?;
+        let borrowed = math::shares_to_amount(
+            borrow_shares,
+            bank.total_borrowed,
+            bank.total_borrowed_shares,
+            true,
+        )?;
+        let (stable, term) = (self.stable_debt(bank)?.0, self.term_debt(bank)?);
+        let borrowed = borrowed
+            .checked_add(stable)
+            .and_then(|borrowed| borrowed.checked_add(term))
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok((deposited, borrowed))
+    }
+
+    /// Requires collateral, weighted by each bank's `max_ltv`, to cover all debt. Each bank is
+    /// paired with the USD value of one base unit of its asset, and the two must be the
+    /// position's USDC and SOL banks.
+    pub fn check_borrow_limit(&self, banks: [(&Bank, f64); 2]) -> Result<()> {
+        let (capacity, debt) = self.weighted_values(banks, |bank| bank.max_ltv)?;
+        require!(capacity >= debt, ErrorCode::PositionUnhealthy);
+        Ok(())
+    }
+
+    /// Collateral weighted by each bank's `liquidation_threshold` over all debt, across both
+    /// banks as in [`User::check_borrow_limit`]. Below 1.0 the position can be liquidated.
+    pub fn health_factor(&self, banks: [(&Bank, f64); 2]) -> Result<f64> {
+        let (capacity, debt) = self.weighted_values(banks, |bank| bank.liquidation_threshold)?;
+        Ok(math::health_factor(capacity, 100, debt))
+    }
+
+    /// USD value of the deposits in both banks, each weighted by `weight` percent of its bank,
+    /// and of all debt.
+    fn weighted_values(
+        &self,
+        banks: [(&Bank, f64); 2],
+        weight: impl Fn(&Bank) -> u64,
+    ) -> Result<(f64, f64)> {
+        require!(
+            self.is_usdc(banks[0].0)? != self.is_usdc(banks[1].0)?,
+            ErrorCode::UnknownBank
+        );
+        let mut capacity = 0.0;
+        let mut debt = 0.0;
+        for (bank, unit_value) in banks {
+            let (deposited, borrowed) = self.balances(bank)?;
+            capacity += deposited as f64 * unit_value * weight(bank) as f64 / 100.0;
+            debt += borrowed as f64 * unit_value;
+        }
+        Ok((capacity, debt))
+    }
 }
 
 #[account]
@@ -32,4 +273,439 @@ pub struct Bank {
     pub max_ltv : u64,
     pub last_updated : i64,
     pub instrest_rate : f64,
+    pub paused : bool,
+    pub reserve_factor : u64,
+    pub protocol_fees : u64,
+    /// Set when the bank was created over a mint that failed the extension checks.
+    pub unsafe_mint_allowed : bool,
+    /// Layout version; see [`Bank::VERSION`].
+    pub version : u8,
+    /// May temporarily raise the outflow limit.
+    pub guardian : Pubkey,
+    /// Seconds over which tracked outflow drains back to zero; 0 disables the limiter.
+    pub outflow_window : i64,
+    /// Cap on net outflow per window in tokens; 0 for none.
+    pub outflow_limit : u64,
+    /// Cap on net outflow per window as a percent of deposits; 0 for none.
+    pub outflow_limit_percent : u64,
+    /// Net outflow still counted against the window as of `outflow_updated`.
+    pub window_outflow : u64,
+    pub outflow_updated : i64,
+    /// Extra allowance granted by the guardian until `outflow_boost_expires_at`.
+    pub outflow_boost : u64,
+    pub outflow_boost_expires_at : i64,
+    /// Seconds a config change must stay queued before it can be executed; 0 applies
+    /// changes immediately.
+    pub config_delay : i64,
+    /// Origination fee added to every borrow's debt, in basis points.
+    pub borrow_fee_bps : u64,
+    /// Percent of the origination fee paid out to a host account passed with the borrow.
+    pub host_fee_percent : u64,
+    /// Percent of the protocol's share of a borrow fee owed to the position's referrer.
+    pub referral_fee_percent : u64,
+    /// Set once a reward pool exists for deposits; balance changes must then settle it.
+    pub deposit_rewards : bool,
+    /// Set once a reward pool exists for borrows; balance changes must then settle it.
+    pub borrow_rewards : bool,
+    /// Whether positions may lock in a stable rate when they borrow.
+    pub stable_borrow_enabled : bool,
+    /// Added to the variable rate to get the rate a stable borrow locks in.
+    pub stable_rate_premium : f64,
+    /// Utilization percent at which anyone may re-price stable debt; 0 disables rebalancing.
+    pub stable_rebalance_utilization : u64,
+    /// Outstanding stable debt, tracked apart from the share-based variable debt.
+    pub total_stable_borrowed : u64,
+    /// Amount-weighted rate of the outstanding stable debt.
+    pub average_stable_rate : f64,
+    /// Lenders' claim on the bank's term pools, locked until each pool matures.
+    pub total_term_lent : u64,
+    /// Owed on term loans, interest to maturity included.
+    pub total_term_borrowed : u64,
+    /// Credited to referrers out of borrow fees and not yet claimed.
+    pub referral_fees_owed : u64,
+    /// Zeroed space for future fields, so small additions need no realloc.
+    pub reserved : [u8; 40],
+}
+
+/// Borrow fees owed to `referrer` in `mint`, paid out by `claim_referral_fees`.
+#[account]
+#[derive(InitSpace)]
+pub struct ReferralFees {
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub owed: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum RewardSide {
+    Deposit,
+    Borrow,
+}
+
+/// Emits `rate` units of `reward_mint` per second until `end_time`, split across every share
+/// on one side of a bank. Paid out of the pool's reward vault.
+#[account]
+#[derive(InitSpace)]
+pub struct RewardPool {
+    pub bank: Pubkey,
+    pub side: RewardSide,
+    pub reward_mint: Pubkey,
+    pub rate: u64,
+    pub end_time: i64,
+    pub last_updated: i64,
+    /// Rewards emitted per share so far, scaled by `REWARD_INDEX_SCALE`.
+    pub index: u128,
+}
+
+impl RewardPool {
+    /// Emits up to `now` across `total_shares`. Time with no shares outstanding emits nothing.
+    pub fn accrue(&mut self, total_shares: u64, now: i64) -> Result<()> {
+        let until = now.min(self.end_time);
+        if until > self.last_updated && total_shares > 0 {
+            let emitted = self.rate as u128 * (until - self.last_updated) as u128;
+            let per_share = emitted
+                .checked_mul(constants::REWARD_INDEX_SCALE)
+                .ok_or(ErrorCode::MathOverflow)?
+                / total_shares as u128;
+            self.index = self
+                .index
+                .checked_add(per_share)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        self.last_updated = self.last_updated.max(now);
+        Ok(())
+    }
+}
+
+/// One position's claim on a reward pool. Liquidations move shares without settling, which
+/// can only cost the liquidated position part of its rewards. A position earns from the
+/// settlement that opens it, so shares it already held earn nothing before then.
+#[account]
+#[derive(InitSpace)]
+pub struct RewardPosition {
+    pub bank: Pubkey,
+    pub side: RewardSide,
+    /// The `User` account whose shares earn these rewards.
+    pub user: Pubkey,
+    /// Pool index as of the last settlement.
+    pub index: u128,
+    /// Settled and not yet claimed.
+    pub accrued: u64,
+}
+
+impl RewardPosition {
+    /// Credits what `shares` earned since the last settlement, rounded down.
+    pub fn settle(&mut self, pool: &RewardPool, user: Pubkey, shares: u64) -> Result<()> {
+        if self.user == Pubkey::default() {
+            self.index = pool.index;
+        }
+        let earned = (shares as u128)
+            .checked_mul(pool.index - self.index)
+            .ok_or(ErrorCode::MathOverflow)?
+            / constants::REWARD_INDEX_SCALE;
+        self.accrued = u64::try_from(earned)
+            .ok()
+            .and_then(|earned| self.accrued.checked_add(earned))
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.index = pool.index;
+        self.bank = pool.bank;
+        self.side = pool.side;
+        self.user = user;
+        Ok(())
+    }
+}
+
+/// Fixed-rate lending in a bank's asset until `maturity`. Lenders' tokens sit in the pool's
+/// own vault, apart from the treasury, and stay locked until the pool matures, then come out
+/// pro rata as loans are settled; borrowers owe interest to maturity from the moment they draw.
+#[account]
+#[derive(InitSpace)]
+pub struct TermPool {
+    pub bank: Pubkey,
+    pub maturity: i64,
+    /// Yearly rate, compounded continuously like variable debt.
+    pub rate: f64,
+    /// Lenders' claim: what they put in plus interest booked on loans, less withdrawals.
+    pub total_value: u64,
+    pub total_shares: u64,
+    /// Lent and not yet drawn by borrowers.
+    pub available: u64,
+    /// Still owed by borrowers.
+    pub outstanding: u64,
+}
+
+/// A lender's shares of a term pool.
+#[account]
+#[derive(InitSpace)]
+pub struct TermDeposit {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub shares: u64,
+}
+
+/// What one position owes a term pool. Repaying early does not reduce the interest.
+#[account]
+#[derive(InitSpace)]
+pub struct TermLoan {
+    pub pool: Pubkey,
+    /// The `User` account that owes it.
+    pub user: Pubkey,
+    pub owed: u64,
+}
+
+/// Keys the bank authority appoints to run its banks day to day, so the authority key itself
+/// can stay cold. The authority keeps every power; each role grants only its own.
+#[account]
+#[derive(InitSpace)]
+pub struct Roles {
+    /// Market authority: lists banks, owns those it lists and is the only one these roles
+    /// apply to. Set once, by whoever creates the account.
+    pub authority: Pubkey,
+    /// Changes risk and rate parameters, queues and cancels timelocked changes, unpauses.
+    pub risk_admin: Pubkey,
+    /// Pauses, makes risk-reducing changes and temporarily raises outflow limits.
+    pub guardian: Pubkey,
+    /// Collects protocol fees.
+    pub fee_admin: Pubkey,
+    /// Lists and delists banks.
+    pub listing_admin: Pubkey,
+    /// Zeroed space for future roles.
+    pub reserved: [u8; 64],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Risk,
+    Guardian,
+    Fee,
+    Listing,
+}
+
+impl Roles {
+    pub fn holder(&self, role: Role) -> Pubkey {
+        match role {
+            Role::Risk => self.risk_admin,
+            Role::Guardian => self.guardian,
+            Role::Fee => self.fee_admin,
+            Role::Listing => self.listing_admin,
+        }
+    }
+
+    /// Passes for the authority or for a holder of any of `allowed`.
+    pub fn authorize(&self, signer: &Pubkey, allowed: &[Role]) -> Result<()> {
+        require!(
+            *signer == self.authority || allowed.iter().any(|role| self.holder(*role) == *signer),
+            ErrorCode::Unauthorized
+        );
+        Ok(())
+    }
+}
+
+/// A config change queued by the bank authority. Anyone may execute it once
+/// `execute_after` has passed; until then the authority may cancel it.
+#[account]
+#[derive(InitSpace)]
+pub struct PendingConfig {
+    pub bank: Pubkey,
+    /// Paid the rent and gets it back when the change is executed or cancelled.
+    pub payer: Pubkey,
+    pub params: BankConfigParams,
+    pub execute_after: i64,
+}
+
+/// Lets `delegate` borrow up to `allowance` of `mint` against the collateral of `owner`'s
+/// sub-account `index`. The debt stays on that position.
+#[account]
+#[derive(InitSpace)]
+pub struct Delegation {
+    pub owner: Pubkey,
+    pub index: u8,
+    pub delegate: Pubkey,
+    pub mint: Pubkey,
+    pub allowance: u64,
+}
+
+impl Bank {
+    /// Bumped whenever fields are added; see [`User::VERSION`].
+    /// Version 2 carved the outflow limiter out of reserved space, where zero means disabled,
+    /// version 3 the config delay, where zero means no timelock, version 4 the borrow fee,
+    /// version 5 the referral share and version 6 the reward flags. Version 7 grew the layout
+    /// for stable-rate debt, version 8 carved out term totals and version 9 unclaimed referral
+    /// fees.
+    pub const VERSION: u8 = 9;
+
+    /// Brings a decoded account of any older version up to [`Bank::VERSION`].
+    pub fn upgrade(&mut self) {
+        self.version = Self::VERSION;
+    }
+
+    /// Passes for the bank authority, or for a holder of any of `allowed` in the market's
+    /// roles account when one is passed and the bank still belongs to the market authority.
+    pub fn authorize(
+        &self,
+        roles: Option<&Roles>,
+        signer: &Pubkey,
+        allowed: &[Role],
+    ) -> Result<()> {
+        match roles {
+            Some(roles) if roles.authority == self.authority => roles.authorize(signer, allowed),
+            _ => {
+                require_keys_eq!(*signer, self.authority, ErrorCode::Unauthorized);
+                Ok(())
+            }
+        }
+    }
+
+    /// Percent parameters must stay within 100 and borrowing must stop before liquidation starts.
+    pub fn validate_config(&self) -> Result<()> {
+        require!(
+            self.liquidation_threshold <= 100
+                && self.max_ltv <= self.liquidation_threshold
+                && self.liquidation_close_factor <= 100
+                && self.reserve_factor <= 100
+                && self.instrest_rate >= 0.0
+                && self.instrest_rate.is_finite()
+                && self.outflow_window >= 0
+                && self.outflow_limit_percent <= 100
+                && (0..=constants::MAX_CONFIG_DELAY).contains(&self.config_delay)
+                && self.borrow_fee_bps <= constants::MAX_BORROW_FEE_BPS
+                && self.host_fee_percent <= 100
+                && self.referral_fee_percent <= 100
+                && self.stable_rate_premium >= 0.0
+                && self.stable_rate_premium.is_finite()
+                && self.stable_rebalance_utilization <= 100,
+            ErrorCode::InvalidConfig
+        );
+        Ok(())
+    }
+
+    /// Yearly rate a stable borrow locks in at the moment.
+    pub fn stable_rate(&self) -> f64 {
+        self.instrest_rate + self.stable_rate_premium
+    }
+
+    /// Percent of deposits lent out, counting variable and stable debt.
+    pub fn utilization(&self) -> u64 {
+        let borrowed = self.total_borrowed as u128 + self.total_stable_borrowed as u128;
+        match self.total_deposits {
+            0 if borrowed == 0 => 0,
+            0 => 100,
+            deposits => (borrowed * 100 / deposits as u128) as u64,
+        }
+    }
+
+    /// Whether utilization has reached the point where stable debt may be re-priced.
+    pub fn stable_rebalance_allowed(&self) -> bool {
+        self.stable_rebalance_utilization > 0
+            && self.utilization() >= self.stable_rebalance_utilization
+    }
+
+    /// Counts `amount` of new stable debt locked at `rate`.
+    pub fn add_stable_debt(&mut self, amount: u64, rate: f64) -> Result<()> {
+        self.average_stable_rate = math::weighted_rate(
+            self.total_stable_borrowed,
+            self.average_stable_rate,
+            amount,
+            rate,
+        );
+        self.total_stable_borrowed = self
+            .total_stable_borrowed
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Drops `amount` of stable debt locked at `rate`. Positions compound at their own rates
+    /// and the total at the average, so the two can drift by rounding; the total never goes
+    /// below zero.
+    pub fn remove_stable_debt(&mut self, amount: u64, rate: f64) {
+        let remaining = self.total_stable_borrowed.saturating_sub(amount);
+        self.average_stable_rate = if remaining == 0 {
+            0.0
+        } else {
+            let weighted =
+                self.average_stable_rate * self.total_stable_borrowed as f64 - rate * amount as f64;
+            (weighted / remaining as f64).max(0.0)
+        };
+        self.total_stable_borrowed = remaining;
+    }
+
+    /// Most that may leave the vault in the current window, or `None` when unlimited.
+    pub fn outflow_cap(&self, now: i64) -> Result<Option<u64>> {
+        if self.outflow_window == 0 {
+            return Ok(None);
+        }
+        let by_percent = (self.outflow_limit_percent > 0)
+            .then(|| math::percent_of(self.total_deposits, self.outflow_limit_percent))
+            .transpose()?;
+        let by_tokens = (self.outflow_limit > 0).then_some(self.outflow_limit);
+        let cap = match (by_tokens, by_percent) {
+            (Some(tokens), Some(percent)) => tokens.min(percent),
+            (cap, None) | (None, cap) => cap,
+        };
+        let boost = if now < self.outflow_boost_expires_at {
+            self.outflow_boost
+        } else {
+            0
+        };
+        Ok(cap.map(|cap| cap.saturating_add(boost)))
+    }
+
+    /// Counts `amount` leaving the vault against the window, failing past the cap.
+    pub fn record_outflow(&mut self, amount: u64, now: i64) -> Result<()> {
+        self.decay_outflow(now)?;
+        let outflow = self.window_outflow.saturating_add(amount);
+        if let Some(cap) = self.outflow_cap(now)? {
+            require!(outflow <= cap, ErrorCode::OutflowLimitExceeded);
+        }
+        self.window_outflow = outflow;
+        Ok(())
+    }
+
+    /// Offsets earlier outflow with `amount` coming back in, so the limiter tracks net flow.
+    pub fn record_inflow(&mut self, amount: u64, now: i64) -> Result<()> {
+        self.decay_outflow(now)?;
+        self.window_outflow = self.window_outflow.saturating_sub(amount);
+        Ok(())
+    }
+
+    fn decay_outflow(&mut self, now: i64) -> Result<()> {
+        self.window_outflow = math::linear_decay(
+            self.window_outflow,
+            now - self.outflow_updated,
+            self.outflow_window,
+        )?;
+        self.outflow_updated = now;
+        Ok(())
+    }
+
+    /// Compounds outstanding variable and stable debt up to `now`, keeps `reserve_factor`
+    /// percent of the interest as protocol fees and credits the rest to depositors.
+    pub fn accrue_interest(&mut self, now: i64) -> Result<()> {
+        let elapsed = now - self.last_updated;
+        if elapsed <= 0 {
+            return Ok(());
+        }
+        let total_borrowed = math::accrue_interest(self.total_borrowed, self.instrest_rate, elapsed)?;
+        let total_stable_borrowed = math::accrue_interest(
+            self.total_stable_borrowed,
+            self.average_stable_rate,
+            elapsed,
+        )?;
+        let interest = (total_borrowed - self.total_borrowed)
+            + (total_stable_borrowed - self.total_stable_borrowed);
+        let fees = math::percent_of(interest, self.reserve_factor)?;
+        self.total_borrowed = total_borrowed;
+        self.total_stable_borrowed = total_stable_borrowed;
+        self.total_deposits = self
+            .total_deposits
+            .checked_add(interest - fees)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.protocol_fees = self
+            .protocol_fees
+            .checked_add(fees)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.last_updated = now;
+        Ok(())
+    }
 }
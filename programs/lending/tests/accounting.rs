@@ -0,0 +1,297 @@
+//! Property tests for share and interest accounting.
+//!
+//! Random sequences of deposits, withdrawals, borrows, repayments, liquidations,
+//! price moves and clock jumps are replayed against the compiled program, and the
+//! bank invariants are checked after every step. Individual operations are allowed
+//! to fail; the invariants must hold regardless.
+
+mod common;
+
+use common::*;
+use lending::{
+    math,
+    state::{Bank, User},
+};
+use proptest::prelude::*;
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{signature::Keypair, signer::Signer};
+
+const USERS: usize = 3;
+const STARTING_BALANCE: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Deposit {
+        user: usize,
+        usdc: bool,
+        amount: u64,
+    },
+    Withdraw {
+        user: usize,
+        usdc: bool,
+        amount: u64,
+    },
+    Borrow {
+        user: usize,
+        amount: u64,
+    },
+    Repay {
+        user: usize,
+        amount: u64,
+    },
+    Liquidate {
+        user: usize,
+    },
+    SetSolPrice {
+        price: i64,
+    },
+    Jump {
+        seconds: i64,
+    },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let user = 0..USERS;
+    let amount = prop_oneof![1..1_000u64, 1..STARTING_BALANCE];
+    prop_oneof![
+        (user.clone(), any::<bool>(), amount.clone())
+            .prop_map(|(user, usdc, amount)| Op::Deposit { user, usdc, amount }),
+        (user.clone(), any::<bool>(), amount.clone())
+            .prop_map(|(user, usdc, amount)| Op::Withdraw { user, usdc, amount }),
+        (user.clone(), amount.clone()).prop_map(|(user, amount)| Op::Borrow { user, amount }),
+        (user.clone(), amount).prop_map(|(user, amount)| Op::Repay { user, amount }),
+        user.prop_map(|user| Op::Liquidate { user }),
+        (1..1_000i64).prop_map(|price| Op::SetSolPrice { price }),
+        (1..30 * 24 * 60 * 60i64).prop_map(|seconds| Op::Jump { seconds }),
+    ]
+}
+
+#[derive(Default, Clone, Copy)]
+struct Flows {
+    deposited: u64,
+    withdrawn: u64,
+    seized: u64,
+}
+
+struct Harness {
+    ctx: ProgramTestContext,
+    market: Market,
+    users: Vec<Keypair>,
+    sol_price: i64,
+    // per user, indexed by `usdc as usize`; liquidations only ever seize usdc here
+    flows: Vec<[Flows; 2]>,
+}
+
+impl Harness {
+    async fn new() -> Self {
+        let mut ctx = start().await;
+        let market = setup_market_with_liquidity(&mut ctx, 0).await;
+        let mut users = Vec::with_capacity(USERS);
+        for _ in 0..USERS {
+            users.push(setup_user(&mut ctx, &market, STARTING_BALANCE, STARTING_BALANCE).await);
+        }
+        Self {
+            ctx,
+            market,
+            users,
+            sol_price: 100,
+            flows: vec![[Flows::default(); 2]; USERS],
+        }
+    }
+
+    fn mint(&self, usdc: bool) -> solana_sdk::pubkey::Pubkey {
+        if usdc {
+            self.market.usdc_mint
+        } else {
+            self.market.sol_mint
+        }
+    }
+
+    async fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::Deposit { user, usdc, amount } => {
+                let mint = self.mint(usdc);
+                let bank_before: Bank = fetch(&mut self.ctx, &bank_pda(&mint)).await;
+                let shares_before = self.deposit_shares(user, usdc).await;
+                let signer = &self.users[user];
+                let ix = deposit_ix(&signer.pubkey(), &mint, amount);
+                if send(&mut self.ctx, &[ix], &[signer]).await.is_ok() {
+                    self.flows[user][usdc as usize].deposited += amount;
+                    let minted = self.deposit_shares(user, usdc).await - shares_before;
+                    // minted shares can never be worth more than the tokens paid in
+                    let bank_after: Bank = fetch(&mut self.ctx, &bank_pda(&mint)).await;
+                    let worth = math::shares_to_amount(
+                        minted,
+                        bank_after.total_deposits,
+                        bank_after.total_deposit_shares,
+                        false,
+                    )
+                    .unwrap();
+                    assert!(
+                        worth <= amount,
+                        "deposit of {amount} minted shares worth {worth}"
+                    );
+                    assert!(bank_after.total_deposits >= bank_before.total_deposits + amount);
+                }
+            }
+            Op::Withdraw { user, usdc, amount } => {
+                let mint = self.mint(usdc);
+                let signer = &self.users[user];
+                let ix = withdraw_ix(&signer.pubkey(), &mint, &self.market, amount);
+                if send(&mut self.ctx, &[ix], &[signer]).await.is_ok() {
+                    self.flows[user][usdc as usize].withdrawn += amount;
+                }
+            }
+            Op::Borrow { user, amount } => {
+                let signer = &self.users[user];
+                let ix = borrow_ix(
+                    &signer.pubkey(),
+                    &self.market.sol_mint,
+                    &self.market,
+                    amount,
+                );
+                let _ = send(&mut self.ctx, &[ix], &[signer]).await;
+            }
+            Op::Repay { user, amount } => {
+                let signer = &self.users[user];
+                let ix = repay_ix(&signer.pubkey(), &self.market.sol_mint, amount);
+                let _ = send(&mut self.ctx, &[ix], &[signer]).await;
+            }
+            Op::Liquidate { user } => {
+                let signer = &self.users[(user + 1) % USERS];
+                let collateral_vault = treasury_pda(&self.market.usdc_mint);
+                let vault_before = token_balance(&mut self.ctx, &collateral_vault).await;
+                let ix = liquidate_ix(
+                    &signer.pubkey(),
+                    &self.users[user].pubkey(),
+                    &self.market.usdc_mint,
+                    &self.market.sol_mint,
+                    &self.market.usdc_price,
+                    &self.market.sol_price,
+                );
+                if send(&mut self.ctx, &[ix], &[signer]).await.is_ok() {
+                    let vault_after = token_balance(&mut self.ctx, &collateral_vault).await;
+                    self.flows[user][1].seized += vault_before - vault_after;
+                }
+            }
+            Op::SetSolPrice { price } => {
+                self.sol_price = price;
+                refresh_prices(&mut self.ctx, &self.market, 1, price).await;
+            }
+            Op::Jump { seconds } => {
+                advance_clock(&mut self.ctx, seconds).await;
+                refresh_prices(&mut self.ctx, &self.market, 1, self.sol_price).await;
+            }
+        }
+    }
+
+    async fn deposit_shares(&mut self, user: usize, usdc: bool) -> u64 {
+        let position: User = fetch(&mut self.ctx, &user_pda(&self.users[user].pubkey())).await;
+        if usdc {
+            position.deposited_usdc_shares
+        } else {
+            position.deposited_sol_shares
+        }
+    }
+
+    async fn check_invariants(&mut self) {
+        for usdc in [true, false] {
+            let mint = self.mint(usdc);
+            let bank: Bank = fetch(&mut self.ctx, &bank_pda(&mint)).await;
+            let vault = token_balance(&mut self.ctx, &treasury_pda(&mint)).await;
+
+            assert!(
+                vault >= bank.total_deposits.saturating_sub(bank.total_borrowed),
+                "vault {vault} below deposits {} - borrows {}",
+                bank.total_deposits,
+                bank.total_borrowed
+            );
+
+            let mut deposit_shares = 0u64;
+            let mut borrow_shares = 0u64;
+            for user in &self.users {
+                let position: User = fetch(&mut self.ctx, &user_pda(&user.pubkey())).await;
+                if usdc {
+                    deposit_shares += position.deposited_usdc_shares;
+                    borrow_shares += position.borrowed_usdc_shares;
+                } else {
+                    deposit_shares += position.deposited_sol_shares;
+                    borrow_shares += position.borrowed_sol_shares;
+                }
+            }
+            assert_eq!(
+                deposit_shares, bank.total_deposit_shares,
+                "deposit share inflation"
+            );
+            assert_eq!(
+                borrow_shares, bank.total_borrowed_shares,
+                "borrow share drift"
+            );
+
+            // nobody can take out more than they paid in plus the interest the bank has earned
+            let net_deposits: u64 = self
+                .flows
+                .iter()
+                .map(|flows| flows[usdc as usize])
+                .map(|f| f.deposited.saturating_sub(f.withdrawn + f.seized))
+                .sum();
+            let earned = bank.total_deposits.saturating_sub(net_deposits);
+            for (index, flows) in self.flows.iter().enumerate() {
+                let flows = flows[usdc as usize];
+                assert!(
+                    flows.withdrawn <= flows.deposited + earned,
+                    "user {index} withdrew {} after depositing {} with {earned} interest",
+                    flows.withdrawn,
+                    flows.deposited
+                );
+            }
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 16, ..ProptestConfig::default() })]
+
+    #[test]
+    fn accounting_invariants_hold(ops in prop::collection::vec(op(), 1..24)) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut harness = Harness::new().await;
+            for op in &ops {
+                harness.apply(op).await;
+                harness.check_invariants().await;
+            }
+        });
+    }
+
+    #[test]
+    fn share_round_trip_never_creates_value(
+        amount in 0..u64::MAX / 4,
+        total_amount in 1..u64::MAX / 4,
+        total_shares in 1..u64::MAX / 4,
+    ) {
+        let shares = math::amount_to_shares(amount, total_amount, total_shares, false).unwrap_or(0);
+        if let Ok(back) = math::shares_to_amount(shares, total_amount, total_shares, false) {
+            prop_assert!(back <= amount);
+        }
+        if let Ok(burned) = math::amount_to_shares(amount, total_amount, total_shares, true) {
+            if let Ok(worth) = math::shares_to_amount(burned, total_amount, total_shares, true) {
+                prop_assert!(worth >= amount);
+            }
+        }
+    }
+
+    #[test]
+    fn interest_never_decreases_balance(
+        amount in 0..u64::MAX / 2,
+        rate in 0.0..1.0f64,
+        elapsed in 0..10 * 365 * 24 * 60 * 60i64,
+    ) {
+        if let Ok(grown) = math::accrue_interest(amount, rate, elapsed) {
+            prop_assert!(grown >= amount);
+        }
+    }
+}
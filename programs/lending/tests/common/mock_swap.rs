@@ -0,0 +1,87 @@
+//! A constant-rate AMM standing in for a real swap program.
+//!
+//! It takes `amount_in` from the user and pays back `amount_in * numerator / denominator`
+//! from a pool owned by the PDA `[b"pool"]`, so tests pick the exchange rate per swap.
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{
+        account_info::AccountInfo,
+        entrypoint::ProgramResult,
+        instruction::AccountMeta,
+        program::{invoke, invoke_signed},
+        program_error::ProgramError,
+    },
+};
+use anchor_spl::{associated_token::get_associated_token_address, token::spl_token};
+
+pub const ID: Pubkey = Pubkey::new_from_array([7; 32]);
+
+pub fn pool_authority() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"pool"], &ID)
+}
+
+/// Accounts: user, user input, pool input, pool output, user output, pool authority,
+/// token program. Data: `amount_in`, `numerator`, `denominator`, each a little-endian u64.
+pub fn process_instruction(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let [user, user_in, pool_in, pool_out, user_out, authority, token_program] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    if data.len() != 24 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let read = |i: usize| u64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap());
+    let (amount_in, numerator, denominator) = (read(0), read(1), read(2));
+    let amount_out = (amount_in as u128 * numerator as u128 / denominator as u128) as u64;
+
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            user_in.key,
+            pool_in.key,
+            user.key,
+            &[],
+            amount_in,
+        )?,
+        &[user_in.clone(), pool_in.clone(), user.clone()],
+    )?;
+    let (_, bump) = pool_authority();
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            pool_out.key,
+            user_out.key,
+            authority.key,
+            &[],
+            amount_out,
+        )?,
+        &[pool_out.clone(), user_out.clone(), authority.clone()],
+        &[&[b"pool", &[bump]]],
+    )
+}
+
+pub fn swap_data(amount_in: u64, numerator: u64, denominator: u64) -> Vec<u8> {
+    [amount_in, numerator, denominator]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// The accounts for swapping `user`'s `mint_in` for `mint_out`, in the order the program
+/// expects them, to be appended as remaining accounts.
+pub fn swap_accounts(user: &Pubkey, mint_in: &Pubkey, mint_out: &Pubkey) -> Vec<AccountMeta> {
+    let (authority, _) = pool_authority();
+    vec![
+        AccountMeta::new_readonly(*user, true),
+        AccountMeta::new(get_associated_token_address(user, mint_in), false),
+        AccountMeta::new(get_associated_token_address(&authority, mint_in), false),
+        AccountMeta::new(get_associated_token_address(&authority, mint_out), false),
+        AccountMeta::new(get_associated_token_address(user, mint_out), false),
+        AccountMeta::new_readonly(authority, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
@@ -0,0 +1,1776 @@
+//! Shared fixtures for the program-test suites.
+//!
+//! The suites load the compiled `lending.so`, so run `anchor build` first.
+//! Pyth prices are written straight into `PriceUpdateV2` accounts, which keeps
+//! everything offline.
+
+#![allow(dead_code)]
+
+pub mod mock_swap;
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{program_pack::Pack, sysvar::clock::Clock},
+    AccountDeserialize, AccountSerialize, InstructionData, ToAccountMetas,
+};
+use anchor_spl::{
+    associated_token::{
+        get_associated_token_address, get_associated_token_address_with_program_id,
+        spl_associated_token_account,
+    },
+    token::spl_token,
+    token_2022::spl_token_2022::{
+        self,
+        extension::{transfer_fee, transfer_hook, ExtensionType, StateWithExtensions},
+    },
+};
+use lending::{error::ErrorCode, state::RewardSide};
+use pyth_solana_receiver_sdk::price_update::{
+    get_feed_id_from_hex, PriceFeedMessage, PriceUpdateV2, VerificationLevel,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    instruction::{AccountMeta, Instruction, InstructionError},
+    signature::Keypair,
+    signer::Signer,
+    system_instruction, system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+pub const SOL_DECIMALS: u8 = 9;
+pub const USDC_DECIMALS: u8 = 6;
+
+pub async fn start() -> ProgramTestContext {
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        std::env::set_var(
+            "BPF_OUT_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../../target/deploy"),
+        );
+    }
+    let mut program_test = ProgramTest::new("lending", lending::ID, None);
+    program_test.prefer_bpf(true);
+    program_test.add_builtin_program(
+        "mock_swap",
+        mock_swap::ID,
+        processor!(mock_swap::process_instruction),
+    );
+    let mut ctx = program_test.start_with_context().await;
+    // banks are listed under the market's roles account, which the payer claims
+    let payer = ctx.payer.pubkey();
+    let none = Pubkey::default();
+    send(
+        &mut ctx,
+        &[set_roles_ix(&payer, &none, &none, &none, &none)],
+        &[],
+    )
+    .await
+    .unwrap();
+    ctx
+}
+
+pub async fn send(
+    ctx: &mut ProgramTestContext,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let blockhash = ctx.banks_client.get_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx =
+        Transaction::new_signed_with_payer(ixs, Some(&ctx.payer.pubkey()), &all_signers, blockhash);
+    ctx.banks_client.process_transaction(tx).await
+}
+
+pub fn assert_error(result: Result<(), BanksClientError>, expected: ErrorCode) {
+    let code: u32 = expected.into();
+    match result.expect_err("transaction should have failed").unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(actual)) => {
+            assert_eq!(actual, code, "expected {expected:?}")
+        }
+        err => panic!("expected {expected:?}, got {err:?}"),
+    }
+}
+
+pub async fn funded_keypair(ctx: &mut ProgramTestContext, lamports: u64) -> Keypair {
+    let keypair = Keypair::new();
+    let ix = system_instruction::transfer(&ctx.payer.pubkey(), &keypair.pubkey(), lamports);
+    send(ctx, &[ix], &[]).await.unwrap();
+    keypair
+}
+
+pub async fn create_mint(ctx: &mut ProgramTestContext, decimals: u8) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let ixs = [
+        system_instruction::create_account(
+            &ctx.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &ctx.payer.pubkey(),
+            None,
+            decimals,
+        )
+        .unwrap(),
+    ];
+    send(ctx, &ixs, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Creates `owner`'s associated token account for `mint` and mints `amount` into it.
+pub async fn fund_ata(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let ata = get_associated_token_address(owner, mint);
+    let mut ixs = vec![
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            &ctx.payer.pubkey(),
+            owner,
+            mint,
+            &spl_token::id(),
+        ),
+    ];
+    if amount > 0 {
+        ixs.push(
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &ata,
+                &ctx.payer.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+    send(ctx, &ixs, &[]).await.unwrap();
+    ata
+}
+
+pub async fn mint_to(ctx: &mut ProgramTestContext, mint: &Pubkey, to: &Pubkey, amount: u64) {
+    let ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        mint,
+        to,
+        &ctx.payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    send(ctx, &[ix], &[]).await.unwrap();
+}
+
+/// Creates a spl-token mint whose accounts the payer can freeze.
+pub async fn create_freezable_mint(ctx: &mut ProgramTestContext, decimals: u8) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let payer = ctx.payer.pubkey();
+    let ixs = [
+        system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &payer,
+            Some(&payer),
+            decimals,
+        )
+        .unwrap(),
+    ];
+    send(ctx, &ixs, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Creates a Token-2022 mint that withholds `fee_bps` of every transfer, up to `max_fee`.
+pub async fn create_fee_mint(
+    ctx: &mut ProgramTestContext,
+    decimals: u8,
+    fee_bps: u16,
+    max_fee: u64,
+) -> Pubkey {
+    let payer = ctx.payer.pubkey();
+    create_mint_2022(ctx, decimals, ExtensionType::TransferFeeConfig, |mint| {
+        transfer_fee::instruction::initialize_transfer_fee_config(
+            &spl_token_2022::id(),
+            mint,
+            Some(&payer),
+            Some(&payer),
+            fee_bps,
+            max_fee,
+        )
+        .unwrap()
+    })
+    .await
+}
+
+/// Creates a Token-2022 mint whose permanent delegate is the payer.
+pub async fn create_permanent_delegate_mint(ctx: &mut ProgramTestContext, decimals: u8) -> Pubkey {
+    let payer = ctx.payer.pubkey();
+    create_mint_2022(ctx, decimals, ExtensionType::PermanentDelegate, |mint| {
+        spl_token_2022::instruction::initialize_permanent_delegate(
+            &spl_token_2022::id(),
+            mint,
+            &payer,
+        )
+        .unwrap()
+    })
+    .await
+}
+
+/// Creates a Token-2022 mint with no transfer hook program yet, but with the payer able to set one.
+pub async fn create_unset_transfer_hook_mint(ctx: &mut ProgramTestContext, decimals: u8) -> Pubkey {
+    let payer = ctx.payer.pubkey();
+    create_mint_2022(ctx, decimals, ExtensionType::TransferHook, |mint| {
+        transfer_hook::instruction::initialize(&spl_token_2022::id(), mint, Some(payer), None)
+            .unwrap()
+    })
+    .await
+}
+
+async fn create_mint_2022(
+    ctx: &mut ProgramTestContext,
+    decimals: u8,
+    extension: ExtensionType,
+    init_extension: impl FnOnce(&Pubkey) -> Instruction,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let space =
+        ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[extension])
+            .unwrap();
+    let payer = ctx.payer.pubkey();
+    let ixs = [
+        system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            rent.minimum_balance(space),
+            space as u64,
+            &spl_token_2022::id(),
+        ),
+        init_extension(&mint.pubkey()),
+        spl_token_2022::instruction::initialize_mint2(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            &payer,
+            None,
+            decimals,
+        )
+        .unwrap(),
+    ];
+    send(ctx, &ixs, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// Token-2022 counterpart of [`fund_ata`].
+pub async fn fund_ata_2022(
+    ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let ata = get_associated_token_address_with_program_id(owner, mint, &spl_token_2022::id());
+    let ixs = [
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            &ctx.payer.pubkey(),
+            owner,
+            mint,
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::instruction::mint_to(
+            &spl_token_2022::id(),
+            mint,
+            &ata,
+            &ctx.payer.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap(),
+    ];
+    send(ctx, &ixs, &[]).await.unwrap();
+    ata
+}
+
+/// Points an instruction built for spl-token at Token-2022 instead, including the
+/// associated token accounts of `owners` for `mint`.
+pub fn with_token_2022(mut ix: Instruction, mint: &Pubkey, owners: &[Pubkey]) -> Instruction {
+    for meta in &mut ix.accounts {
+        if meta.pubkey == spl_token::id() {
+            meta.pubkey = spl_token_2022::id();
+        }
+        for owner in owners {
+            if meta.pubkey == get_associated_token_address(owner, mint) {
+                meta.pubkey = get_associated_token_address_with_program_id(
+                    owner,
+                    mint,
+                    &spl_token_2022::id(),
+                );
+            }
+        }
+    }
+    ix
+}
+
+pub async fn token_balance(ctx: &mut ProgramTestContext, address: &Pubkey) -> u64 {
+    let account = ctx.banks_client.get_account(*address).await.unwrap();
+    account
+        .map(|account| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+                .unwrap()
+                .base
+                .amount
+        })
+        .unwrap_or_default()
+}
+
+pub async fn fetch<T: AccountDeserialize>(ctx: &mut ProgramTestContext, address: &Pubkey) -> T {
+    let account = ctx
+        .banks_client
+        .get_account(*address)
+        .await
+        .unwrap()
+        .expect("account does not exist");
+    T::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+/// Writes `state` as a program-owned account, for standing in for accounts that older
+/// program versions created.
+pub async fn set_program_account<T: AccountSerialize>(
+    ctx: &mut ProgramTestContext,
+    address: &Pubkey,
+    state: &T,
+) {
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    set_program_account_data(ctx, address, data).await;
+}
+
+pub async fn set_program_account_data(
+    ctx: &mut ProgramTestContext,
+    address: &Pubkey,
+    data: Vec<u8>,
+) {
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let account = Account {
+        lamports: rent.minimum_balance(data.len()),
+        data,
+        owner: lending::ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(address, &AccountSharedData::from(account));
+}
+
+/// The wrapped SOL mint, written in place when the test validator does not already have it.
+pub async fn native_mint(ctx: &mut ProgramTestContext) -> Pubkey {
+    let mint = spl_token::native_mint::id();
+    if ctx.banks_client.get_account(mint).await.unwrap().is_none() {
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint {
+            decimals: SOL_DECIMALS,
+            is_initialized: true,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        let rent = ctx.banks_client.get_rent().await.unwrap();
+        let account = Account {
+            lamports: rent.minimum_balance(data.len()),
+            data,
+            owner: spl_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        };
+        ctx.set_account(&mint, &AccountSharedData::from(account));
+    }
+    mint
+}
+
+pub async fn clock(ctx: &mut ProgramTestContext) -> Clock {
+    ctx.banks_client.get_sysvar::<Clock>().await.unwrap()
+}
+
+/// Moves the cluster clock forward without producing new slots.
+pub async fn advance_clock(ctx: &mut ProgramTestContext, seconds: i64) {
+    let mut clock = clock(ctx).await;
+    clock.unix_timestamp += seconds;
+    ctx.set_sysvar(&clock);
+}
+
+/// Writes a fully verified `PriceUpdateV2` for `feed_id_hex` at `address`.
+pub fn set_price(
+    ctx: &mut ProgramTestContext,
+    address: &Pubkey,
+    feed_id_hex: &str,
+    price: i64,
+    conf: u64,
+    exponent: i32,
+    publish_time: i64,
+) {
+    let update = PriceUpdateV2 {
+        write_authority: Pubkey::new_unique(),
+        verification_level: VerificationLevel::Full,
+        price_message: PriceFeedMessage {
+            feed_id: get_feed_id_from_hex(feed_id_hex).unwrap(),
+            price,
+            conf,
+            exponent,
+            publish_time,
+            prev_publish_time: publish_time,
+            ema_price: price,
+            ema_conf: conf,
+        },
+        posted_slot: 0,
+    };
+    let mut data = Vec::new();
+    update.try_serialize(&mut data).unwrap();
+    let account = Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: pyth_solana_receiver_sdk::ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(address, &AccountSharedData::from(account));
+}
+
+pub use lending::pda::{
+    bank_address as bank_pda, delegation_address as delegation_pda, legacy_bank_address,
+    legacy_user_address, pending_config_address as pending_config_pda,
+    referral_fees_address as referral_fees_pda, reward_pool_address as reward_pool_pda,
+    reward_position_address as reward_position_pda, reward_vault_address as reward_vault_pda,
+    roles_address as roles_pda, term_deposit_address as term_deposit_pda,
+    term_loan_address as term_loan_pda, term_pool_address as term_pool_pda,
+    term_vault_address as term_vault_pda, treasury_address as treasury_pda,
+    user_address as sub_account_pda, wsol_address as wsol_pda,
+};
+
+/// The owner's default sub-account, which every other builder here acts on.
+pub fn user_pda(owner: &Pubkey) -> Pubkey {
+    sub_account_pda(owner, 0)
+}
+
+pub fn init_bank_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    liquidation_threshold: u64,
+    max_ltv: u64,
+    allow_unsafe_mint: bool,
+) -> Instruction {
+    list_bank_ix(
+        signer,
+        mint,
+        liquidation_threshold,
+        max_ltv,
+        allow_unsafe_mint,
+    )
+}
+
+/// Lists `mint` under the market authority, with `signer` acting as its listing admin.
+pub fn list_bank_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    liquidation_threshold: u64,
+    max_ltv: u64,
+    allow_unsafe_mint: bool,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::InitBank {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            bank_token_account: treasury_pda(mint),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            roles: roles_pda(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::InitBank {
+            liquidation_threshold,
+            max_ltv,
+            allow_unsafe_mint,
+        }
+        .data(),
+    }
+}
+
+pub fn init_user_ix(signer: &Pubkey, usdc_address: &Pubkey, sol_address: &Pubkey) -> Instruction {
+    init_sub_account_ix(signer, usdc_address, sol_address, 0)
+}
+
+pub fn init_sub_account_ix(
+    signer: &Pubkey,
+    usdc_address: &Pubkey,
+    sol_address: &Pubkey,
+    index: u8,
+) -> Instruction {
+    init_referred_user_ix(signer, usdc_address, sol_address, index, None)
+}
+
+pub fn init_referred_user_ix(
+    signer: &Pubkey,
+    usdc_address: &Pubkey,
+    sol_address: &Pubkey,
+    index: u8,
+    referrer: Option<Pubkey>,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::InitUser {
+            signer: *signer,
+            user_account: sub_account_pda(signer, index),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::InitUser {
+            usdc_address: *usdc_address,
+            sol_address: *sol_address,
+            index,
+            referrer,
+        }
+        .data(),
+    }
+}
+
+pub fn deposit_ix(signer: &Pubkey, mint: &Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::Deposit {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            bank_token_account: treasury_pda(mint),
+            user: user_pda(signer),
+            user_token_account: Some(get_associated_token_address(signer, mint)),
+            wsol_account: None,
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            associated_token_program: spl_associated_token_account::id(),
+            reward_pool: None,
+            reward_position: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::Deposit { amount }.data(),
+    }
+}
+
+/// Leaves the optional account at `address` out of `ix`, as a client passing `None` would.
+pub fn without_account(mut ix: Instruction, address: &Pubkey) -> Instruction {
+    for meta in &mut ix.accounts {
+        if meta.pubkey == *address {
+            *meta = AccountMeta::new_readonly(lending::ID, false);
+        }
+    }
+    ix
+}
+
+/// Turns a payment instruction into its `_native` form: the signer's token account drops out
+/// and the wrapped SOL account the program opens for the instruction takes the next slot.
+fn as_native(mut ix: Instruction, signer: &Pubkey, mint: &Pubkey, data: Vec<u8>) -> Instruction {
+    let ata = get_associated_token_address(signer, mint);
+    let slot = ix
+        .accounts
+        .iter()
+        .position(|meta| meta.pubkey == ata)
+        .unwrap();
+    ix.accounts[slot] = AccountMeta::new_readonly(lending::ID, false);
+    ix.accounts[slot + 1] = AccountMeta::new(wsol_pda(signer), false);
+    Instruction { data, ..ix }
+}
+
+/// Like [`deposit_ix`], paying in lamports.
+pub fn deposit_native_ix(signer: &Pubkey, mint: &Pubkey, amount: u64) -> Instruction {
+    as_native(
+        deposit_ix(signer, mint, amount),
+        signer,
+        mint,
+        lending::instruction::DepositNative { amount }.data(),
+    )
+}
+
+pub fn withdraw_ix(signer: &Pubkey, mint: &Pubkey, market: &Market, amount: u64) -> Instruction {
+    let (price_update, other_mint, other_price_update) = market.sides(mint);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::Withdraw {
+            signer: *signer,
+            mint: *mint,
+            other_mint,
+            bank: bank_pda(mint),
+            other_bank: bank_pda(&other_mint),
+            bank_token_account: treasury_pda(mint),
+            user: user_pda(signer),
+            user_token_account: Some(get_associated_token_address(signer, mint)),
+            wsol_account: None,
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            associated_token_program: spl_associated_token_account::id(),
+            price_update,
+            other_price_update,
+            reward_pool: None,
+            reward_position: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::Withdraw { amount }.data(),
+    }
+}
+
+/// Like [`withdraw_ix`], paying out lamports.
+pub fn withdraw_native_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    market: &Market,
+    amount: u64,
+) -> Instruction {
+    as_native(
+        withdraw_ix(signer, mint, market, amount),
+        signer,
+        mint,
+        lending::instruction::WithdrawNative { amount }.data(),
+    )
+}
+
+pub fn borrow_ix(
+    signer: &Pubkey,
+    borrow_mint: &Pubkey,
+    market: &Market,
+    amount: u64,
+) -> Instruction {
+    borrow_for_ix(signer, signer, borrow_mint, market, amount)
+}
+
+/// Like [`borrow_ix`], paying out lamports.
+pub fn borrow_native_ix(
+    signer: &Pubkey,
+    borrow_mint: &Pubkey,
+    market: &Market,
+    amount: u64,
+) -> Instruction {
+    as_native(
+        borrow_ix(signer, borrow_mint, market, amount),
+        signer,
+        borrow_mint,
+        lending::instruction::BorrowNative { amount }.data(),
+    )
+}
+
+/// Borrows against `owner`'s position; a signer other than the owner borrows as a delegate.
+pub fn borrow_for_ix(
+    signer: &Pubkey,
+    owner: &Pubkey,
+    borrow_mint: &Pubkey,
+    market: &Market,
+    amount: u64,
+) -> Instruction {
+    borrow_with_fees_ix(signer, owner, borrow_mint, market, amount, None, None)
+}
+
+/// Like [`borrow_for_ix`], paying the host share of the origination fee to `host_fee_receiver`
+/// and crediting the referral share to `referrer`, who must be the one on the position.
+pub fn borrow_with_fees_ix(
+    signer: &Pubkey,
+    owner: &Pubkey,
+    borrow_mint: &Pubkey,
+    market: &Market,
+    amount: u64,
+    host_fee_receiver: Option<Pubkey>,
+    referrer: Option<Pubkey>,
+) -> Instruction {
+    let (price_update, other_mint, other_price_update) = market.sides(borrow_mint);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::Borrow {
+            signer: *signer,
+            owner: *owner,
+            borrow_mint: *borrow_mint,
+            other_mint,
+            borrow_bank: bank_pda(borrow_mint),
+            other_bank: bank_pda(&other_mint),
+            borrow_bank_token_account: treasury_pda(borrow_mint),
+            user: user_pda(owner),
+            delegation: (signer != owner).then(|| delegation_pda(owner, signer, borrow_mint, 0)),
+            user_token_account: Some(get_associated_token_address(signer, borrow_mint)),
+            wsol_account: None,
+            host_fee_receiver,
+            referral_fees: referrer.map(|referrer| referral_fees_pda(&referrer, borrow_mint)),
+            associated_token_program: spl_associated_token_account::id(),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            price_update,
+            other_price_update,
+            reward_pool: None,
+            reward_position: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::Borrow { amount }.data(),
+    }
+}
+
+/// Like [`borrow_ix`], locking the debt at the bank's stable rate.
+pub fn borrow_stable_ix(
+    signer: &Pubkey,
+    borrow_mint: &Pubkey,
+    market: &Market,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        data: lending::instruction::BorrowStable { amount }.data(),
+        ..borrow_ix(signer, borrow_mint, market, amount)
+    }
+}
+
+pub fn repay_stable_ix(signer: &Pubkey, repay_mint: &Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        data: lending::instruction::RepayStable { amount }.data(),
+        ..repay_ix(signer, repay_mint, amount)
+    }
+}
+
+pub fn rebalance_stable_rate_ix(mint: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::RebalanceStableRate {
+            mint: *mint,
+            bank: bank_pda(mint),
+            user: user_pda(owner),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::RebalanceStableRate {}.data(),
+    }
+}
+
+pub fn repay_ix(signer: &Pubkey, repay_mint: &Pubkey, amount: u64) -> Instruction {
+    repay_for_ix(signer, signer, repay_mint, amount)
+}
+
+/// Like [`repay_ix`], paying in lamports.
+pub fn repay_native_ix(signer: &Pubkey, repay_mint: &Pubkey, amount: u64) -> Instruction {
+    as_native(
+        repay_ix(signer, repay_mint, amount),
+        signer,
+        repay_mint,
+        lending::instruction::RepayNative { amount }.data(),
+    )
+}
+
+pub fn repay_for_ix(
+    signer: &Pubkey,
+    owner: &Pubkey,
+    repay_mint: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::Repay {
+            signer: *signer,
+            owner: *owner,
+            repay_mint: *repay_mint,
+            repay_bank: bank_pda(repay_mint),
+            repay_bank_token_account: treasury_pda(repay_mint),
+            user: user_pda(owner),
+            user_token_account: Some(get_associated_token_address(signer, repay_mint)),
+            wsol_account: None,
+            associated_token_program: spl_associated_token_account::id(),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            reward_pool: None,
+            reward_position: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::Repay { amount }.data(),
+    }
+}
+
+pub fn liquidate_ix(
+    liquidator: &Pubkey,
+    borrower: &Pubkey,
+    collateral_mint: &Pubkey,
+    borrowed_mint: &Pubkey,
+    collateral_price_update: &Pubkey,
+    borrowed_price_update: &Pubkey,
+) -> Instruction {
+    liquidate_term_loan_ix(
+        liquidator,
+        borrower,
+        collateral_mint,
+        borrowed_mint,
+        collateral_price_update,
+        borrowed_price_update,
+        None,
+    )
+}
+
+/// Like [`liquidate_ix`], repaying the borrower's loan from the `borrowed_mint` term pool
+/// maturing at `maturity` instead of variable and stable debt.
+pub fn liquidate_term_loan_ix(
+    liquidator: &Pubkey,
+    borrower: &Pubkey,
+    collateral_mint: &Pubkey,
+    borrowed_mint: &Pubkey,
+    collateral_price_update: &Pubkey,
+    borrowed_price_update: &Pubkey,
+    maturity: Option<i64>,
+) -> Instruction {
+    let term_pool = maturity.map(|maturity| term_pool_pda(&bank_pda(borrowed_mint), maturity));
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::Liquidate {
+            liquidator: *liquidator,
+            collateral_price_update: *collateral_price_update,
+            borrowed_price_update: *borrowed_price_update,
+            collateral_mint: *collateral_mint,
+            borrowed_mint: *borrowed_mint,
+            collateral_bank: bank_pda(collateral_mint),
+            borrowed_bank: bank_pda(borrowed_mint),
+            collateral_bank_token_account: treasury_pda(collateral_mint),
+            borrowed_bank_token_account: treasury_pda(borrowed_mint),
+            user_account: user_pda(borrower),
+            liquidator_collateral_token_account: get_associated_token_address(
+                liquidator,
+                collateral_mint,
+            ),
+            liquidator_borrowed_token_account: get_associated_token_address(
+                liquidator,
+                borrowed_mint,
+            ),
+            term_pool,
+            term_loan: term_pool.map(|term_pool| term_loan_pda(&term_pool, &user_pda(borrower))),
+            term_vault: term_pool.map(|term_pool| term_vault_pda(&term_pool)),
+            associated_token_program: spl_associated_token_account::id(),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::Liquidate {}.data(),
+    }
+}
+
+pub fn claim_referral_fees_ix(
+    referrer: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::ClaimReferralFees {
+            signer: *referrer,
+            mint: *mint,
+            referral_fees: referral_fees_pda(referrer, mint),
+            bank: bank_pda(mint),
+            bank_token_account: treasury_pda(mint),
+            destination: *destination,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::ClaimReferralFees {}.data(),
+    }
+}
+
+/// Fills in the reward pool and positions, the trailing optional accounts of deposit,
+/// withdraw, borrow, repay and transfer_collateral, for `mint`'s bank on `side`. `users` are
+/// the positions' `User` accounts in the order the instruction lists them.
+pub fn with_rewards(
+    mut ix: Instruction,
+    mint: &Pubkey,
+    side: RewardSide,
+    users: &[Pubkey],
+) -> Instruction {
+    let bank = bank_pda(mint);
+    let addresses = std::iter::once(reward_pool_pda(&bank, side)).chain(
+        users
+            .iter()
+            .map(|user| reward_position_pda(&bank, side, user)),
+    );
+    let placeholders: Vec<usize> = (0..ix.accounts.len())
+        .filter(|&index| ix.accounts[index].pubkey == lending::ID)
+        .collect();
+    let first = placeholders.len() - 1 - users.len();
+    for (&index, address) in placeholders[first..].iter().zip(addresses) {
+        ix.accounts[index] = AccountMeta::new(address, false);
+    }
+    ix
+}
+
+pub fn configure_rewards_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    reward_mint: &Pubkey,
+    side: RewardSide,
+    rate: u64,
+    end_time: i64,
+) -> Instruction {
+    let reward_pool = reward_pool_pda(&bank_pda(mint), side);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::ConfigureRewards {
+            signer: *signer,
+            bank: bank_pda(mint),
+            roles: None,
+            reward_mint: *reward_mint,
+            reward_pool,
+            reward_vault: reward_vault_pda(&reward_pool),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::ConfigureRewards {
+            side,
+            rate,
+            end_time,
+        }
+        .data(),
+    }
+}
+
+pub fn claim_rewards_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    reward_mint: &Pubkey,
+    side: RewardSide,
+    destination: &Pubkey,
+) -> Instruction {
+    let bank = bank_pda(mint);
+    let reward_pool = reward_pool_pda(&bank, side);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::ClaimRewards {
+            signer: *signer,
+            bank,
+            user: user_pda(signer),
+            reward_pool,
+            reward_position: reward_position_pda(&bank, side, &user_pda(signer)),
+            reward_mint: *reward_mint,
+            reward_vault: reward_vault_pda(&reward_pool),
+            destination: *destination,
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::ClaimRewards { side }.data(),
+    }
+}
+
+pub fn create_term_pool_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    maturity: i64,
+    rate: f64,
+) -> Instruction {
+    let term_pool = term_pool_pda(&bank_pda(mint), maturity);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::CreateTermPool {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            roles: None,
+            term_pool,
+            term_vault: term_vault_pda(&term_pool),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::CreateTermPool { maturity, rate }.data(),
+    }
+}
+
+pub fn lend_term_ix(signer: &Pubkey, mint: &Pubkey, maturity: i64, amount: u64) -> Instruction {
+    let term_pool = term_pool_pda(&bank_pda(mint), maturity);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::LendTerm {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            term_pool,
+            term_vault: term_vault_pda(&term_pool),
+            term_deposit: term_deposit_pda(&term_pool, signer),
+            user_token_account: get_associated_token_address(signer, mint),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            associated_token_program: spl_associated_token_account::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::LendTerm { amount }.data(),
+    }
+}
+
+pub fn withdraw_term_ix(signer: &Pubkey, mint: &Pubkey, maturity: i64) -> Instruction {
+    let term_pool = term_pool_pda(&bank_pda(mint), maturity);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::WithdrawTerm {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            term_pool,
+            term_vault: term_vault_pda(&term_pool),
+            term_deposit: term_deposit_pda(&term_pool, signer),
+            user_token_account: get_associated_token_address(signer, mint),
+            token_program: spl_token::id(),
+            associated_token_program: spl_associated_token_account::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::WithdrawTerm {}.data(),
+    }
+}
+
+/// Borrows `mint` from its term pool maturing at `maturity`, valuing the position with
+/// `other_mint` priced by `other_price_update`.
+pub fn borrow_term_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    price_update: &Pubkey,
+    other_mint: &Pubkey,
+    other_price_update: &Pubkey,
+    maturity: i64,
+    amount: u64,
+) -> Instruction {
+    let term_pool = term_pool_pda(&bank_pda(mint), maturity);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::BorrowTerm {
+            signer: *signer,
+            mint: *mint,
+            other_mint: *other_mint,
+            bank: bank_pda(mint),
+            other_bank: bank_pda(other_mint),
+            user: user_pda(signer),
+            term_pool,
+            term_vault: term_vault_pda(&term_pool),
+            term_loan: term_loan_pda(&term_pool, &user_pda(signer)),
+            user_token_account: get_associated_token_address(signer, mint),
+            price_update: *price_update,
+            other_price_update: *other_price_update,
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            associated_token_program: spl_associated_token_account::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::BorrowTerm { amount }.data(),
+    }
+}
+
+pub fn repay_term_ix(
+    signer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    maturity: i64,
+    amount: u64,
+) -> Instruction {
+    let term_pool = term_pool_pda(&bank_pda(mint), maturity);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::RepayTerm {
+            signer: *signer,
+            owner: *owner,
+            mint: *mint,
+            bank: bank_pda(mint),
+            user: user_pda(owner),
+            term_pool,
+            term_vault: term_vault_pda(&term_pool),
+            term_loan: term_loan_pda(&term_pool, &user_pda(owner)),
+            user_token_account: get_associated_token_address(signer, mint),
+            token_program: spl_token::id(),
+            associated_token_program: spl_associated_token_account::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::RepayTerm { amount }.data(),
+    }
+}
+
+pub fn roll_term_loan_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    maturity: i64,
+) -> Instruction {
+    let term_pool = term_pool_pda(&bank_pda(mint), maturity);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::RollTermLoan {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            bank_token_account: treasury_pda(mint),
+            user: user_pda(owner),
+            term_pool,
+            term_vault: term_vault_pda(&term_pool),
+            term_loan: term_loan_pda(&term_pool, &user_pda(owner)),
+            token_program: spl_token::id(),
+            system_program: system_program::id(),
+            reward_pool: None,
+            reward_position: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::RollTermLoan {}.data(),
+    }
+}
+
+pub fn approve_delegation_ix(
+    owner: &Pubkey,
+    delegate: &Pubkey,
+    mint: &Pubkey,
+    allowance: u64,
+) -> Instruction {
+    approve_sub_account_delegation_ix(owner, delegate, mint, allowance, 0)
+}
+
+/// Like [`approve_delegation_ix`], for the owner's sub-account `index`.
+pub fn approve_sub_account_delegation_ix(
+    owner: &Pubkey,
+    delegate: &Pubkey,
+    mint: &Pubkey,
+    allowance: u64,
+    index: u8,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::ApproveDelegation {
+            owner: *owner,
+            delegate: *delegate,
+            mint: *mint,
+            user: sub_account_pda(owner, index),
+            delegation: delegation_pda(owner, delegate, mint, index),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::ApproveDelegation { allowance }.data(),
+    }
+}
+
+pub fn revoke_delegation_ix(owner: &Pubkey, delegate: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::RevokeDelegation {
+            owner: *owner,
+            delegation: delegation_pda(owner, delegate, mint, 0),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::RevokeDelegation {}.data(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_collateral_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    other_mint: &Pubkey,
+    from_index: u8,
+    to_index: u8,
+    price_update: &Pubkey,
+    other_price_update: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::TransferCollateral {
+            signer: *signer,
+            mint: *mint,
+            other_mint: *other_mint,
+            bank: bank_pda(mint),
+            other_bank: bank_pda(other_mint),
+            from: sub_account_pda(signer, from_index),
+            to: sub_account_pda(signer, to_index),
+            price_update: *price_update,
+            other_price_update: *other_price_update,
+            reward_pool: None,
+            from_reward_position: None,
+            to_reward_position: None,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::TransferCollateral { amount }.data(),
+    }
+}
+
+/// Swaps `amount` of `from_mint` collateral into `to_mint` through the mock AMM at
+/// `numerator / denominator`.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_collateral_ix(
+    signer: &Pubkey,
+    from_mint: &Pubkey,
+    from_price_update: &Pubkey,
+    to_mint: &Pubkey,
+    to_price_update: &Pubkey,
+    amount: u64,
+    min_amount_out: u64,
+    (numerator, denominator): (u64, u64),
+) -> Instruction {
+    let mut accounts = lending::accounts::SwapCollateral {
+        signer: *signer,
+        from_mint: *from_mint,
+        to_mint: *to_mint,
+        from_bank: bank_pda(from_mint),
+        to_bank: bank_pda(to_mint),
+        from_bank_token_account: treasury_pda(from_mint),
+        to_bank_token_account: treasury_pda(to_mint),
+        user: user_pda(signer),
+        from_token_account: get_associated_token_address(signer, from_mint),
+        to_token_account: get_associated_token_address(signer, to_mint),
+        from_price_update: *from_price_update,
+        to_price_update: *to_price_update,
+        swap_program: mock_swap::ID,
+        token_program: spl_token::id(),
+        associated_token_program: spl_associated_token_account::id(),
+        system_program: system_program::id(),
+        from_reward_pool: None,
+        from_reward_position: None,
+        to_reward_pool: None,
+        to_reward_position: None,
+    }
+    .to_account_metas(None);
+    accounts.extend(mock_swap::swap_accounts(signer, from_mint, to_mint));
+    Instruction {
+        program_id: lending::ID,
+        accounts,
+        data: lending::instruction::SwapCollateral {
+            amount,
+            min_amount_out,
+            swap_data: mock_swap::swap_data(amount, numerator, denominator),
+        }
+        .data(),
+    }
+}
+
+/// Refinances `from_mint` debt by borrowing `amount` of `to_mint` and swapping it through the
+/// mock AMM at `numerator / denominator`.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_debt_ix(
+    signer: &Pubkey,
+    from_mint: &Pubkey,
+    from_price_update: &Pubkey,
+    to_mint: &Pubkey,
+    to_price_update: &Pubkey,
+    amount: u64,
+    min_amount_out: u64,
+    (numerator, denominator): (u64, u64),
+) -> Instruction {
+    let mut accounts = lending::accounts::SwapDebt {
+        signer: *signer,
+        from_mint: *from_mint,
+        to_mint: *to_mint,
+        from_bank: bank_pda(from_mint),
+        to_bank: bank_pda(to_mint),
+        from_bank_token_account: treasury_pda(from_mint),
+        to_bank_token_account: treasury_pda(to_mint),
+        user: user_pda(signer),
+        from_token_account: get_associated_token_address(signer, from_mint),
+        to_token_account: get_associated_token_address(signer, to_mint),
+        referral_fees: None,
+        from_price_update: *from_price_update,
+        to_price_update: *to_price_update,
+        swap_program: mock_swap::ID,
+        token_program: spl_token::id(),
+        associated_token_program: spl_associated_token_account::id(),
+        system_program: system_program::id(),
+        from_reward_pool: None,
+        from_reward_position: None,
+        to_reward_pool: None,
+        to_reward_position: None,
+    }
+    .to_account_metas(None);
+    accounts.extend(mock_swap::swap_accounts(signer, to_mint, from_mint));
+    Instruction {
+        program_id: lending::ID,
+        accounts,
+        data: lending::instruction::SwapDebt {
+            amount,
+            min_amount_out,
+            swap_data: mock_swap::swap_data(amount, numerator, denominator),
+        }
+        .data(),
+    }
+}
+
+/// The accounts for levering `collateral_mint` against `debt_mint`, swapping `mint_in` through
+/// the mock AMM.
+fn leverage_accounts(
+    signer: &Pubkey,
+    collateral_mint: &Pubkey,
+    collateral_price_update: &Pubkey,
+    debt_mint: &Pubkey,
+    debt_price_update: &Pubkey,
+    mint_in: &Pubkey,
+    mint_out: &Pubkey,
+) -> Vec<AccountMeta> {
+    let mut accounts = lending::accounts::Leverage {
+        signer: *signer,
+        collateral_mint: *collateral_mint,
+        debt_mint: *debt_mint,
+        collateral_bank: bank_pda(collateral_mint),
+        debt_bank: bank_pda(debt_mint),
+        collateral_bank_token_account: treasury_pda(collateral_mint),
+        debt_bank_token_account: treasury_pda(debt_mint),
+        user: user_pda(signer),
+        collateral_token_account: get_associated_token_address(signer, collateral_mint),
+        debt_token_account: get_associated_token_address(signer, debt_mint),
+        referral_fees: None,
+        collateral_price_update: *collateral_price_update,
+        debt_price_update: *debt_price_update,
+        swap_program: mock_swap::ID,
+        token_program: spl_token::id(),
+        associated_token_program: spl_associated_token_account::id(),
+        system_program: system_program::id(),
+        deposit_reward_pool: None,
+        deposit_reward_position: None,
+        borrow_reward_pool: None,
+        borrow_reward_position: None,
+    }
+    .to_account_metas(None);
+    accounts.extend(mock_swap::swap_accounts(signer, mint_in, mint_out));
+    accounts
+}
+
+/// Levers `amount` of `market.sol_mint` up to `leverage_bps` against flash-borrowed USDC,
+/// swapped through the mock AMM at `numerator / denominator`. The swap sells what the program
+/// borrows at the prices `setup_market` starts with.
+pub fn open_leverage_ix(
+    signer: &Pubkey,
+    market: &Market,
+    amount: u64,
+    leverage_bps: u64,
+    min_amount_out: u64,
+    (numerator, denominator): (u64, u64),
+) -> Instruction {
+    let borrow_amount = lending::math::leverage_borrow_amount(
+        amount,
+        leverage_bps,
+        lending::math::usd_value(1, 100, 0, SOL_DECIMALS),
+        lending::math::usd_value(1, 1, 0, USDC_DECIMALS),
+    )
+    .unwrap_or(0);
+    Instruction {
+        program_id: lending::ID,
+        accounts: leverage_accounts(
+            signer,
+            &market.sol_mint,
+            &market.sol_price,
+            &market.usdc_mint,
+            &market.usdc_price,
+            &market.usdc_mint,
+            &market.sol_mint,
+        ),
+        data: lending::instruction::OpenLeverage {
+            amount,
+            leverage_bps,
+            min_amount_out,
+            swap_data: mock_swap::swap_data(borrow_amount, numerator, denominator),
+        }
+        .data(),
+    }
+}
+
+/// Sells `amount` of SOL collateral through the mock AMM to repay USDC debt.
+pub fn close_leverage_ix(
+    signer: &Pubkey,
+    market: &Market,
+    amount: u64,
+    min_amount_out: u64,
+    (numerator, denominator): (u64, u64),
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: leverage_accounts(
+            signer,
+            &market.sol_mint,
+            &market.sol_price,
+            &market.usdc_mint,
+            &market.usdc_price,
+            &market.sol_mint,
+            &market.usdc_mint,
+        ),
+        data: lending::instruction::CloseLeverage {
+            amount,
+            min_amount_out,
+            swap_data: mock_swap::swap_data(amount, numerator, denominator),
+        }
+        .data(),
+    }
+}
+
+pub fn migrate_bank_ix(signer: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::MigrateBank {
+            signer: *signer,
+            mint: *mint,
+            legacy_bank: legacy_bank_address(mint),
+            bank: bank_pda(mint),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::MigrateBank {}.data(),
+    }
+}
+
+pub fn migrate_user_ix(signer: &Pubkey, owner: &Pubkey, sol_mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::MigrateUser {
+            signer: *signer,
+            owner: *owner,
+            legacy_user: legacy_user_address(owner),
+            user: user_pda(owner),
+            sol_mint: *sol_mint,
+            sol_bank: bank_pda(sol_mint),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::MigrateUser {}.data(),
+    }
+}
+
+pub fn upgrade_account_ix(payer: &Pubkey, account: &Pubkey) -> Instruction {
+    upgrade_user_ix(payer, account, None)
+}
+
+/// Upgrades `owner`'s position at `account`, recording `sol_mint` if it predates the field.
+pub fn upgrade_user_ix(owner: &Pubkey, account: &Pubkey, sol_mint: Option<&Pubkey>) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::UpgradeAccount {
+            payer: *owner,
+            account: *account,
+            system_program: system_program::id(),
+            sol_mint: sol_mint.copied(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::UpgradeAccount {}.data(),
+    }
+}
+
+pub fn close_user_ix(signer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::CloseUser {
+            signer: *signer,
+            user_account: user_pda(signer),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::CloseUser {}.data(),
+    }
+}
+
+pub fn close_bank_ix(signer: &Pubkey, mint: &Pubkey, destination: &Pubkey) -> Instruction {
+    close_rewarded_bank_ix(signer, mint, destination, None)
+}
+
+/// Like [`close_bank_ix`], passing the bank's reward vault on the `rewarded` side, along
+/// with its reward mint and the account of it the vault is swept into.
+pub fn close_rewarded_bank_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    rewarded: Option<(RewardSide, Pubkey, Pubkey)>,
+) -> Instruction {
+    let rewarded_on = |side| rewarded.filter(|(rewarded_side, _, _)| *rewarded_side == side);
+    let reward_vault =
+        |side| rewarded_on(side).map(|_| reward_vault_pda(&reward_pool_pda(&bank_pda(mint), side)));
+    let reward_mint = |side| rewarded_on(side).map(|(_, reward_mint, _)| reward_mint);
+    let reward_destination =
+        |side| rewarded_on(side).map(|(_, _, reward_destination)| reward_destination);
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::CloseBank {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            bank_token_account: treasury_pda(mint),
+            destination: *destination,
+            token_program: spl_token::id(),
+            roles: None,
+            deposit_reward_vault: reward_vault(RewardSide::Deposit),
+            deposit_reward_mint: reward_mint(RewardSide::Deposit),
+            deposit_reward_destination: reward_destination(RewardSide::Deposit),
+            borrow_reward_vault: reward_vault(RewardSide::Borrow),
+            borrow_reward_mint: reward_mint(RewardSide::Borrow),
+            borrow_reward_destination: reward_destination(RewardSide::Borrow),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::CloseBank {}.data(),
+    }
+}
+
+pub fn update_bank_config_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    params: lending::instructions::BankConfigParams,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::UpdateBank {
+            signer: *signer,
+            bank: bank_pda(mint),
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::UpdateBankConfig { params }.data(),
+    }
+}
+
+pub fn queue_bank_config_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    params: lending::instructions::BankConfigParams,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::QueueBankConfig {
+            signer: *signer,
+            bank: bank_pda(mint),
+            pending_config: pending_config_pda(&bank_pda(mint)),
+            system_program: system_program::id(),
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::QueueBankConfig { params }.data(),
+    }
+}
+
+pub fn execute_bank_config_ix(mint: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::ExecuteBankConfig {
+            bank: bank_pda(mint),
+            pending_config: pending_config_pda(&bank_pda(mint)),
+            payer: *payer,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::ExecuteBankConfig {}.data(),
+    }
+}
+
+pub fn cancel_bank_config_ix(signer: &Pubkey, mint: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::CancelBankConfig {
+            signer: *signer,
+            bank: bank_pda(mint),
+            pending_config: pending_config_pda(&bank_pda(mint)),
+            payer: *payer,
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::CancelBankConfig {}.data(),
+    }
+}
+
+pub fn set_paused_ix(signer: &Pubkey, mint: &Pubkey, paused: bool) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::UpdateBank {
+            signer: *signer,
+            bank: bank_pda(mint),
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::SetPaused { paused }.data(),
+    }
+}
+
+pub fn raise_outflow_limit_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    extra: u64,
+    duration: i64,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::RaiseOutflowLimit {
+            signer: *signer,
+            bank: bank_pda(mint),
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::RaiseOutflowLimit { extra, duration }.data(),
+    }
+}
+
+pub fn transfer_authority_ix(
+    signer: &Pubkey,
+    mint: &Pubkey,
+    new_authority: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::UpdateBank {
+            signer: *signer,
+            bank: bank_pda(mint),
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::TransferAuthority {
+            new_authority: *new_authority,
+        }
+        .data(),
+    }
+}
+
+pub fn collect_fees_ix(signer: &Pubkey, mint: &Pubkey, fee_destination: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::CollectFees {
+            signer: *signer,
+            mint: *mint,
+            bank: bank_pda(mint),
+            bank_token_account: treasury_pda(mint),
+            fee_destination: *fee_destination,
+            token_program: spl_token::id(),
+            roles: None,
+        }
+        .to_account_metas(None),
+        data: lending::instruction::CollectFees {}.data(),
+    }
+}
+
+pub fn set_roles_ix(
+    signer: &Pubkey,
+    risk_admin: &Pubkey,
+    guardian: &Pubkey,
+    fee_admin: &Pubkey,
+    listing_admin: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: lending::ID,
+        accounts: lending::accounts::SetRoles {
+            signer: *signer,
+            roles: roles_pda(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: lending::instruction::SetRoles {
+            risk_admin: *risk_admin,
+            guardian: *guardian,
+            fee_admin: *fee_admin,
+            listing_admin: *listing_admin,
+        }
+        .data(),
+    }
+}
+
+/// Passes the market's roles account to an admin instruction built without one, so the
+/// signer can act through a role. Only for builders whose single optional account is roles.
+pub fn with_roles(mut ix: Instruction) -> Instruction {
+    let roles = ix
+        .accounts
+        .iter_mut()
+        .find(|meta| meta.pubkey == lending::ID)
+        .expect("instruction has no roles account to fill");
+    roles.pubkey = roles_pda();
+    ix
+}
+
+/// Two funded banks, a SOL and a USDC one, with their price accounts in place.
+pub struct Market {
+    pub usdc_mint: Pubkey,
+    pub sol_mint: Pubkey,
+    pub usdc_price: Pubkey,
+    pub sol_price: Pubkey,
+}
+
+impl Market {
+    /// The price account for `mint`, followed by the other mint and its price account.
+    pub fn sides(&self, mint: &Pubkey) -> (Pubkey, Pubkey, Pubkey) {
+        if *mint == self.usdc_mint {
+            (self.usdc_price, self.sol_mint, self.sol_price)
+        } else {
+            (self.sol_price, self.usdc_mint, self.usdc_price)
+        }
+    }
+}
+
+pub const BANK_LIQUIDITY: u64 = 1_000_000_000_000;
+
+pub async fn setup_market(ctx: &mut ProgramTestContext) -> Market {
+    setup_market_with_liquidity(ctx, BANK_LIQUIDITY).await
+}
+
+/// Like [`setup_market`], seeding each treasury with `liquidity` tokens that no depositor owns.
+pub async fn setup_market_with_liquidity(ctx: &mut ProgramTestContext, liquidity: u64) -> Market {
+    let usdc_mint = create_mint(ctx, USDC_DECIMALS).await;
+    let sol_mint = create_mint(ctx, SOL_DECIMALS).await;
+    let payer = ctx.payer.pubkey();
+    for mint in [usdc_mint, sol_mint] {
+        send(ctx, &[init_bank_ix(&payer, &mint, 80, 75, false)], &[])
+            .await
+            .unwrap();
+        if liquidity > 0 {
+            mint_to(ctx, &mint, &treasury_pda(&mint), liquidity).await;
+        }
+    }
+
+    let market = Market {
+        usdc_mint,
+        sol_mint,
+        usdc_price: Pubkey::new_unique(),
+        sol_price: Pubkey::new_unique(),
+    };
+    refresh_prices(ctx, &market, 1, 100).await;
+    market
+}
+
+/// Republishes both feeds at the current clock so they never go stale mid-test.
+pub async fn refresh_prices(
+    ctx: &mut ProgramTestContext,
+    market: &Market,
+    usdc_price: i64,
+    sol_price: i64,
+) {
+    let now = clock(ctx).await.unix_timestamp;
+    set_price(
+        ctx,
+        &market.usdc_price,
+        lending::constants::USDC_USD_FEED_ID,
+        usdc_price,
+        0,
+        0,
+        now,
+    );
+    set_price(
+        ctx,
+        &market.sol_price,
+        lending::constants::SOL_USD_FEED_ID,
+        sol_price,
+        0,
+        0,
+        now,
+    );
+}
+
+/// A wallet with an initialized position and funded token accounts for both banks.
+pub async fn setup_user(
+    ctx: &mut ProgramTestContext,
+    market: &Market,
+    usdc_amount: u64,
+    sol_amount: u64,
+) -> Keypair {
+    let user = funded_keypair(ctx, 10_000_000_000).await;
+    fund_ata(ctx, &market.usdc_mint, &user.pubkey(), usdc_amount).await;
+    fund_ata(ctx, &market.sol_mint, &user.pubkey(), sol_amount).await;
+    send(
+        ctx,
+        &[init_user_ix(
+            &user.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+        )],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    user
+}
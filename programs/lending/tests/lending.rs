@@ -0,0 +1,3077 @@
+mod common;
+
+use anchor_lang::AccountSerialize;
+use anchor_spl::token::spl_token;
+use common::*;
+use lending::{
+    constants::{AMOUNT_ALL, SECONDS_PER_YEAR},
+    error::ErrorCode,
+    instructions::BankConfigParams,
+    math,
+    state::{
+        Bank, Delegation, ReferralFees, RewardSide, Roles, TermDeposit, TermLoan, TermPool, User,
+    },
+};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction};
+
+#[tokio::test]
+async fn init_bank_records_authority_and_risk_params() {
+    let mut ctx = start().await;
+    let mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let payer = ctx.payer.pubkey();
+
+    send(&mut ctx, &[init_bank_ix(&payer, &mint, 80, 70, false)], &[])
+        .await
+        .unwrap();
+
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&mint)).await;
+    assert_eq!(bank.authority, payer);
+    assert_eq!(bank.mint_address, mint);
+    assert_eq!(bank.liquidation_threshold, 80);
+    assert_eq!(bank.max_ltv, 70);
+    assert!(!bank.unsafe_mint_allowed);
+    assert_eq!(bank.version, Bank::VERSION);
+    assert_eq!(token_balance(&mut ctx, &treasury_pda(&mint)).await, 0);
+}
+
+#[tokio::test]
+async fn init_bank_rejects_mint_with_freeze_authority() {
+    let mut ctx = start().await;
+    let mint = create_freezable_mint(&mut ctx, USDC_DECIMALS).await;
+    let payer = ctx.payer.pubkey();
+
+    let result = send(&mut ctx, &[init_bank_ix(&payer, &mint, 80, 75, false)], &[]).await;
+
+    assert_error(result, ErrorCode::UnsafeMint);
+}
+
+#[tokio::test]
+async fn init_bank_records_override_for_unsafe_mint() {
+    let mut ctx = start().await;
+    let mint = create_permanent_delegate_mint(&mut ctx, USDC_DECIMALS).await;
+    let payer = ctx.payer.pubkey();
+    let result = send(
+        &mut ctx,
+        &[with_token_2022(
+            init_bank_ix(&payer, &mint, 80, 75, false),
+            &mint,
+            &[],
+        )],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::UnsafeMint);
+
+    send(
+        &mut ctx,
+        &[with_token_2022(
+            init_bank_ix(&payer, &mint, 80, 75, true),
+            &mint,
+            &[],
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&mint)).await;
+    assert!(bank.unsafe_mint_allowed);
+}
+
+#[tokio::test]
+async fn init_bank_rejects_transfer_hook_that_can_still_be_set() {
+    let mut ctx = start().await;
+    let mint = create_unset_transfer_hook_mint(&mut ctx, USDC_DECIMALS).await;
+    let payer = ctx.payer.pubkey();
+
+    let result = send(
+        &mut ctx,
+        &[with_token_2022(
+            init_bank_ix(&payer, &mint, 80, 75, false),
+            &mint,
+            &[],
+        )],
+        &[],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::UnsafeMint);
+}
+
+#[tokio::test]
+async fn init_user_records_owner_and_both_mints() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 0, 0).await;
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.owner, user.pubkey());
+    assert_eq!(position.usdc_address, market.usdc_mint);
+    assert_eq!(position.sol_address, market.sol_mint);
+    assert_eq!(position.deposited_usdc, 0);
+}
+
+#[tokio::test]
+async fn positions_reject_banks_outside_their_two_assets() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let other_mint = create_mint(&mut ctx, SOL_DECIMALS).await;
+    send(
+        &mut ctx,
+        &[init_bank_ix(&payer, &other_mint, 80, 75, false)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let user = setup_user(&mut ctx, &market, 0, 0).await;
+    fund_ata(&mut ctx, &other_mint, &user.pubkey(), 1_000_000).await;
+
+    // a third bank would otherwise be booked into the SOL slot and redeemable against it
+    let result = send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &other_mint, 1_000_000)],
+        &[&user],
+    )
+    .await;
+    assert_error(result, ErrorCode::UnknownBank);
+
+    let result = send(
+        &mut ctx,
+        &[init_user_ix(&payer, &market.usdc_mint, &market.usdc_mint)],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::InvalidConfig);
+}
+
+#[tokio::test]
+async fn deposit_moves_tokens_into_treasury() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let treasury = treasury_pda(&market.usdc_mint);
+    assert_eq!(
+        token_balance(&mut ctx, &treasury).await,
+        BANK_LIQUIDITY + 1_000_000
+    );
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.deposited_usdc, 1_000_000);
+}
+
+#[tokio::test]
+async fn deposit_worth_no_shares_fails_with_amount_too_small() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+
+    let result = send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 0)],
+        &[&user],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::AmountTooSmall);
+}
+
+#[tokio::test]
+async fn withdraw_returns_tokens_to_user() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    send(
+        &mut ctx,
+        &[withdraw_ix(
+            &user.pubkey(),
+            &market.usdc_mint,
+            &market,
+            400_000,
+        )],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let ata = anchor_spl::associated_token::get_associated_token_address(
+        &user.pubkey(),
+        &market.usdc_mint,
+    );
+    assert_eq!(token_balance(&mut ctx, &ata).await, 400_000);
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.deposited_usdc, 600_000);
+}
+
+#[tokio::test]
+async fn withdraw_without_deposit_fails_with_insufficient_funds() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let sol_depositor = setup_user(&mut ctx, &market, 0, 1_000_000).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(
+            &sol_depositor.pubkey(),
+            &market.sol_mint,
+            1_000_000,
+        )],
+        &[&sol_depositor],
+    )
+    .await
+    .unwrap();
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let result = send(
+        &mut ctx,
+        &[withdraw_ix(&user.pubkey(), &market.sol_mint, &market, 1)],
+        &[&user],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::InsufficientFunds);
+}
+
+#[tokio::test]
+async fn withdraw_cannot_leave_debt_uncovered() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    // $1 of USDC backing $0.50 of SOL debt, with a 75% max LTV
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 5_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let withdraw = |amount| withdraw_ix(&user.pubkey(), &market.usdc_mint, &market, amount);
+
+    for amount in [400_000, AMOUNT_ALL] {
+        let result = send(&mut ctx, &[withdraw(amount)], &[&user]).await;
+        assert_error(result, ErrorCode::PositionUnhealthy);
+    }
+
+    send(&mut ctx, &[withdraw(300_000)], &[&user])
+        .await
+        .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.deposited_usdc, 700_000);
+}
+
+#[tokio::test]
+async fn transfer_fee_mint_credits_only_what_reaches_the_vault() {
+    let mut ctx = start().await;
+    // 1% on every transfer, uncapped
+    let mint = create_fee_mint(&mut ctx, USDC_DECIMALS, 100, u64::MAX).await;
+    let market = setup_market(&mut ctx).await;
+    let market = Market {
+        usdc_mint: mint,
+        ..market
+    };
+    let payer = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[with_token_2022(
+            init_bank_ix(&payer, &mint, 80, 75, false),
+            &mint,
+            &[],
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let user = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let ata = fund_ata_2022(&mut ctx, &mint, &user.pubkey(), 1_000_000).await;
+
+    send(
+        &mut ctx,
+        &[
+            init_user_ix(&user.pubkey(), &mint, &market.sol_mint),
+            with_token_2022(
+                deposit_ix(&user.pubkey(), &mint, 1_000_000),
+                &mint,
+                &[user.pubkey()],
+            ),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let treasury = treasury_pda(&mint);
+    assert_eq!(token_balance(&mut ctx, &treasury).await, 990_000);
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.deposited_usdc, 990_000);
+
+    send(
+        &mut ctx,
+        &[with_token_2022(
+            withdraw_ix(&user.pubkey(), &mint, &market, 990_000),
+            &mint,
+            &[user.pubkey()],
+        )],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(token_balance(&mut ctx, &treasury).await, 0);
+    assert_eq!(token_balance(&mut ctx, &ata).await, 980_100);
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&mint)).await;
+    assert_eq!(bank.total_deposits, 0);
+}
+
+#[tokio::test]
+async fn borrow_against_collateral_transfers_tokens() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    send(
+        &mut ctx,
+        &[borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let ata = anchor_spl::associated_token::get_associated_token_address(
+        &user.pubkey(),
+        &market.sol_mint,
+    );
+    assert_eq!(token_balance(&mut ctx, &ata).await, 1_000);
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 1_000);
+    assert_eq!(position.borrowed_usdc, 0);
+}
+
+#[tokio::test]
+async fn borrow_fee_is_added_to_debt_and_shared_with_host() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let host = Pubkey::new_unique();
+    let host_ata = fund_ata(&mut ctx, &market.sol_mint, &host, 0).await;
+    let borrow = |amount, host_fee_receiver| {
+        borrow_with_fees_ix(
+            &user.pubkey(),
+            &user.pubkey(),
+            &market.sol_mint,
+            &market,
+            amount,
+            host_fee_receiver,
+            None,
+        )
+    };
+    send(
+        &mut ctx,
+        &[
+            update_bank_config_ix(
+                &payer,
+                &market.sol_mint,
+                BankConfigParams {
+                    borrow_fee_bps: Some(100),
+                    host_fee_percent: Some(20),
+                    ..BankConfigParams::default()
+                },
+            ),
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow(10_000, Some(host_ata)),
+            borrow(5_000, None),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let ata = anchor_spl::associated_token::get_associated_token_address(
+        &user.pubkey(),
+        &market.sol_mint,
+    );
+    assert_eq!(token_balance(&mut ctx, &ata).await, 15_000);
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 15_150);
+    // a fifth of the first fee went to the host; the second borrow named none
+    assert_eq!(token_balance(&mut ctx, &host_ata).await, 20);
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.sol_mint)).await;
+    assert_eq!(bank.protocol_fees, 130);
+}
+
+#[tokio::test]
+async fn referrer_is_credited_a_share_of_borrow_fees() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let referrer = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let user = funded_keypair(&mut ctx, 1_000_000_000).await;
+    fund_ata(&mut ctx, &market.usdc_mint, &user.pubkey(), 1_000_000).await;
+    let borrow = |amount, referrer| {
+        borrow_with_fees_ix(
+            &user.pubkey(),
+            &user.pubkey(),
+            &market.sol_mint,
+            &market,
+            amount,
+            None,
+            referrer,
+        )
+    };
+    send(
+        &mut ctx,
+        &[
+            update_bank_config_ix(
+                &payer,
+                &market.sol_mint,
+                BankConfigParams {
+                    borrow_fee_bps: Some(100),
+                    referral_fee_percent: Some(50),
+                    ..BankConfigParams::default()
+                },
+            ),
+            init_referred_user_ix(
+                &user.pubkey(),
+                &market.usdc_mint,
+                &market.sol_mint,
+                0,
+                Some(referrer.pubkey()),
+            ),
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let result = send(&mut ctx, &[borrow(10_000, None)], &[&user]).await;
+    assert_error(result, ErrorCode::ReferralAccountMismatch);
+    send(
+        &mut ctx,
+        &[borrow(10_000, Some(referrer.pubkey()))],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(
+        (position.referrer, position.borrowed_sol),
+        (referrer.pubkey(), 10_100)
+    );
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.sol_mint)).await;
+    assert_eq!((bank.protocol_fees, bank.referral_fees_owed), (50, 50));
+
+    let destination = fund_ata(&mut ctx, &market.sol_mint, &referrer.pubkey(), 0).await;
+    send(
+        &mut ctx,
+        &[claim_referral_fees_ix(
+            &referrer.pubkey(),
+            &market.sol_mint,
+            &destination,
+        )],
+        &[&referrer],
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut ctx, &destination).await, 50);
+    let owed: ReferralFees = fetch(
+        &mut ctx,
+        &referral_fees_pda(&referrer.pubkey(), &market.sol_mint),
+    )
+    .await;
+    assert_eq!(owed.owed, 0);
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.sol_mint)).await;
+    assert_eq!(bank.referral_fees_owed, 0);
+}
+
+#[tokio::test]
+async fn init_user_rejects_self_referral() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = funded_keypair(&mut ctx, 1_000_000_000).await;
+
+    let result = send(
+        &mut ctx,
+        &[init_referred_user_ix(
+            &user.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+            0,
+            Some(user.pubkey()),
+        )],
+        &[&user],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::InvalidConfig);
+}
+
+#[tokio::test]
+async fn stable_borrow_locks_its_rate_until_rebalanced() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let sol = market.sol_mint;
+    let lender = setup_user(&mut ctx, &market, 0, 10_000).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&lender.pubkey(), &sol, 10_000),
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+        ],
+        &[&lender, &borrower],
+    )
+    .await
+    .unwrap();
+    let borrow = |amount| borrow_stable_ix(&borrower.pubkey(), &sol, &market, amount);
+
+    let result = send(&mut ctx, &[borrow(1)], &[&borrower]).await;
+    assert_error(result, ErrorCode::StableBorrowDisabled);
+
+    send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &sol,
+            BankConfigParams {
+                stable_borrow_enabled: Some(true),
+                stable_rate_premium: Some(0.02),
+                stable_rebalance_utilization: Some(90),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    send(&mut ctx, &[borrow(1_000)], &[&borrower])
+        .await
+        .unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(
+        (position.stable_borrowed_sol, position.borrowed_sol_shares),
+        (1_000, 0)
+    );
+    assert!((position.stable_sol_rate - 0.07).abs() < 1e-12);
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&sol)).await;
+    assert_eq!(
+        (bank.total_stable_borrowed, bank.total_borrowed),
+        (1_000, 0)
+    );
+
+    // at 10% utilization the locked rate stands, even after the variable rate moves
+    send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &sol,
+            BankConfigParams {
+                interest_rate: Some(0.10),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[rebalance_stable_rate_ix(&sol, &borrower.pubkey())],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::RebalanceNotAllowed);
+    // the debt is in SOL, so the USDC bank has nothing of this position's to re-price
+    let result = send(
+        &mut ctx,
+        &[rebalance_stable_rate_ix(
+            &market.usdc_mint,
+            &borrower.pubkey(),
+        )],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::NoStableDebt);
+
+    // lenders pulling out pushes utilization past the threshold
+    send(
+        &mut ctx,
+        &[
+            withdraw_ix(&lender.pubkey(), &sol, &market, 8_900),
+            rebalance_stable_rate_ix(&sol, &borrower.pubkey()),
+        ],
+        &[&lender],
+    )
+    .await
+    .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert!((position.stable_sol_rate - 0.12).abs() < 1e-12);
+
+    send(
+        &mut ctx,
+        &[repay_stable_ix(&borrower.pubkey(), &sol, AMOUNT_ALL)],
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(position.stable_borrowed_sol, 0);
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&sol)).await;
+    assert_eq!(bank.total_stable_borrowed, 0);
+}
+
+#[tokio::test]
+async fn term_loan_fixes_interest_to_maturity_then_rolls_into_variable_debt() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let sol = market.sol_mint;
+    let lender = setup_user(&mut ctx, &market, 0, 1_000_000_000).await;
+    let borrower = setup_user(&mut ctx, &market, 100_000_000, 0).await;
+    let now = clock(&mut ctx).await.unix_timestamp;
+    let term = SECONDS_PER_YEAR as i64;
+    let maturity = now + term;
+    send(
+        &mut ctx,
+        &[
+            create_term_pool_ix(&payer, &sol, maturity, 0.10),
+            lend_term_ix(&lender.pubkey(), &sol, maturity, 1_000_000_000),
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 100_000_000),
+        ],
+        &[&lender, &borrower],
+    )
+    .await
+    .unwrap();
+
+    let borrow = |amount| {
+        borrow_term_ix(
+            &borrower.pubkey(),
+            &sol,
+            &market.sol_price,
+            &market.usdc_mint,
+            &market.usdc_price,
+            maturity,
+            amount,
+        )
+    };
+    send(&mut ctx, &[borrow(500_000_000)], &[&borrower])
+        .await
+        .unwrap();
+    // a year at 10% is owed up front; on-chain float math may differ in the last unit
+    let pool: TermPool = fetch(&mut ctx, &term_pool_pda(&bank_pda(&sol), maturity)).await;
+    let owed = pool.outstanding;
+    let expected = math::accrue_interest(500_000_000, 0.10, term).unwrap();
+    assert!(owed.abs_diff(expected) <= 1);
+    assert_eq!(pool.available, 500_000_000);
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(position.term_borrowed_sol, owed);
+    // term liquidity sits in the pool's vault, out of reach of the variable side
+    let vault = term_vault_pda(&term_pool_pda(&bank_pda(&sol), maturity));
+    assert_eq!(token_balance(&mut ctx, &vault).await, 500_000_000);
+    assert_eq!(
+        token_balance(&mut ctx, &treasury_pda(&sol)).await,
+        BANK_LIQUIDITY
+    );
+
+    // lenders are locked in until maturity
+    let result = send(
+        &mut ctx,
+        &[withdraw_term_ix(&lender.pubkey(), &sol, maturity)],
+        &[&lender],
+    )
+    .await;
+    assert_error(result, ErrorCode::TermNotMatured);
+
+    advance_clock(&mut ctx, term).await;
+    let result = send(&mut ctx, &[borrow(1)], &[&borrower]).await;
+    assert_error(result, ErrorCode::TermPoolMatured);
+    // the undrawn half is back for the taking; the shares behind the loan stay with the lender
+    let lender_ata =
+        anchor_spl::associated_token::get_associated_token_address(&lender.pubkey(), &sol);
+    send(
+        &mut ctx,
+        &[withdraw_term_ix(&lender.pubkey(), &sol, maturity)],
+        &[&lender],
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut ctx, &lender_ata).await, 500_000_000);
+    let deposit_address =
+        term_deposit_pda(&term_pool_pda(&bank_pda(&sol), maturity), &lender.pubkey());
+    let deposit: TermDeposit = fetch(&mut ctx, &deposit_address).await;
+    assert!(deposit.shares > 0);
+
+    // an unpaid loan rolls into variable debt, which repays the pool
+    send(
+        &mut ctx,
+        &[
+            roll_term_loan_ix(&payer, &sol, &borrower.pubkey(), maturity),
+            withdraw_term_ix(&lender.pubkey(), &sol, maturity),
+        ],
+        &[&lender],
+    )
+    .await
+    .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(
+        (position.term_borrowed_sol, position.borrowed_sol_shares),
+        (0, owed)
+    );
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&sol)).await;
+    assert_eq!(
+        (
+            bank.total_borrowed,
+            bank.total_term_borrowed,
+            bank.total_term_lent
+        ),
+        (owed, 0, 0)
+    );
+    assert_eq!(
+        token_balance(&mut ctx, &lender_ata).await,
+        1_000_000_000 + owed - 500_000_000
+    );
+    assert!(ctx
+        .banks_client
+        .get_account(deposit_address)
+        .await
+        .unwrap()
+        .is_none());
+    // rolling lent the pool the loan's balance out of the treasury
+    assert_eq!(token_balance(&mut ctx, &vault).await, 0);
+    assert_eq!(
+        token_balance(&mut ctx, &treasury_pda(&sol)).await,
+        BANK_LIQUIDITY - owed
+    );
+}
+
+#[tokio::test]
+async fn withdraw_term_with_everything_lent_out_fails_with_term_loans_outstanding() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let sol = market.sol_mint;
+    let lender = setup_user(&mut ctx, &market, 0, 100_000_000).await;
+    let borrower = setup_user(&mut ctx, &market, 100_000_000, 0).await;
+    let maturity = clock(&mut ctx).await.unix_timestamp + 60;
+    send(
+        &mut ctx,
+        &[
+            create_term_pool_ix(&payer, &sol, maturity, 0.10),
+            lend_term_ix(&lender.pubkey(), &sol, maturity, 100_000_000),
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 100_000_000),
+            borrow_term_ix(
+                &borrower.pubkey(),
+                &sol,
+                &market.sol_price,
+                &market.usdc_mint,
+                &market.usdc_price,
+                maturity,
+                100_000_000,
+            ),
+        ],
+        &[&lender, &borrower],
+    )
+    .await
+    .unwrap();
+
+    // nothing has come back into the vault for the lender to take
+    advance_clock(&mut ctx, 60).await;
+    let result = send(
+        &mut ctx,
+        &[withdraw_term_ix(&lender.pubkey(), &sol, maturity)],
+        &[&lender],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::TermLoansOutstanding);
+}
+
+#[tokio::test]
+async fn swap_collateral_only_checks_health_once_the_swap_lands() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let (sol, usdc) = (market.sol_mint, market.usdc_mint);
+    let (pool, _) = mock_swap::pool_authority();
+    fund_ata(&mut ctx, &usdc, &pool, 1_000_000_000).await;
+    let pool_sol = fund_ata(&mut ctx, &sol, &pool, 0).await;
+    // $100 of SOL backing $70 of USDC debt: too close to the limit to withdraw any of it
+    let user = setup_user(&mut ctx, &market, 0, 1_000_000_000).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &sol, 1_000_000_000),
+            borrow_ix(&user.pubkey(), &usdc, &market, 70_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let swap = |min_amount_out, rate| {
+        swap_collateral_ix(
+            &user.pubkey(),
+            &sol,
+            &market.sol_price,
+            &usdc,
+            &market.usdc_price,
+            1_000_000_000,
+            min_amount_out,
+            rate,
+        )
+    };
+
+    let result = send(&mut ctx, &[swap(100_000_001, (1, 10))], &[&user]).await;
+    assert_error(result, ErrorCode::SlippageExceeded);
+    // at half the price the new collateral no longer covers the debt
+    let result = send(&mut ctx, &[swap(0, (1, 20))], &[&user]).await;
+    assert_error(result, ErrorCode::PositionUnhealthy);
+
+    send(&mut ctx, &[swap(100_000_000, (1, 10))], &[&user])
+        .await
+        .unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(
+        (position.deposited_sol_shares, position.deposited_usdc),
+        (0, 100_000_000)
+    );
+    assert_eq!(position.borrowed_usdc, 70_000_000);
+    let sol_bank: Bank = fetch(&mut ctx, &bank_pda(&sol)).await;
+    let usdc_bank: Bank = fetch(&mut ctx, &bank_pda(&usdc)).await;
+    assert_eq!(
+        (sol_bank.total_deposits, usdc_bank.total_deposits),
+        (0, 100_000_000)
+    );
+    assert_eq!(token_balance(&mut ctx, &pool_sol).await, 1_000_000_000);
+}
+
+#[tokio::test]
+async fn swaps_through_this_program_fail_with_invalid_swap() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let (sol, usdc) = (market.sol_mint, market.usdc_mint);
+    let user = setup_user(&mut ctx, &market, 0, 1_000_000_000).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &sol, 1_000_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let mut swap = swap_collateral_ix(
+        &user.pubkey(),
+        &sol,
+        &market.sol_price,
+        &usdc,
+        &market.usdc_price,
+        1_000_000_000,
+        0,
+        (1, 10),
+    );
+    for meta in &mut swap.accounts {
+        if meta.pubkey == mock_swap::ID {
+            meta.pubkey = lending::ID;
+        }
+    }
+    let result = send(&mut ctx, &[swap], &[&user]).await;
+
+    assert_error(result, ErrorCode::InvalidSwap);
+}
+
+#[tokio::test]
+async fn swap_debt_moves_the_loan_into_the_other_asset() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let (sol, usdc) = (market.sol_mint, market.usdc_mint);
+    let (pool, _) = mock_swap::pool_authority();
+    fund_ata(&mut ctx, &sol, &pool, 10_000_000_000).await;
+    let pool_usdc = fund_ata(&mut ctx, &usdc, &pool, 0).await;
+    // $100 of USDC backing $50 of SOL debt
+    let user = setup_user(&mut ctx, &market, 100_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &usdc, 100_000_000),
+            borrow_ix(&user.pubkey(), &sol, &market, 500_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let swap = |min_amount_out, rate| {
+        swap_debt_ix(
+            &user.pubkey(),
+            &sol,
+            &market.sol_price,
+            &usdc,
+            &market.usdc_price,
+            50_000_000,
+            min_amount_out,
+            rate,
+        )
+    };
+
+    let result = send(&mut ctx, &[swap(500_000_001, (10, 1))], &[&user]).await;
+    assert_error(result, ErrorCode::SlippageExceeded);
+    // a bad fill only pays down $20 of the SOL debt for $50 of new USDC debt
+    let result = send(&mut ctx, &[swap(0, (4, 1))], &[&user]).await;
+    assert_error(result, ErrorCode::PositionUnhealthy);
+
+    send(&mut ctx, &[swap(500_000_000, (10, 1))], &[&user])
+        .await
+        .unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(
+        (position.borrowed_sol_shares, position.borrowed_usdc),
+        (0, 50_000_000)
+    );
+    let sol_bank: Bank = fetch(&mut ctx, &bank_pda(&sol)).await;
+    let usdc_bank: Bank = fetch(&mut ctx, &bank_pda(&usdc)).await;
+    assert_eq!(
+        (sol_bank.total_borrowed, usdc_bank.total_borrowed),
+        (0, 50_000_000)
+    );
+    assert_eq!(token_balance(&mut ctx, &pool_usdc).await, 50_000_000);
+}
+
+#[tokio::test]
+async fn leverage_opens_and_closes_in_one_transaction() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let (pool, _) = mock_swap::pool_authority();
+    fund_ata(&mut ctx, &market.sol_mint, &pool, 10_000_000_000).await;
+    fund_ata(&mut ctx, &market.usdc_mint, &pool, 1_000_000_000).await;
+    let user = setup_user(&mut ctx, &market, 0, 1_000_000_000).await;
+    // $1 of USDC buys 0.01 SOL
+    let open = |leverage_bps, min_amount_out| {
+        open_leverage_ix(
+            &user.pubkey(),
+            &market,
+            1_000_000_000,
+            leverage_bps,
+            min_amount_out,
+            (10, 1),
+        )
+    };
+
+    let result = send(&mut ctx, &[open(10_000, 0)], &[&user]).await;
+    assert_error(result, ErrorCode::InvalidLeverage);
+    let result = send(&mut ctx, &[open(30_000, 2_000_000_001)], &[&user]).await;
+    assert_error(result, ErrorCode::SlippageExceeded);
+    // 5x on $100 would owe $400 against $500 of SOL, past the 75% max LTV
+    let result = send(&mut ctx, &[open(50_000, 0)], &[&user]).await;
+    assert_error(result, ErrorCode::PositionUnhealthy);
+
+    // 3x: $300 of SOL against $200 of USDC debt
+    send(&mut ctx, &[open(30_000, 2_000_000_000)], &[&user])
+        .await
+        .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(
+        (position.deposited_sol, position.borrowed_usdc),
+        (3_000_000_000, 200_000_000)
+    );
+    let sol_ata = anchor_spl::associated_token::get_associated_token_address(
+        &user.pubkey(),
+        &market.sol_mint,
+    );
+    assert_eq!(token_balance(&mut ctx, &sol_ata).await, 0);
+
+    send(
+        &mut ctx,
+        &[close_leverage_ix(
+            &user.pubkey(),
+            &market,
+            2_000_000_000,
+            200_000_000,
+            (1, 10),
+        )],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(
+        (position.deposited_sol, position.borrowed_usdc_shares),
+        (1_000_000_000, 0)
+    );
+    let usdc_bank: Bank = fetch(&mut ctx, &bank_pda(&market.usdc_mint)).await;
+    assert_eq!(usdc_bank.total_borrowed, 0);
+}
+
+#[tokio::test]
+async fn native_sol_is_wrapped_and_unwrapped_within_the_instruction() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let wsol = native_mint(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    send(&mut ctx, &[init_bank_ix(&payer, &wsol, 80, 75, false)], &[])
+        .await
+        .unwrap();
+    let market = Market {
+        sol_mint: wsol,
+        ..market
+    };
+    let user = setup_user(&mut ctx, &market, 100_000_000, 0).await;
+    // the user's own wrapped SOL account is left exactly as it was
+    let wsol_ata =
+        anchor_spl::associated_token::get_associated_token_address(&user.pubkey(), &wsol);
+    send(
+        &mut ctx,
+        &[
+            system_instruction::transfer(&user.pubkey(), &wsol_ata, 1_000_000_000),
+            spl_token::instruction::sync_native(&spl_token::id(), &wsol_ata).unwrap(),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let result = send(
+        &mut ctx,
+        &[deposit_native_ix(&user.pubkey(), &market.usdc_mint, 1)],
+        &[&user],
+    )
+    .await;
+    assert_error(result, ErrorCode::NotNativeMint);
+
+    // the transaction fee is the payer's, so the user's lamports move by exactly the amounts
+    let steps = [
+        deposit_native_ix(&user.pubkey(), &wsol, 2_000_000_000),
+        withdraw_native_ix(&user.pubkey(), &wsol, &market, 1_000_000_000),
+        borrow_native_ix(&user.pubkey(), &wsol, &market, 500_000_000),
+        repay_native_ix(&user.pubkey(), &wsol, AMOUNT_ALL),
+    ];
+    let changes = [-2_000_000_000, 1_000_000_000, 500_000_000, -500_000_000];
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 100_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    for (ix, change) in steps.into_iter().zip(changes) {
+        let before = ctx.banks_client.get_balance(user.pubkey()).await.unwrap();
+        send(&mut ctx, &[ix], &[&user]).await.unwrap();
+        let after = ctx.banks_client.get_balance(user.pubkey()).await.unwrap();
+        assert_eq!(after as i64 - before as i64, change);
+        assert_eq!(token_balance(&mut ctx, &wsol_ata).await, 1_000_000_000);
+        assert!(ctx
+            .banks_client
+            .get_account(wsol_pda(&user.pubkey()))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(
+        (position.deposited_sol, position.borrowed_sol_shares),
+        (1_000_000_000, 0)
+    );
+    assert_eq!(
+        token_balance(&mut ctx, &treasury_pda(&wsol)).await,
+        1_000_000_000
+    );
+}
+
+#[tokio::test]
+async fn payments_need_exactly_one_source_account() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let wsol = native_mint(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    send(&mut ctx, &[init_bank_ix(&payer, &wsol, 80, 75, false)], &[])
+        .await
+        .unwrap();
+    let market = Market {
+        sol_mint: wsol,
+        ..market
+    };
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let user_ata = anchor_spl::associated_token::get_associated_token_address(
+        &user.pubkey(),
+        &market.usdc_mint,
+    );
+
+    // a plain deposit without the signer's token account
+    let result = send(
+        &mut ctx,
+        &[without_account(
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            &user_ata,
+        )],
+        &[&user],
+    )
+    .await;
+    assert_error(result, ErrorCode::PaymentAccountMismatch);
+
+    // a native deposit without the wrapped SOL account
+    let result = send(
+        &mut ctx,
+        &[without_account(
+            deposit_native_ix(&user.pubkey(), &wsol, 1_000_000),
+            &wsol_pda(&user.pubkey()),
+        )],
+        &[&user],
+    )
+    .await;
+    assert_error(result, ErrorCode::PaymentAccountMismatch);
+}
+
+#[tokio::test]
+async fn borrow_above_collateral_fails_as_unhealthy() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let result = send(
+        &mut ctx,
+        &[borrow_ix(
+            &user.pubkey(),
+            &market.sol_mint,
+            &market,
+            100_000_000,
+        )],
+        &[&user],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::PositionUnhealthy);
+}
+
+#[tokio::test]
+async fn borrow_limit_scales_prices_and_counts_existing_debt() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    // the same $1 and $100 prices, published the way Pyth does with eight decimals
+    let now = clock(&mut ctx).await.unix_timestamp;
+    set_price(
+        &mut ctx,
+        &market.usdc_price,
+        lending::constants::USDC_USD_FEED_ID,
+        100_000_000,
+        0,
+        -8,
+        now,
+    );
+    set_price(
+        &mut ctx,
+        &market.sol_price,
+        lending::constants::SOL_USD_FEED_ID,
+        10_000_000_000,
+        0,
+        -8,
+        now,
+    );
+    // $1 of USDC at a 75% max LTV covers 0.0075 SOL of debt in total
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let borrow = |amount| borrow_ix(&user.pubkey(), &market.sol_mint, &market, amount);
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow(5_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    // fits on its own, but not on top of the debt already owed
+    let result = send(&mut ctx, &[borrow(3_000_000)], &[&user]).await;
+    assert_error(result, ErrorCode::PositionUnhealthy);
+
+    send(&mut ctx, &[borrow(2_000_000)], &[&user])
+        .await
+        .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 7_000_000);
+}
+
+#[tokio::test]
+async fn borrow_rejects_stale_price() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    advance_clock(&mut ctx, lending::constants::MAX_AGE as i64 + 1).await;
+    let result = send(
+        &mut ctx,
+        &[borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1)],
+        &[&user],
+    )
+    .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn delegate_borrows_against_owner_collateral_within_allowance() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let owner = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let delegate = funded_keypair(&mut ctx, 1_000_000_000).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&owner.pubkey(), &market.usdc_mint, 1_000_000),
+            approve_delegation_ix(&owner.pubkey(), &delegate.pubkey(), &market.sol_mint, 1_000),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let borrow = |amount| {
+        borrow_for_ix(
+            &delegate.pubkey(),
+            &owner.pubkey(),
+            &market.sol_mint,
+            &market,
+            amount,
+        )
+    };
+    send(&mut ctx, &[borrow(600)], &[&delegate]).await.unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&owner.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 600);
+    let delegate_ata = anchor_spl::associated_token::get_associated_token_address(
+        &delegate.pubkey(),
+        &market.sol_mint,
+    );
+    assert_eq!(token_balance(&mut ctx, &delegate_ata).await, 600);
+    let delegation: Delegation = fetch(
+        &mut ctx,
+        &delegation_pda(&owner.pubkey(), &delegate.pubkey(), &market.sol_mint, 0),
+    )
+    .await;
+    assert_eq!(delegation.allowance, 400);
+
+    let result = send(&mut ctx, &[borrow(401)], &[&delegate]).await;
+    assert_error(result, ErrorCode::InsufficientAllowance);
+
+    send(
+        &mut ctx,
+        &[revoke_delegation_ix(
+            &owner.pubkey(),
+            &delegate.pubkey(),
+            &market.sol_mint,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+    assert!(send(&mut ctx, &[borrow(1)], &[&delegate]).await.is_err());
+}
+
+#[tokio::test]
+async fn delegation_only_covers_its_own_sub_account() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let owner = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let delegate = funded_keypair(&mut ctx, 1_000_000_000).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&owner.pubkey(), &market.usdc_mint, 1_000_000),
+            init_sub_account_ix(&owner.pubkey(), &market.usdc_mint, &market.sol_mint, 1),
+            approve_sub_account_delegation_ix(
+                &owner.pubkey(),
+                &delegate.pubkey(),
+                &market.sol_mint,
+                1_000,
+                1,
+            ),
+        ],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    // an allowance on the empty sub-account cannot reach the collateral in the default one
+    let mut borrow = borrow_for_ix(
+        &delegate.pubkey(),
+        &owner.pubkey(),
+        &market.sol_mint,
+        &market,
+        600,
+    );
+    let default_delegation =
+        delegation_pda(&owner.pubkey(), &delegate.pubkey(), &market.sol_mint, 0);
+    let meta = borrow
+        .accounts
+        .iter_mut()
+        .find(|meta| meta.pubkey == default_delegation)
+        .unwrap();
+    meta.pubkey = delegation_pda(&owner.pubkey(), &delegate.pubkey(), &market.sol_mint, 1);
+    let result = send(&mut ctx, &[borrow], &[&delegate]).await;
+
+    assert!(result.is_err());
+    let position: User = fetch(&mut ctx, &user_pda(&owner.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 0);
+}
+
+#[tokio::test]
+async fn borrow_for_another_owner_without_delegation_fails_with_not_delegated() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let owner = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let stranger = funded_keypair(&mut ctx, 1_000_000_000).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&owner.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let borrow = borrow_for_ix(
+        &stranger.pubkey(),
+        &owner.pubkey(),
+        &market.sol_mint,
+        &market,
+        1_000,
+    );
+    let delegation = delegation_pda(&owner.pubkey(), &stranger.pubkey(), &market.sol_mint, 0);
+    let result = send(
+        &mut ctx,
+        &[without_account(borrow, &delegation)],
+        &[&stranger],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::NotDelegated);
+}
+
+#[tokio::test]
+async fn repay_returns_tokens_to_treasury() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    send(
+        &mut ctx,
+        &[repay_ix(&user.pubkey(), &market.sol_mint, 500)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let treasury = treasury_pda(&market.sol_mint);
+    assert_eq!(
+        token_balance(&mut ctx, &treasury).await,
+        BANK_LIQUIDITY - 500
+    );
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 500);
+}
+
+#[tokio::test]
+async fn repay_for_another_owner_reduces_their_debt() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let bot = setup_user(&mut ctx, &market, 0, 1_000).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    send(
+        &mut ctx,
+        &[repay_for_ix(
+            &bot.pubkey(),
+            &user.pubkey(),
+            &market.sol_mint,
+            400,
+        )],
+        &[&bot],
+    )
+    .await
+    .unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 600);
+    let bot_ata =
+        anchor_spl::associated_token::get_associated_token_address(&bot.pubkey(), &market.sol_mint);
+    assert_eq!(token_balance(&mut ctx, &bot_ata).await, 600);
+}
+
+#[tokio::test]
+async fn repay_above_debt_fails_with_over_repay() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    mint_to(
+        &mut ctx,
+        &market.sol_mint,
+        &anchor_spl::associated_token::get_associated_token_address(
+            &user.pubkey(),
+            &market.sol_mint,
+        ),
+        10_000,
+    )
+    .await;
+
+    let result = send(
+        &mut ctx,
+        &[repay_ix(&user.pubkey(), &market.sol_mint, 5_000)],
+        &[&user],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::OverRepay);
+}
+
+#[tokio::test]
+async fn sub_accounts_are_isolated_positions() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            init_sub_account_ix(&user.pubkey(), &market.usdc_mint, &market.sol_mint, 1),
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let main: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    let second: User = fetch(&mut ctx, &sub_account_pda(&user.pubkey(), 1)).await;
+    assert_eq!((main.index, main.deposited_usdc), (0, 1_000_000));
+    assert_eq!((second.index, second.deposited_usdc), (1, 0));
+}
+
+#[tokio::test]
+async fn transfer_collateral_moves_deposits_while_source_stays_healthy() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    // $1 of USDC backing $0.50 of SOL debt, with a 75% max LTV
+    send(
+        &mut ctx,
+        &[
+            init_sub_account_ix(&user.pubkey(), &market.usdc_mint, &market.sol_mint, 1),
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 5_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let transfer = |amount| {
+        transfer_collateral_ix(
+            &user.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+            0,
+            1,
+            &market.usdc_price,
+            &market.sol_price,
+            amount,
+        )
+    };
+
+    let result = send(&mut ctx, &[transfer(500_000)], &[&user]).await;
+    assert_error(result, ErrorCode::PositionUnhealthy);
+
+    // a transfer onto itself would be written back twice and mint collateral
+    let to_itself = transfer_collateral_ix(
+        &user.pubkey(),
+        &market.usdc_mint,
+        &market.sol_mint,
+        0,
+        0,
+        &market.usdc_price,
+        &market.sol_price,
+        100_000,
+    );
+    let result = send(&mut ctx, &[to_itself], &[&user]).await;
+    assert_error(result, ErrorCode::SubAccountMismatch);
+
+    send(&mut ctx, &[transfer(200_000)], &[&user])
+        .await
+        .unwrap();
+
+    let main: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    let second: User = fetch(&mut ctx, &sub_account_pda(&user.pubkey(), 1)).await;
+    assert_eq!(main.deposited_usdc, 800_000);
+    assert_eq!(second.deposited_usdc, 200_000);
+    assert_eq!(
+        main.deposited_usdc_shares + second.deposited_usdc_shares,
+        1_000_000
+    );
+}
+
+#[tokio::test]
+async fn amount_all_repays_and_withdraws_without_dust() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 10_000).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    advance_clock(&mut ctx, 30 * 24 * 60 * 60).await;
+    refresh_prices(&mut ctx, &market, 1, 100).await;
+
+    send(
+        &mut ctx,
+        &[
+            repay_ix(&user.pubkey(), &market.sol_mint, AMOUNT_ALL),
+            withdraw_ix(&user.pubkey(), &market.usdc_mint, &market, AMOUNT_ALL),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let position: User = fetch(&mut ctx, &user_pda(&user.pubkey())).await;
+    assert_eq!(position.borrowed_sol_shares, 0);
+    assert_eq!(position.borrowed_sol, 0);
+    assert_eq!(position.deposited_usdc_shares, 0);
+    assert_eq!(position.deposited_usdc, 0);
+    let sol_ata = anchor_spl::associated_token::get_associated_token_address(
+        &user.pubkey(),
+        &market.sol_mint,
+    );
+    // a month of 5% interest on the 1_000 borrowed
+    assert!(token_balance(&mut ctx, &sol_ata).await < 10_000);
+}
+
+#[tokio::test]
+async fn amount_all_covers_the_transfer_fee_of_a_fee_mint() {
+    let mut ctx = start().await;
+    // 1% on every transfer, uncapped, standing in for the SOL side
+    let mint = create_fee_mint(&mut ctx, SOL_DECIMALS, 100, u64::MAX).await;
+    let market = setup_market(&mut ctx).await;
+    let market = Market {
+        sol_mint: mint,
+        ..market
+    };
+    let payer = ctx.payer.pubkey();
+    let lender = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let borrower = funded_keypair(&mut ctx, 1_000_000_000).await;
+    fund_ata_2022(&mut ctx, &mint, &lender.pubkey(), 1_000_000).await;
+    fund_ata(&mut ctx, &market.usdc_mint, &borrower.pubkey(), 1_000_000).await;
+    send(
+        &mut ctx,
+        &[
+            with_token_2022(init_bank_ix(&payer, &mint, 80, 75, false), &mint, &[]),
+            init_user_ix(&lender.pubkey(), &market.usdc_mint, &mint),
+            with_token_2022(
+                deposit_ix(&lender.pubkey(), &mint, 1_000_000),
+                &mint,
+                &[lender.pubkey()],
+            ),
+            init_user_ix(&borrower.pubkey(), &market.usdc_mint, &mint),
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            with_token_2022(
+                borrow_ix(&borrower.pubkey(), &mint, &market, 100_000),
+                &mint,
+                &[borrower.pubkey()],
+            ),
+        ],
+        &[&lender, &borrower],
+    )
+    .await
+    .unwrap();
+    // the borrow itself arrived 1% short, so top up enough to cover the fee both ways
+    let ata = fund_ata_2022(&mut ctx, &mint, &borrower.pubkey(), 10_000).await;
+
+    send(
+        &mut ctx,
+        &[with_token_2022(
+            repay_ix(&borrower.pubkey(), &mint, AMOUNT_ALL),
+            &mint,
+            &[borrower.pubkey()],
+        )],
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+
+    // 101_011 sent, of which the mint withheld 1_011
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(
+        (position.borrowed_sol_shares, position.borrowed_sol),
+        (0, 0)
+    );
+    assert_eq!(
+        token_balance(&mut ctx, &ata).await,
+        99_000 + 10_000 - 101_011
+    );
+}
+
+#[tokio::test]
+async fn liquidate_healthy_position_fails_with_not_under_collateralized() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&borrower.pubkey(), &market.sol_mint, &market, 1_000),
+        ],
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    let liquidator = setup_user(&mut ctx, &market, 0, 1_000_000).await;
+
+    let result = send(
+        &mut ctx,
+        &[liquidate_ix(
+            &liquidator.pubkey(),
+            &borrower.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+            &market.usdc_price,
+            &market.sol_price,
+        )],
+        &[&liquidator],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::NotUnderCollateralized);
+}
+
+#[tokio::test]
+async fn liquidate_counts_collateral_in_both_banks() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 10_000_000).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            deposit_ix(&borrower.pubkey(), &market.sol_mint, 10_000_000),
+            borrow_ix(&borrower.pubkey(), &market.sol_mint, &market, 5_000_000),
+        ],
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    let liquidator = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+
+    // the USDC alone no longer covers the SOL debt, but the SOL deposited beside it does
+    refresh_prices(&mut ctx, &market, 1, 200).await;
+    let result = send(
+        &mut ctx,
+        &[liquidate_ix(
+            &liquidator.pubkey(),
+            &borrower.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+            &market.usdc_price,
+            &market.sol_price,
+        )],
+        &[&liquidator],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::NotUnderCollateralized);
+}
+
+#[tokio::test]
+async fn liquidate_underwater_position_repays_debt_and_seizes_collateral() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&borrower.pubkey(), &market.sol_mint, &market, 5_000_000),
+        ],
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    let liquidator = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+
+    // $1 of USDC backing 0.005 SOL is only safe while SOL stays below $160
+    refresh_prices(&mut ctx, &market, 1, 200).await;
+    send(
+        &mut ctx,
+        &[liquidate_ix(
+            &liquidator.pubkey(),
+            &borrower.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+            &market.usdc_price,
+            &market.sol_price,
+        )],
+        &[&liquidator],
+    )
+    .await
+    .unwrap();
+
+    let liquidator_sol = anchor_spl::associated_token::get_associated_token_address(
+        &liquidator.pubkey(),
+        &market.sol_mint,
+    );
+    let liquidator_usdc = anchor_spl::associated_token::get_associated_token_address(
+        &liquidator.pubkey(),
+        &market.usdc_mint,
+    );
+    assert_eq!(token_balance(&mut ctx, &liquidator_sol).await, 7_500_000);
+    let seized = token_balance(&mut ctx, &liquidator_usdc).await;
+    assert!((524_999..=525_000).contains(&seized), "seized {seized}");
+
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(position.borrowed_sol, 2_500_000);
+    assert_eq!(position.deposited_usdc, 1_000_000 - seized);
+}
+
+#[tokio::test]
+async fn liquidate_term_loan_underwater_before_maturity_repays_the_pool() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let sol = market.sol_mint;
+    let lender = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let maturity = clock(&mut ctx).await.unix_timestamp + 3_600;
+    send(
+        &mut ctx,
+        &[
+            create_term_pool_ix(&payer, &sol, maturity, 0.0),
+            lend_term_ix(&lender.pubkey(), &sol, maturity, 10_000_000),
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_term_ix(
+                &borrower.pubkey(),
+                &sol,
+                &market.sol_price,
+                &market.usdc_mint,
+                &market.usdc_price,
+                maturity,
+                5_000_000,
+            ),
+        ],
+        &[&lender, &borrower],
+    )
+    .await
+    .unwrap();
+    let liquidator = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+
+    // the position holds term debt only, which the liquidation pays back into the pool
+    refresh_prices(&mut ctx, &market, 1, 200).await;
+    send(
+        &mut ctx,
+        &[liquidate_term_loan_ix(
+            &liquidator.pubkey(),
+            &borrower.pubkey(),
+            &market.usdc_mint,
+            &sol,
+            &market.usdc_price,
+            &market.sol_price,
+            Some(maturity),
+        )],
+        &[&liquidator],
+    )
+    .await
+    .unwrap();
+
+    let term_pool = term_pool_pda(&bank_pda(&sol), maturity);
+    let loan: TermLoan = fetch(
+        &mut ctx,
+        &term_loan_pda(&term_pool, &user_pda(&borrower.pubkey())),
+    )
+    .await;
+    assert_eq!(loan.owed, 2_500_000);
+    let pool: TermPool = fetch(&mut ctx, &term_pool).await;
+    assert_eq!(pool.outstanding, 2_500_000);
+    assert_eq!(
+        token_balance(&mut ctx, &term_vault_pda(&term_pool)).await,
+        7_500_000
+    );
+    assert_eq!(
+        token_balance(&mut ctx, &treasury_pda(&sol)).await,
+        BANK_LIQUIDITY
+    );
+
+    let liquidator_usdc = anchor_spl::associated_token::get_associated_token_address(
+        &liquidator.pubkey(),
+        &market.usdc_mint,
+    );
+    let seized = token_balance(&mut ctx, &liquidator_usdc).await;
+    assert!((524_999..=525_000).contains(&seized), "seized {seized}");
+    let position: User = fetch(&mut ctx, &user_pda(&borrower.pubkey())).await;
+    assert_eq!(position.term_borrowed_sol, 2_500_000);
+    assert_eq!(position.deposited_usdc, 1_000_000 - seized);
+}
+
+#[tokio::test]
+async fn liquidate_term_loan_past_maturity_ignores_health() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let sol = market.sol_mint;
+    let lender = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let maturity = clock(&mut ctx).await.unix_timestamp + 60;
+    send(
+        &mut ctx,
+        &[
+            create_term_pool_ix(&payer, &sol, maturity, 0.0),
+            lend_term_ix(&lender.pubkey(), &sol, maturity, 10_000_000),
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_term_ix(
+                &borrower.pubkey(),
+                &sol,
+                &market.sol_price,
+                &market.usdc_mint,
+                &market.usdc_price,
+                maturity,
+                1_000_000,
+            ),
+        ],
+        &[&lender, &borrower],
+    )
+    .await
+    .unwrap();
+    let liquidator = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+    let liquidate = liquidate_term_loan_ix(
+        &liquidator.pubkey(),
+        &borrower.pubkey(),
+        &market.usdc_mint,
+        &sol,
+        &market.usdc_price,
+        &market.sol_price,
+        Some(maturity),
+    );
+
+    let result = send(&mut ctx, &[liquidate.clone()], &[&liquidator]).await;
+    assert_error(result, ErrorCode::NotUnderCollateralized);
+
+    // the position is as healthy as before, but the loan is overdue
+    advance_clock(&mut ctx, 60).await;
+    refresh_prices(&mut ctx, &market, 1, 100).await;
+    send(&mut ctx, &[liquidate], &[&liquidator]).await.unwrap();
+
+    let term_pool = term_pool_pda(&bank_pda(&sol), maturity);
+    let loan: TermLoan = fetch(
+        &mut ctx,
+        &term_loan_pda(&term_pool, &user_pda(&borrower.pubkey())),
+    )
+    .await;
+    assert_eq!(loan.owed, 500_000);
+}
+
+#[tokio::test]
+async fn liquidate_worthless_collateral_fails_with_math_overflow() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let borrower = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&borrower.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_ix(&borrower.pubkey(), &market.sol_mint, &market, 5_000_000),
+        ],
+        &[&borrower],
+    )
+    .await
+    .unwrap();
+    let liquidator = setup_user(&mut ctx, &market, 0, 10_000_000).await;
+
+    // collateral priced at zero cannot be converted into a seize amount
+    refresh_prices(&mut ctx, &market, 0, 100).await;
+    let result = send(
+        &mut ctx,
+        &[liquidate_ix(
+            &liquidator.pubkey(),
+            &borrower.pubkey(),
+            &market.usdc_mint,
+            &market.sol_mint,
+            &market.usdc_price,
+            &market.sol_price,
+        )],
+        &[&liquidator],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::MathOverflow);
+}
+
+#[tokio::test]
+async fn migrate_moves_legacy_accounts_to_namespaced_addresses() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let existing = setup_user(&mut ctx, &market, 0, 0).await;
+    let wallet = funded_keypair(&mut ctx, 1_000_000_000).await;
+
+    // stand-ins for a bank and a position written by the baseline program: unprefixed seeds
+    // and the original layouts, which end at the interest rate and at last_updated_borrowed
+    const BASELINE_BANK_LEN: usize = 8 + 32 * 2 + 8 * 8 + 8 + 8;
+    const BASELINE_USER_LEN: usize = 8 + 32 + 8 * 8 + 32 + 8 + 8;
+    let mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let mut legacy_bank: Bank = fetch(&mut ctx, &bank_pda(&market.usdc_mint)).await;
+    legacy_bank.mint_address = mint;
+    legacy_bank.total_deposits = 42;
+    let mut legacy_user: User = fetch(&mut ctx, &user_pda(&existing.pubkey())).await;
+    legacy_user.owner = wallet.pubkey();
+    legacy_user.deposited_usdc = 1_000;
+    let mut bank_data = Vec::new();
+    legacy_bank.try_serialize(&mut bank_data).unwrap();
+    bank_data.truncate(BASELINE_BANK_LEN);
+    set_program_account_data(&mut ctx, &legacy_bank_address(&mint), bank_data).await;
+    let mut user_data = Vec::new();
+    legacy_user.try_serialize(&mut user_data).unwrap();
+    user_data.truncate(BASELINE_USER_LEN);
+    set_program_account_data(&mut ctx, &legacy_user_address(&wallet.pubkey()), user_data).await;
+
+    send(&mut ctx, &[migrate_bank_ix(&payer, &mint)], &[])
+        .await
+        .unwrap();
+    // anyone may move the position, and it comes out knowing both of its banks
+    let result = send(
+        &mut ctx,
+        &[migrate_user_ix(&payer, &wallet.pubkey(), &market.usdc_mint)],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::InvalidConfig);
+    send(
+        &mut ctx,
+        &[migrate_user_ix(&payer, &wallet.pubkey(), &market.sol_mint)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&mint)).await;
+    assert_eq!(
+        (bank.authority, bank.total_deposits, bank.version),
+        (payer, 42, Bank::VERSION)
+    );
+    assert_eq!((bank.liquidation_threshold, bank.protocol_fees), (80, 0));
+    let position: User = fetch(&mut ctx, &user_pda(&wallet.pubkey())).await;
+    assert_eq!(
+        (
+            position.owner,
+            position.deposited_usdc,
+            position.usdc_address,
+            position.sol_address
+        ),
+        (wallet.pubkey(), 1_000, market.usdc_mint, market.sol_mint)
+    );
+    assert_eq!((position.index, position.version), (0, User::VERSION));
+    for legacy in [
+        legacy_bank_address(&mint),
+        legacy_user_address(&wallet.pubkey()),
+    ] {
+        assert!(ctx
+            .banks_client
+            .get_account(legacy)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn upgrade_account_grows_unversioned_layouts() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let wallet = setup_user(&mut ctx, &market, 0, 0).await;
+
+    // drop the version byte and everything after it to recreate the layout from before versioning
+    let mut old_accounts = Vec::new();
+    for (address, trailing) in [
+        (bank_pda(&market.usdc_mint), 1 + 219),
+        (user_pda(&wallet.pubkey()), 1 + 136),
+    ] {
+        let account = ctx
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut data = account.data;
+        data.truncate(data.len() - trailing);
+        set_program_account_data(&mut ctx, &address, data).await;
+        old_accounts.push(address);
+    }
+
+    let ixs: Vec<_> = old_accounts
+        .iter()
+        .map(|address| upgrade_account_ix(&payer, address))
+        .collect();
+    send(&mut ctx, &ixs, &[]).await.unwrap();
+
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.usdc_mint)).await;
+    assert_eq!(
+        (bank.version, bank.liquidation_threshold),
+        (Bank::VERSION, 80)
+    );
+    let position: User = fetch(&mut ctx, &user_pda(&wallet.pubkey())).await;
+    assert_eq!(
+        (position.version, position.owner, position.sol_address),
+        (User::VERSION, wallet.pubkey(), Pubkey::default())
+    );
+
+    // the SOL mint was never recorded, and only the owner may fill it in
+    let record_sol = |owner: &Pubkey| {
+        upgrade_user_ix(owner, &user_pda(&wallet.pubkey()), Some(&market.sol_mint))
+    };
+    let result = send(&mut ctx, &[record_sol(&payer)], &[]).await;
+    assert_error(result, ErrorCode::Unauthorized);
+    send(&mut ctx, &[record_sol(&wallet.pubkey())], &[&wallet])
+        .await
+        .unwrap();
+    let position: User = fetch(&mut ctx, &user_pda(&wallet.pubkey())).await;
+    assert_eq!(position.sol_address, market.sol_mint);
+
+    // already current, so a second pass changes nothing
+    send(
+        &mut ctx,
+        &[upgrade_account_ix(&payer, &bank_pda(&market.usdc_mint))],
+        &[],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn upgrade_account_rejects_accounts_that_are_not_banks_or_positions() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let owner = setup_user(&mut ctx, &market, 0, 0).await;
+    let delegate = Keypair::new();
+    send(
+        &mut ctx,
+        &[approve_delegation_ix(
+            &owner.pubkey(),
+            &delegate.pubkey(),
+            &market.sol_mint,
+            1_000,
+        )],
+        &[&owner],
+    )
+    .await
+    .unwrap();
+
+    let delegation = delegation_pda(&owner.pubkey(), &delegate.pubkey(), &market.sol_mint, 0);
+    let result = send(
+        &mut ctx,
+        &[upgrade_account_ix(&owner.pubkey(), &delegation)],
+        &[&owner],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::UnknownAccount);
+}
+
+#[tokio::test]
+async fn close_user_requires_an_empty_position() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let result = send(&mut ctx, &[close_user_ix(&user.pubkey())], &[&user]).await;
+    assert_error(result, ErrorCode::PositionNotEmpty);
+
+    send(
+        &mut ctx,
+        &[
+            withdraw_ix(&user.pubkey(), &market.usdc_mint, &market, 1_000_000),
+            close_user_ix(&user.pubkey()),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let position = ctx
+        .banks_client
+        .get_account(user_pda(&user.pubkey()))
+        .await
+        .unwrap();
+    assert!(position.is_none());
+}
+
+#[tokio::test]
+async fn close_bank_sweeps_treasury_once_empty() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let destination = fund_ata(&mut ctx, &market.sol_mint, &payer, 0).await;
+    let user = setup_user(&mut ctx, &market, 0, 1_000).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.sol_mint, 1_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let close = close_bank_ix(&payer, &market.sol_mint, &destination);
+    let result = send(&mut ctx, &[close.clone()], &[]).await;
+    assert_error(result, ErrorCode::BankNotEmpty);
+
+    send(
+        &mut ctx,
+        &[
+            withdraw_ix(&user.pubkey(), &market.sol_mint, &market, 1_000),
+            close,
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(token_balance(&mut ctx, &destination).await, BANK_LIQUIDITY);
+    for closed in [bank_pda(&market.sol_mint), treasury_pda(&market.sol_mint)] {
+        assert!(ctx
+            .banks_client
+            .get_account(closed)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn close_bank_waits_for_protocol_fees_and_writes_off_referral_fees() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let sol = market.sol_mint;
+    let destination = fund_ata(&mut ctx, &sol, &payer, 0).await;
+    let referrer = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let user = funded_keypair(&mut ctx, 1_000_000_000).await;
+    fund_ata(&mut ctx, &market.usdc_mint, &user.pubkey(), 1_000_000).await;
+    fund_ata(&mut ctx, &sol, &user.pubkey(), 1_000).await;
+    // the borrow is repaid in full, but its fee is still split between protocol and referrer
+    send(
+        &mut ctx,
+        &[
+            update_bank_config_ix(
+                &payer,
+                &sol,
+                BankConfigParams {
+                    borrow_fee_bps: Some(100),
+                    referral_fee_percent: Some(50),
+                    ..BankConfigParams::default()
+                },
+            ),
+            init_referred_user_ix(
+                &user.pubkey(),
+                &market.usdc_mint,
+                &sol,
+                0,
+                Some(referrer.pubkey()),
+            ),
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+            borrow_with_fees_ix(
+                &user.pubkey(),
+                &user.pubkey(),
+                &sol,
+                &market,
+                10_000,
+                None,
+                Some(referrer.pubkey()),
+            ),
+            repay_ix(&user.pubkey(), &sol, AMOUNT_ALL),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&sol)).await;
+    assert_eq!((bank.protocol_fees, bank.referral_fees_owed), (50, 50));
+
+    let close = close_bank_ix(&payer, &sol, &destination);
+    let result = send(&mut ctx, &[close.clone()], &[]).await;
+    assert_error(result, ErrorCode::FeesUnclaimed);
+    // the referrer never claimed, which no longer keeps the bank listed
+    send(
+        &mut ctx,
+        &[collect_fees_ix(&payer, &sol, &destination), close],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert!(ctx
+        .banks_client
+        .get_account(bank_pda(&sol))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn close_bank_sweeps_unclaimed_rewards_and_closes_the_vault() {
+    let mut ctx = start().await;
+    let payer = ctx.payer.pubkey();
+    let mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let reward_mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let destination = fund_ata(&mut ctx, &mint, &payer, 0).await;
+    let reward_destination = fund_ata(&mut ctx, &reward_mint, &payer, 0).await;
+    let end_time = clock(&mut ctx).await.unix_timestamp + 1_000;
+    send(
+        &mut ctx,
+        &[
+            init_bank_ix(&payer, &mint, 80, 75, false),
+            configure_rewards_ix(
+                &payer,
+                &mint,
+                &reward_mint,
+                RewardSide::Deposit,
+                1_000,
+                end_time,
+            ),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+    let reward_vault = reward_vault_pda(&reward_pool_pda(&bank_pda(&mint), RewardSide::Deposit));
+    mint_to(&mut ctx, &reward_mint, &reward_vault, 1_000).await;
+
+    // rewards nobody has claimed go out with the bank instead of keeping it listed
+    let result = send(&mut ctx, &[close_bank_ix(&payer, &mint, &destination)], &[]).await;
+    assert_error(result, ErrorCode::RewardAccountsMissing);
+    send(
+        &mut ctx,
+        &[close_rewarded_bank_ix(
+            &payer,
+            &mint,
+            &destination,
+            Some((RewardSide::Deposit, reward_mint, reward_destination)),
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(token_balance(&mut ctx, &reward_destination).await, 1_000);
+    for closed in [bank_pda(&mint), reward_vault] {
+        assert!(ctx
+            .banks_client
+            .get_account(closed)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn update_bank_config_changes_params_for_authority_only() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let params = BankConfigParams {
+        max_ltv: Some(60),
+        liquidation_bonus: Some(8),
+        ..BankConfigParams::default()
+    };
+
+    let stranger = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let result = send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &stranger.pubkey(),
+            &market.usdc_mint,
+            params.clone(),
+        )],
+        &[&stranger],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+
+    send(
+        &mut ctx,
+        &[update_bank_config_ix(&payer, &market.usdc_mint, params)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.usdc_mint)).await;
+    assert_eq!(bank.max_ltv, 60);
+    assert_eq!(bank.liquidation_bonus, 8);
+    assert_eq!(bank.liquidation_threshold, 80);
+}
+
+#[tokio::test]
+async fn update_bank_config_rejects_ltv_above_liquidation_threshold() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+
+    let result = send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &market.usdc_mint,
+            BankConfigParams {
+                max_ltv: Some(90),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await;
+
+    assert_error(result, ErrorCode::InvalidConfig);
+}
+
+#[tokio::test]
+async fn paused_bank_rejects_deposits_until_unpaused() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+
+    send(
+        &mut ctx,
+        &[set_paused_ix(&payer, &market.usdc_mint, true)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000)],
+        &[&user],
+    )
+    .await;
+    assert_error(result, ErrorCode::BankPaused);
+
+    send(
+        &mut ctx,
+        &[set_paused_ix(&payer, &market.usdc_mint, false)],
+        &[],
+    )
+    .await
+    .unwrap();
+    // a different amount, so the retry is not rejected as an already-processed transaction
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &market.usdc_mint, 500_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn outflow_limit_caps_withdrawals_until_guardian_raises_it() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let guardian = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            update_bank_config_ix(
+                &payer,
+                &market.usdc_mint,
+                BankConfigParams {
+                    guardian: Some(guardian.pubkey()),
+                    outflow_window: Some(60 * 60),
+                    outflow_limit: Some(100_000),
+                    ..BankConfigParams::default()
+                },
+            ),
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    let withdraw = |amount| withdraw_ix(&user.pubkey(), &market.usdc_mint, &market, amount);
+    send(&mut ctx, &[withdraw(80_000)], &[&user]).await.unwrap();
+    let result = send(&mut ctx, &[withdraw(30_000)], &[&user]).await;
+    assert_error(result, ErrorCode::OutflowLimitExceeded);
+
+    let result = send(
+        &mut ctx,
+        &[raise_outflow_limit_ix(
+            &user.pubkey(),
+            &market.usdc_mint,
+            50_000,
+            60,
+        )],
+        &[&user],
+    )
+    .await;
+    assert_error(result, ErrorCode::NotGuardian);
+    send(
+        &mut ctx,
+        &[raise_outflow_limit_ix(
+            &guardian.pubkey(),
+            &market.usdc_mint,
+            50_000,
+            60,
+        )],
+        &[&guardian],
+    )
+    .await
+    .unwrap();
+    send(&mut ctx, &[withdraw(30_001)], &[&user]).await.unwrap();
+
+    // once the window has passed the tracked outflow has drained away
+    advance_clock(&mut ctx, 60 * 60).await;
+    send(&mut ctx, &[withdraw(90_000)], &[&user]).await.unwrap();
+}
+
+#[tokio::test]
+async fn timelocked_config_changes_wait_for_the_delay() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let keeper = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let pending = pending_config_pda(&bank_pda(&market.usdc_mint));
+    let rate_change = || BankConfigParams {
+        interest_rate: Some(0.1),
+        ..BankConfigParams::default()
+    };
+
+    // with no delay yet, turning the timelock on applies immediately
+    send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &market.usdc_mint,
+            BankConfigParams {
+                config_delay: Some(24 * 60 * 60),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &market.usdc_mint,
+            rate_change(),
+        )],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::TimelockRequired);
+    // lowering max_ltv only makes the bank safer, so it skips the queue
+    send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &market.usdc_mint,
+            BankConfigParams {
+                max_ltv: Some(60),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    send(
+        &mut ctx,
+        &[queue_bank_config_ix(
+            &payer,
+            &market.usdc_mint,
+            rate_change(),
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[execute_bank_config_ix(&market.usdc_mint, &payer)],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::TimelockNotElapsed);
+
+    advance_clock(&mut ctx, 24 * 60 * 60).await;
+    // execution needs no signer beyond the fee payer once the delay has passed
+    send(
+        &mut ctx,
+        &[execute_bank_config_ix(&market.usdc_mint, &payer)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.usdc_mint)).await;
+    assert_eq!((bank.instrest_rate, bank.max_ltv), (0.1, 60));
+    assert!(ctx
+        .banks_client
+        .get_account(pending)
+        .await
+        .unwrap()
+        .is_none());
+
+    send(
+        &mut ctx,
+        &[queue_bank_config_ix(
+            &payer,
+            &market.usdc_mint,
+            BankConfigParams {
+                interest_rate: Some(0.2),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[cancel_bank_config_ix(
+            &keeper.pubkey(),
+            &market.usdc_mint,
+            &payer,
+        )],
+        &[&keeper],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+    send(
+        &mut ctx,
+        &[cancel_bank_config_ix(&payer, &market.usdc_mint, &payer)],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert!(ctx
+        .banks_client
+        .get_account(pending)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn roles_limit_each_admin_to_its_own_instructions() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let risk_admin = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let guardian = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let fee_admin = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let listing_admin = funded_keypair(&mut ctx, 1_000_000_000).await;
+    send(
+        &mut ctx,
+        &[set_roles_ix(
+            &payer,
+            &risk_admin.pubkey(),
+            &guardian.pubkey(),
+            &fee_admin.pubkey(),
+            &listing_admin.pubkey(),
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let mint = market.usdc_mint;
+    let as_role = with_roles;
+
+    // the guardian may pause and reduce risk, but nothing else
+    send(
+        &mut ctx,
+        &[
+            as_role(set_paused_ix(&guardian.pubkey(), &mint, true)),
+            as_role(update_bank_config_ix(
+                &guardian.pubkey(),
+                &mint,
+                BankConfigParams {
+                    max_ltv: Some(70),
+                    ..BankConfigParams::default()
+                },
+            )),
+        ],
+        &[&guardian],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[as_role(set_paused_ix(&guardian.pubkey(), &mint, false))],
+        &[&guardian],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+    let result = send(
+        &mut ctx,
+        &[as_role(update_bank_config_ix(
+            &guardian.pubkey(),
+            &mint,
+            BankConfigParams {
+                interest_rate: Some(0.1),
+                ..BankConfigParams::default()
+            },
+        ))],
+        &[&guardian],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+
+    send(
+        &mut ctx,
+        &[
+            as_role(set_paused_ix(&risk_admin.pubkey(), &mint, false)),
+            as_role(update_bank_config_ix(
+                &risk_admin.pubkey(),
+                &mint,
+                BankConfigParams {
+                    interest_rate: Some(0.1),
+                    ..BankConfigParams::default()
+                },
+            )),
+        ],
+        &[&risk_admin],
+    )
+    .await
+    .unwrap();
+
+    let destination = fund_ata(&mut ctx, &mint, &fee_admin.pubkey(), 0).await;
+    let result = send(
+        &mut ctx,
+        &[as_role(collect_fees_ix(
+            &risk_admin.pubkey(),
+            &mint,
+            &destination,
+        ))],
+        &[&risk_admin],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+    send(
+        &mut ctx,
+        &[as_role(collect_fees_ix(
+            &fee_admin.pubkey(),
+            &mint,
+            &destination,
+        ))],
+        &[&fee_admin],
+    )
+    .await
+    .unwrap();
+
+    // only the listing admin may list for the market authority
+    let new_mint = create_mint(&mut ctx, SOL_DECIMALS).await;
+    let result = send(
+        &mut ctx,
+        &[list_bank_ix(&risk_admin.pubkey(), &new_mint, 80, 75, false)],
+        &[&risk_admin],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+
+    // a bank listed through the listing role belongs to the authority
+    send(
+        &mut ctx,
+        &[list_bank_ix(
+            &listing_admin.pubkey(),
+            &new_mint,
+            80,
+            75,
+            false,
+        )],
+        &[&listing_admin],
+    )
+    .await
+    .unwrap();
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&new_mint)).await;
+    assert_eq!(bank.authority, payer);
+
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&mint)).await;
+    assert_eq!(
+        (bank.paused, bank.max_ltv, bank.instrest_rate),
+        (false, 70, 0.1)
+    );
+}
+
+#[tokio::test]
+async fn only_the_market_authority_sets_roles_and_lists_banks() {
+    let mut ctx = start().await;
+    let stranger = funded_keypair(&mut ctx, 1_000_000_000).await;
+    let mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+
+    // the payer claimed the market's roles account, so a stranger can neither take it over
+    // nor list a bank by naming themselves listing admin
+    let result = send(
+        &mut ctx,
+        &[set_roles_ix(
+            &stranger.pubkey(),
+            &stranger.pubkey(),
+            &stranger.pubkey(),
+            &stranger.pubkey(),
+            &stranger.pubkey(),
+        )],
+        &[&stranger],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+    let result = send(
+        &mut ctx,
+        &[init_bank_ix(&stranger.pubkey(), &mint, 80, 75, false)],
+        &[&stranger],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+
+    let roles: Roles = fetch(&mut ctx, &roles_pda()).await;
+    assert_eq!(roles.authority, ctx.payer.pubkey());
+    assert_eq!(roles.listing_admin, Pubkey::default());
+}
+
+#[tokio::test]
+async fn deposit_rewards_split_by_share_until_the_end_time() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let usdc = market.usdc_mint;
+    let reward_mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let alice = setup_user(&mut ctx, &market, 3_000_000, 0).await;
+    let bob = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    let end_time = clock(&mut ctx).await.unix_timestamp + 1_000;
+    send(
+        &mut ctx,
+        &[configure_rewards_ix(
+            &payer,
+            &usdc,
+            &reward_mint,
+            RewardSide::Deposit,
+            1_000,
+            end_time,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let reward_pool = reward_pool_pda(&bank_pda(&usdc), RewardSide::Deposit);
+    mint_to(
+        &mut ctx,
+        &reward_mint,
+        &reward_vault_pda(&reward_pool),
+        10_000_000,
+    )
+    .await;
+
+    // skipping the reward accounts would let a deposit dodge settlement
+    let result = send(
+        &mut ctx,
+        &[deposit_ix(&alice.pubkey(), &usdc, 3_000_000)],
+        &[&alice],
+    )
+    .await;
+    assert_error(result, ErrorCode::RewardAccountsMissing);
+    let deposit = |user: &Keypair, amount| {
+        with_rewards(
+            deposit_ix(&user.pubkey(), &usdc, amount),
+            &usdc,
+            RewardSide::Deposit,
+            &[user_pda(&user.pubkey())],
+        )
+    };
+    send(
+        &mut ctx,
+        &[deposit(&alice, 3_000_000), deposit(&bob, 1_000_000)],
+        &[&alice, &bob],
+    )
+    .await
+    .unwrap();
+
+    advance_clock(&mut ctx, 100).await;
+    let mut destinations = Vec::new();
+    for user in [&alice, &bob] {
+        let destination = fund_ata(&mut ctx, &reward_mint, &user.pubkey(), 0).await;
+        let claim = claim_rewards_ix(
+            &user.pubkey(),
+            &usdc,
+            &reward_mint,
+            RewardSide::Deposit,
+            &destination,
+        );
+        send(&mut ctx, &[claim], &[user]).await.unwrap();
+        destinations.push(destination);
+    }
+    assert_eq!(token_balance(&mut ctx, &destinations[0]).await, 75_000);
+    assert_eq!(token_balance(&mut ctx, &destinations[1]).await, 25_000);
+
+    // emissions stop at the end time however long claims wait
+    advance_clock(&mut ctx, 2_000).await;
+    let claim = claim_rewards_ix(
+        &alice.pubkey(),
+        &usdc,
+        &reward_mint,
+        RewardSide::Deposit,
+        &destinations[0],
+    );
+    send(&mut ctx, &[claim], &[&alice]).await.unwrap();
+    assert_eq!(token_balance(&mut ctx, &destinations[0]).await, 750_000);
+}
+
+#[tokio::test]
+async fn reward_positions_only_earn_from_when_they_open() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let usdc = market.usdc_mint;
+    let reward_mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let other_mint = create_mint(&mut ctx, USDC_DECIMALS).await;
+    let user = setup_user(&mut ctx, &market, 1_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[deposit_ix(&user.pubkey(), &usdc, 1_000_000)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    let end_time = clock(&mut ctx).await.unix_timestamp + 1_000;
+    send(
+        &mut ctx,
+        &[
+            init_bank_ix(&payer, &other_mint, 80, 75, false),
+            configure_rewards_ix(
+                &payer,
+                &usdc,
+                &reward_mint,
+                RewardSide::Deposit,
+                1_000,
+                end_time,
+            ),
+            configure_rewards_ix(
+                &payer,
+                &other_mint,
+                &reward_mint,
+                RewardSide::Deposit,
+                1_000,
+                end_time,
+            ),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+    let reward_pool = reward_pool_pda(&bank_pda(&usdc), RewardSide::Deposit);
+    mint_to(
+        &mut ctx,
+        &reward_mint,
+        &reward_vault_pda(&reward_pool),
+        10_000_000,
+    )
+    .await;
+    let destination = fund_ata(&mut ctx, &reward_mint, &user.pubkey(), 0).await;
+    // a second destination keeps the later claim from repeating the first transaction
+    let later_destination = fund_ata(&mut ctx, &reward_mint, &payer, 0).await;
+    let claim = |mint, destination| {
+        claim_rewards_ix(
+            &user.pubkey(),
+            mint,
+            &reward_mint,
+            RewardSide::Deposit,
+            destination,
+        )
+    };
+
+    let result = send(&mut ctx, &[claim(&other_mint, &destination)], &[&user]).await;
+    assert_error(result, ErrorCode::UnknownBank);
+
+    // the shares predate the position, so what the pool emitted before it opened is not theirs
+    advance_clock(&mut ctx, 100).await;
+    send(&mut ctx, &[claim(&usdc, &destination)], &[&user])
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut ctx, &destination).await, 0);
+
+    advance_clock(&mut ctx, 100).await;
+    send(&mut ctx, &[claim(&usdc, &later_destination)], &[&user])
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut ctx, &later_destination).await, 100_000);
+}
+
+#[tokio::test]
+async fn transfer_authority_hands_over_admin_rights() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    let new_authority = funded_keypair(&mut ctx, 1_000_000_000).await;
+
+    send(
+        &mut ctx,
+        &[transfer_authority_ix(
+            &payer,
+            &market.usdc_mint,
+            &new_authority.pubkey(),
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let result = send(
+        &mut ctx,
+        &[set_paused_ix(&payer, &market.usdc_mint, true)],
+        &[],
+    )
+    .await;
+    assert_error(result, ErrorCode::Unauthorized);
+    send(
+        &mut ctx,
+        &[set_paused_ix(
+            &new_authority.pubkey(),
+            &market.usdc_mint,
+            true,
+        )],
+        &[&new_authority],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn collect_fees_pays_out_reserved_interest() {
+    let mut ctx = start().await;
+    let market = setup_market(&mut ctx).await;
+    let payer = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[update_bank_config_ix(
+            &payer,
+            &market.sol_mint,
+            BankConfigParams {
+                interest_rate: Some(1.0),
+                reserve_factor: Some(20),
+                ..BankConfigParams::default()
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let user = setup_user(&mut ctx, &market, 1_000_000_000, 0).await;
+    send(
+        &mut ctx,
+        &[
+            deposit_ix(&user.pubkey(), &market.usdc_mint, 1_000_000_000),
+            borrow_ix(&user.pubkey(), &market.sol_mint, &market, 1_000_000_000),
+        ],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    advance_clock(&mut ctx, 30 * 24 * 60 * 60).await;
+    let destination = fund_ata(&mut ctx, &market.sol_mint, &payer, 0).await;
+    send(
+        &mut ctx,
+        &[collect_fees_ix(&payer, &market.sol_mint, &destination)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let collected = token_balance(&mut ctx, &destination).await;
+    assert!(collected > 0);
+    let bank: Bank = fetch(&mut ctx, &bank_pda(&market.sol_mint)).await;
+    assert_eq!(bank.protocol_fees, 0);
+}
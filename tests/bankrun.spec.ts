@@ -62,6 +62,18 @@ describe("Lending Smart Contract Tests", async () => {
 
   context.setAccount(solUsdPriceFeedAccountAddress, feedAccountInfo);
 
+  const USDC_PRICE_FEED_ID =
+    "0xeaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a";
+
+  const usdcUsdPriceFeedAccountAddress =
+    pythSolanaReceiver.getPriceFeedAccountAddress(0, USDC_PRICE_FEED_ID);
+
+  const usdcFeedAccountInfo = await devnetConnection.getAccountInfo(
+    usdcUsdPriceFeedAccountAddress
+  );
+
+  context.setAccount(usdcUsdPriceFeedAccountAddress, usdcFeedAccountInfo);
+
   program = new Program<Lending>(IDL as Lending, provider);
   banksClient = context.banksClient;
   signer = provider.wallet.payer;
@@ -82,10 +94,38 @@ describe("Lending Smart Contract Tests", async () => {
   ],
   program.programId);
 
+  // sub-account 0 of the signer
+  const [userAccount] = PublicKey.findProgramAddressSync([
+    Buffer.from("user"),
+    signer.publicKey.toBuffer(),
+    Buffer.from([0])
+  ],
+  program.programId);
+
+  // banks are listed under the market's roles account, which the signer claims first
+  const [rolesAccount] = PublicKey.findProgramAddressSync([
+    Buffer.from("roles")
+  ],
+  program.programId);
+
+  it("Test Set Roles",async ()=>{
+    const setRolesTx = await program.methods.setRoles(
+      PublicKey.default,
+      PublicKey.default,
+      PublicKey.default,
+      PublicKey.default
+    ).accounts({
+      signer:signer.publicKey,
+    }).rpc({commitment:"confirmed"});
+
+    console.log("Set Roles",setRolesTx);
+  });
+
   it("Test Init And Fund Bank",async ()=>{
-    const initUsdcBankTx = await program.methods.initBank(new BN(1),new BN(1)).accounts({
+    const initUsdcBankTx = await program.methods.initBank(new BN(1),new BN(1),false).accounts({
       signer:signer.publicKey,
       mint:mintUSDC,
+      roles:rolesAccount,
       tokenProgram:TOKEN_PROGRAM_ID
     }).rpc({commitment:"confirmed"});
 
@@ -106,7 +146,7 @@ describe("Lending Smart Contract Tests", async () => {
   });
 
   it("Test Init User",async () => {
-    const initUserTx = await program.methods.initUser(mintUSDC).accounts({
+    const initUserTx = await program.methods.initUser(mintUSDC,mintSOL,0,null).accounts({
       signer:signer.publicKey
     }).rpc({commitment:"confirmed"});
 
@@ -116,10 +156,12 @@ describe("Lending Smart Contract Tests", async () => {
   it("Test Init and Fund Sol Bank",async ()=> {
     const initSolBankTx = await program.methods.initBank(
       new BN(1),
-      new BN(2)
+      new BN(1),
+      false
     ).accounts({
       signer:signer.publicKey,
       mint:mintSOL,
+      roles:rolesAccount,
       tokenProgram:TOKEN_PROGRAM_ID
     }).rpc({commitment:"confirmed"});
 
@@ -166,9 +208,13 @@ describe("Lending Smart Contract Tests", async () => {
   it("Test Deposit",async ()=> {
     const depositUsdcTx = await program.methods
     .deposit(new BN(100_000_000_000))
-    .accounts({
+    .accountsPartial({
       signer:signer.publicKey,
+      user:userAccount,
       mint:mintUSDC,
+      rewardPool:null,
+      rewardPosition:null,
+      wsolAccount:null,
       tokenProgram:TOKEN_PROGRAM_ID
     }).rpc({commitment:"confirmed"});
 
@@ -176,20 +222,35 @@ describe("Lending Smart Contract Tests", async () => {
   });
 
   it("Test Borrow",async () => {
-    const borrowSOL = await program.methods.borrow(new BN(1)).accounts({
+    const borrowSOL = await program.methods.borrow(new BN(1)).accountsPartial({
       signer:signer.publicKey,
+      owner:signer.publicKey,
+      user:userAccount,
+      delegation:null,
+      hostFeeReceiver:null,
+      referralFees:null,
+      rewardPool:null,
+      rewardPosition:null,
+      wsolAccount:null,
       borrowMint:mintSOL,
+      otherMint:mintUSDC,
       tokenProgram:TOKEN_PROGRAM_ID,
-      priceUpdate:solUsdPriceFeedAccountAddress
+      priceUpdate:solUsdPriceFeedAccountAddress,
+      otherPriceUpdate:usdcUsdPriceFeedAccountAddress
     }).rpc({commitment:"confirmed"});
 
     console.log("Borrow SOL",borrowSOL);
   });
 
   it("Test Repay",async () => {
-    const repaySol = await program.methods.repay(new BN(1)).accounts({
+    const repaySol = await program.methods.repay(new BN(1)).accountsPartial({
       signer:signer.publicKey,
+      owner:signer.publicKey,
+      user:userAccount,
       repayMint:mintSOL,
+      rewardPool:null,
+      rewardPosition:null,
+      wsolAccount:null,
       tokenProgram:TOKEN_PROGRAM_ID
     }).rpc({commitment:"confirmed"});
 
@@ -197,10 +258,17 @@ describe("Lending Smart Contract Tests", async () => {
   });
 
   it("Test Withdraw",async () => {
-    const withdrawUsdc = await program.methods.withdraw(new BN(100)).accounts({
+    const withdrawUsdc = await program.methods.withdraw(new BN(100)).accountsPartial({
       signer:signer.publicKey,
+      user:userAccount,
       mint:mintUSDC,
-      tokenProgram:TOKEN_PROGRAM_ID
+      otherMint:mintSOL,
+      rewardPool:null,
+      rewardPosition:null,
+      wsolAccount:null,
+      tokenProgram:TOKEN_PROGRAM_ID,
+      priceUpdate:usdcUsdPriceFeedAccountAddress,
+      otherPriceUpdate:solUsdPriceFeedAccountAddress
     }).rpc({commitment:"confirmed"});
 
     console.log("Withdraw Usdc",withdrawUsdc);